@@ -0,0 +1 @@
+#define EXTRA 7
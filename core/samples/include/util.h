@@ -0,0 +1,12 @@
+// インクルードガードで 2 回目以降のインクルードを無視する
+#ifndef UTIL_H
+#define UTIL_H
+
+#define UTIL_SCALE 3
+
+int square(int x)
+{
+    return x * x;
+}
+
+#endif
@@ -0,0 +1,469 @@
+use crate::interpreter::{Array, RuntimeErrorKind, VariableType};
+use std::io::{BufRead, Write};
+
+/// 組み込み関数の名前
+const BUILTIN_FUNCTIONS: [&str; 7] = ["printf", "putchar", "puts", "getchar", "read_int", "read_float", "scanf"];
+
+/// 組み込み関数かどうか
+pub fn is_builtin(name: &str) -> bool
+{
+  BUILTIN_FUNCTIONS.contains(&name)
+}
+
+/// 組み込み関数を呼び出す. 入力は input から読み, 出力は output に書き込む.
+/// 値を変数に書き戻す scanf は呼び出し側で扱う
+pub fn call(name: &str, arguments: &[VariableType], input: &mut dyn BufRead, output: &mut dyn Write)
+            -> Result<VariableType, RuntimeErrorKind>
+{
+  match name
+  {
+    "printf" => printf(arguments, output),
+    "putchar" =>
+      {
+        check_argument_count(name, arguments, 1)?;
+        let c = character(name, &arguments[0])?;
+        write(output, &[c])?;
+        Ok(VariableType::Int(c as i32))
+      }
+    "puts" =>
+      {
+        check_argument_count(name, arguments, 1)?;
+        let mut bytes = string(name, &arguments[0])?;
+        bytes.push(b'\n');
+        write(output, &bytes)?;
+        Ok(VariableType::Int(bytes.len() as i32))
+      }
+    "getchar" =>
+      {
+        check_argument_count(name, arguments, 0)?;
+        match peek(input)?
+        {
+          Some(c) =>
+            {
+              input.consume(1);
+              Ok(VariableType::Int(c as i32))
+            }
+          None => Ok(VariableType::Int(-1)),
+        }
+      }
+    "read_int" =>
+      {
+        check_argument_count(name, arguments, 0)?;
+        skip_whitespace(input)?;
+        if peek(input)?.is_none()
+        {
+          return Ok(VariableType::Int(-1));
+        }
+        read_int(input)?.map(VariableType::Int)
+          .ok_or_else(|| RuntimeErrorKind::InvalidInput("整数ではありません".to_string()))
+      }
+    "read_float" =>
+      {
+        check_argument_count(name, arguments, 0)?;
+        skip_whitespace(input)?;
+        if peek(input)?.is_none()
+        {
+          return Ok(VariableType::Float(-1.0));
+        }
+        read_float(input)?.map(VariableType::Float)
+          .ok_or_else(|| RuntimeErrorKind::InvalidInput("浮動小数点数ではありません".to_string()))
+      }
+    _ => Err(RuntimeErrorKind::UndefinedFunction(name.to_string())),
+  }
+}
+
+/// printf. %d, %f, %c, %s, %% に対応し, 書き込んだバイト数を返す
+fn printf(arguments: &[VariableType], output: &mut dyn Write) -> Result<VariableType, RuntimeErrorKind>
+{
+  let format = match arguments.first()
+  {
+    Some(format) => string("printf", format)?,
+    None => return Err(RuntimeErrorKind::ArgumentCountMismatch {
+      function: "printf".to_string(),
+      expected: 1,
+      found: 0,
+    }),
+  };
+
+  let mut bytes = Vec::new();
+  let mut values = arguments[1..].iter();
+  let mut conversions = format.iter();
+  while let Some(&c) = conversions.next()
+  {
+    if c != b'%'
+    {
+      bytes.push(c);
+      continue;
+    }
+
+    let specifier = match conversions.next()
+    {
+      Some(&specifier) => specifier,
+      None => return Err(RuntimeErrorKind::Unsupported("printf の書式が % で終わっています".to_string())),
+    };
+    if specifier == b'%'
+    {
+      bytes.push(b'%');
+      continue;
+    }
+
+    // 書式の数だけ引数が必要
+    let value = match values.next()
+    {
+      Some(value) => value,
+      None => return Err(RuntimeErrorKind::ArgumentCountMismatch {
+        function: "printf".to_string(),
+        expected: conversion_count(&format) + 1,
+        found: arguments.len(),
+      }),
+    };
+    match (specifier, value)
+    {
+      (b'd', VariableType::Int(value)) => bytes.extend(value.to_string().bytes()),
+      (b'd', VariableType::Char(value)) => bytes.extend(value.to_string().bytes()),
+      (b'f', VariableType::Float(value)) => bytes.extend(format!("{:.6}", value).bytes()),
+      (b'c', value) => bytes.push(character("printf", value)?),
+      (b's', value) => bytes.extend(string("printf", value)?),
+      (b'd', value) | (b'f', value) =>
+        {
+          return Err(RuntimeErrorKind::TypeMismatch(
+            format!("printf の %{} に {} は渡せません", specifier as char, value)));
+        }
+      _ =>
+        {
+          return Err(RuntimeErrorKind::Unsupported(
+            format!("printf の書式 %{} は未対応です", specifier.escape_ascii())));
+        }
+    }
+  }
+
+  // 書式より引数が多い
+  if values.next().is_some()
+  {
+    return Err(RuntimeErrorKind::ArgumentCountMismatch {
+      function: "printf".to_string(),
+      expected: conversion_count(&format) + 1,
+      found: arguments.len(),
+    });
+  }
+
+  write(output, &bytes)?;
+  Ok(VariableType::Int(bytes.len() as i32))
+}
+
+/// scanf. arguments は書式と代入先の現在の値で, %d, %f, %% に対応する.
+/// 読み取った値を返し, 最初の変換の前に入力が終わった場合は None を返す
+pub fn scanf(arguments: &[VariableType], input: &mut dyn BufRead)
+             -> Result<Option<Vec<VariableType>>, RuntimeErrorKind>
+{
+  let format = match arguments.first()
+  {
+    Some(format) => string("scanf", format)?,
+    None => return Err(RuntimeErrorKind::ArgumentCountMismatch {
+      function: "scanf".to_string(),
+      expected: 1,
+      found: 0,
+    }),
+  };
+  if conversion_count(&format) + 1 != arguments.len()
+  {
+    return Err(RuntimeErrorKind::ArgumentCountMismatch {
+      function: "scanf".to_string(),
+      expected: conversion_count(&format) + 1,
+      found: arguments.len(),
+    });
+  }
+
+  let mut values = Vec::new();
+  let mut targets = arguments[1..].iter();
+  let mut conversions = format.iter();
+  while let Some(&c) = conversions.next()
+  {
+    // 書式の空白は 0 個以上の空白に一致する
+    if c.is_ascii_whitespace()
+    {
+      skip_whitespace(input)?;
+      continue;
+    }
+
+    let specifier = match c
+    {
+      b'%' => match conversions.next()
+      {
+        Some(&specifier) => specifier,
+        None => return Err(RuntimeErrorKind::Unsupported("scanf の書式が % で終わっています".to_string())),
+      },
+      c => c,
+    };
+    if c != b'%' || specifier == b'%'
+    {
+      // 書式の文字と一致しなければ読み取りをやめる
+      match peek(input)?
+      {
+        Some(next) if next == specifier => input.consume(1),
+        Some(_) => break,
+        None if values.is_empty() => return Ok(None),
+        None => break,
+      }
+      continue;
+    }
+
+    skip_whitespace(input)?;
+    if peek(input)?.is_none()
+    {
+      if values.is_empty()
+      {
+        return Ok(None);
+      }
+      break;
+    }
+
+    let value = match (specifier, targets.next())
+    {
+      (b'd', Some(VariableType::Int(_))) | (b'd', Some(VariableType::Char(_))) =>
+        read_int(input)?.map(VariableType::Int),
+      (b'f', Some(VariableType::Float(_))) => read_float(input)?.map(VariableType::Float),
+      (b'd', Some(value)) | (b'f', Some(value)) =>
+        {
+          return Err(RuntimeErrorKind::TypeMismatch(
+            format!("scanf の %{} で {} には代入できません", specifier as char, value)));
+        }
+      _ =>
+        {
+          return Err(RuntimeErrorKind::Unsupported(
+            format!("scanf の書式 %{} は未対応です", specifier.escape_ascii())));
+        }
+    };
+    match value
+    {
+      Some(value) => values.push(value),
+      None => break,
+    }
+  }
+
+  Ok(Some(values))
+}
+
+/// 次の 1 バイトを読まずに返す
+fn peek(input: &mut dyn BufRead) -> Result<Option<u8>, RuntimeErrorKind>
+{
+  let buffer = input.fill_buf().map_err(|error| RuntimeErrorKind::Io(error.to_string()))?;
+  Ok(buffer.first().copied())
+}
+
+fn skip_whitespace(input: &mut dyn BufRead) -> Result<(), RuntimeErrorKind>
+{
+  while peek(input)?.is_some_and(|c| c.is_ascii_whitespace())
+  {
+    input.consume(1);
+  }
+  Ok(())
+}
+
+/// condition を満たすバイトを読み進めて text に追加し, 読んだ数を返す
+fn read_while(input: &mut dyn BufRead, text: &mut String, condition: impl Fn(u8) -> bool)
+              -> Result<usize, RuntimeErrorKind>
+{
+  let mut count = 0;
+  while let Some(c) = peek(input)?.filter(|&c| condition(c))
+  {
+    input.consume(1);
+    text.push(c as char);
+    count += 1;
+  }
+  Ok(count)
+}
+
+/// 符号付きの整数を読む. 数字が無ければ None を返す
+fn read_int(input: &mut dyn BufRead) -> Result<Option<i32>, RuntimeErrorKind>
+{
+  let mut text = String::new();
+  read_while(input, &mut text, |c| c == b'+' || c == b'-')?;
+  if text.len() > 1 || read_while(input, &mut text, |c| c.is_ascii_digit())? == 0
+  {
+    return Ok(None);
+  }
+  text.parse().map(Some)
+    .map_err(|_| RuntimeErrorKind::InvalidInput(format!("int の範囲外です : {}", text)))
+}
+
+/// 符号付きの小数を読む. 数字が無ければ None を返す
+fn read_float(input: &mut dyn BufRead) -> Result<Option<f64>, RuntimeErrorKind>
+{
+  let mut text = String::new();
+  read_while(input, &mut text, |c| c == b'+' || c == b'-')?;
+  if text.len() > 1
+  {
+    return Ok(None);
+  }
+  let mut digits = read_while(input, &mut text, |c| c.is_ascii_digit())?;
+  if read_while(input, &mut text, |c| c == b'.')? > 1
+  {
+    return Ok(None);
+  }
+  digits += read_while(input, &mut text, |c| c.is_ascii_digit())?;
+  if digits == 0
+  {
+    return Ok(None);
+  }
+  Ok(text.parse().ok())
+}
+
+/// %% を除いた書式の数
+fn conversion_count(format: &[u8]) -> usize
+{
+  let mut count = 0;
+  let mut conversions = format.iter();
+  while let Some(&c) = conversions.next()
+  {
+    if c == b'%' && conversions.next().is_some_and(|&specifier| specifier != b'%')
+    {
+      count += 1;
+    }
+  }
+  count
+}
+
+fn check_argument_count(name: &str, arguments: &[VariableType], expected: usize)
+                        -> Result<(), RuntimeErrorKind>
+{
+  if arguments.len() != expected
+  {
+    return Err(RuntimeErrorKind::ArgumentCountMismatch {
+      function: name.to_string(),
+      expected,
+      found: arguments.len(),
+    });
+  }
+  Ok(())
+}
+
+/// int, char の値を 1 バイトの文字にする
+fn character(name: &str, value: &VariableType) -> Result<u8, RuntimeErrorKind>
+{
+  match value
+  {
+    VariableType::Int(value) => Ok(*value as u8),
+    VariableType::Char(value) => Ok(*value as u8),
+    value => Err(RuntimeErrorKind::TypeMismatch(format!("{} に文字ではない値 {} が渡されました", name, value))),
+  }
+}
+
+/// char の配列を '\0' の手前までのバイト列にする
+fn string(name: &str, value: &VariableType) -> Result<Vec<u8>, RuntimeErrorKind>
+{
+  let array = match value
+  {
+    VariableType::Array(array) => array,
+    value => return Err(RuntimeErrorKind::TypeMismatch(format!("{} に文字列ではない値 {} が渡されました", name, value))),
+  };
+
+  let array: &Array = &array.borrow();
+  let mut bytes = Vec::new();
+  for value in array.values()
+  {
+    match value
+    {
+      VariableType::Char(0) => break,
+      VariableType::Char(c) => bytes.push(*c as u8),
+      _ => return Err(RuntimeErrorKind::TypeMismatch(
+        format!("{} に char ではない配列 {} が渡されました", name, array.name()))),
+    }
+  }
+  Ok(bytes)
+}
+
+fn write(output: &mut dyn Write, bytes: &[u8]) -> Result<(), RuntimeErrorKind>
+{
+  output.write_all(bytes).map_err(|error| RuntimeErrorKind::Io(error.to_string()))
+}
+
+#[cfg(test)]
+mod tests
+{
+  use crate::builtin::{call, scanf};
+  use crate::interpreter::{Array, Interpreter, RuntimeErrorKind, VariableType};
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  fn string(s: &str) -> VariableType
+  {
+    let values = Interpreter::string_values(s, s.len() + 1);
+    VariableType::Array(Rc::new(RefCell::new(Array::new(s.to_string(), VariableType::Char(0), values))))
+  }
+
+  fn output(name: &str, arguments: &[VariableType]) -> Result<(VariableType, String), RuntimeErrorKind>
+  {
+    let mut output = Vec::new();
+    let value = call(name, arguments, &mut std::io::empty(), &mut output)?;
+    Ok((value, String::from_utf8(output).unwrap()))
+  }
+
+  #[test]
+  fn test_printf()
+  {
+    let arguments = [string("%d + %c = %f%% %s\n"), VariableType::Int(-3), VariableType::Char(b'x' as i8),
+      VariableType::Float(1.5), string("ok")];
+    assert_eq!(output("printf", &arguments), Ok((VariableType::Int(22), "-3 + x = 1.500000% ok\n".to_string())));
+
+    assert_eq!(output("printf", &[string("%d")]), Err(RuntimeErrorKind::ArgumentCountMismatch {
+      function: "printf".to_string(),
+      expected: 2,
+      found: 1,
+    }));
+    assert_eq!(output("printf", &[string("%%"), VariableType::Int(1)]), Err(RuntimeErrorKind::ArgumentCountMismatch {
+      function: "printf".to_string(),
+      expected: 1,
+      found: 2,
+    }));
+    assert!(matches!(output("printf", &[string("%d"), VariableType::Float(1.0)]), Err(RuntimeErrorKind::TypeMismatch(_))));
+    assert!(matches!(output("printf", &[string("%x"), VariableType::Int(1)]), Err(RuntimeErrorKind::Unsupported(_))));
+    assert!(matches!(output("printf", &[VariableType::Int(1)]), Err(RuntimeErrorKind::TypeMismatch(_))));
+  }
+
+  #[test]
+  fn test_putchar_and_puts()
+  {
+    assert_eq!(output("putchar", &[VariableType::Int(b'a' as i32)]), Ok((VariableType::Int(97), "a".to_string())));
+    assert_eq!(output("puts", &[string("line")]), Ok((VariableType::Int(5), "line\n".to_string())));
+    assert!(matches!(output("puts", &[]), Err(RuntimeErrorKind::ArgumentCountMismatch { .. })));
+  }
+
+  fn input(name: &str, mut input: &[u8], count: usize) -> Result<Vec<VariableType>, RuntimeErrorKind>
+  {
+    (0..count).map(|_| call(name, &[], &mut input, &mut Vec::new())).collect()
+  }
+
+  #[test]
+  fn test_getchar_and_readers()
+  {
+    assert_eq!(input("getchar", b"ab", 3), Ok(vec![VariableType::Int(97), VariableType::Int(98), VariableType::Int(-1)]));
+    assert_eq!(input("read_int", b" 12\n-3 +4", 4),
+               Ok(vec![VariableType::Int(12), VariableType::Int(-3), VariableType::Int(4), VariableType::Int(-1)]));
+    assert_eq!(input("read_float", b"1.5 -2 .25", 4), Ok(vec![VariableType::Float(1.5), VariableType::Float(-2.0),
+      VariableType::Float(0.25), VariableType::Float(-1.0)]));
+    assert!(matches!(input("read_int", b"x", 1), Err(RuntimeErrorKind::InvalidInput(_))));
+    assert!(matches!(input("read_int", b"99999999999", 1), Err(RuntimeErrorKind::InvalidInput(_))));
+  }
+
+  #[test]
+  fn test_scanf()
+  {
+    let mut input: &[u8] = b" 3, 2.5\n7 x";
+    let arguments = [string("%d,%f"), VariableType::Int(0), VariableType::Float(0.0)];
+    assert_eq!(scanf(&arguments, &mut input), Ok(Some(vec![VariableType::Int(3), VariableType::Float(2.5)])));
+
+    // 一致しない入力は読まずに残す
+    let arguments = [string("%d %d"), VariableType::Int(0), VariableType::Char(0)];
+    assert_eq!(scanf(&arguments, &mut input), Ok(Some(vec![VariableType::Int(7)])));
+    assert_eq!(input, b"x");
+    let mut input: &[u8] = b"  ";
+    assert_eq!(scanf(&arguments, &mut input), Ok(None));
+
+    let mut input: &[u8] = b"1";
+    assert!(matches!(scanf(&[string("%d"), VariableType::Float(0.0)], &mut input),
+                     Err(RuntimeErrorKind::TypeMismatch(_))));
+    assert!(matches!(scanf(&[string("%d %d"), VariableType::Int(0)], &mut input),
+                     Err(RuntimeErrorKind::ArgumentCountMismatch { .. })));
+  }
+}
@@ -0,0 +1,1203 @@
+use crate::interpreter::{Array, Interpreter, RuntimeErrorKind, Struct, VariableType};
+use crate::lexical::{Constant, Operator, Span, UnaryOperator, ValueType};
+use crate::parser::{Argument, FunctionCall, FunctionDefinition, Leaf, Node};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// VM の命令. 飛び先は関数内の命令の位置
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction
+{
+  // 定数プールの値を積む
+  Constant(usize),
+  // 値を捨てる
+  Pop,
+
+  // ローカル変数. slot は関数フレーム内の位置, name と member は名前プールの位置
+  LoadLocal(usize),
+  DefineLocal(usize),
+  StoreLocal { slot: usize, name: usize },
+  SetLocalMember { slot: usize, name: usize, member: usize },
+
+  // グローバル変数
+  LoadGlobal(usize),
+  DefineGlobal(usize),
+  StoreGlobal(usize),
+  SetGlobalMember { global: usize, member: usize },
+
+  // 宣言時の型変換. 引数は変数名
+  ToInt(usize),
+  ToFloat(usize),
+  ToChar(usize),
+
+  // 配列と構造体. element は初期値の定数. CopyArray は定数プールの配列を複製して積む
+  NewArray { name: usize, size: usize, element: usize },
+  CopyArray(usize),
+  Index,
+  CheckIndex(usize),
+  StoreIndex,
+  Member(usize),
+
+  // 演算. Test は値を 0 か 1 の int にする
+  Binary(Operator),
+  Unary(UnaryOperator),
+  Test,
+
+  // 分岐. Keep は判定した値を捨てない
+  Jump(usize),
+  JumpIfFalse(usize),
+  JumpIfFalseKeep(usize),
+  JumpIfTrueKeep(usize),
+
+  // 関数
+  DefineFunction(usize),
+  Call { function: usize, argc: usize },
+  // 定義されていない関数. 実行時にホストの関数, 組み込み関数の順に探す. name は名前プールの位置
+  CallExternal { name: usize, argc: usize },
+  // scanf. 読み取った値は代入先ごとに ScanfValue で積む. 読み取っていない代入先は JumpIfNotScanned で飛ばす
+  Scanf { name: usize, argc: usize },
+  ScanfValue(usize),
+  JumpIfNotScanned { index: usize, target: usize },
+  CheckVoid(usize),
+  Return,
+
+  // エラープールの実行時エラーを発生させる
+  Raise(usize),
+}
+
+/// コンパイル済みの関数
+#[derive(Debug, Clone)]
+pub struct Function
+{
+  name: String,
+  parameters: Vec<Argument>,
+
+  // 引数を含むローカル変数の数
+  locals: usize,
+
+  code: Vec<Instruction>,
+
+  // 各命令に対応するソース上の位置
+  spans: Vec<Span>,
+}
+
+impl Function
+{
+  fn new(name: String, parameters: Vec<Argument>) -> Self
+  {
+    Function { name, parameters, locals: 0, code: Vec::new(), spans: Vec::new() }
+  }
+
+  pub fn name(&self) -> &String
+  {
+    &self.name
+  }
+
+  pub fn parameters(&self) -> &Vec<Argument>
+  {
+    &self.parameters
+  }
+
+  pub fn locals(&self) -> usize
+  {
+    self.locals
+  }
+
+  pub fn code(&self) -> &Vec<Instruction>
+  {
+    &self.code
+  }
+
+  pub fn spans(&self) -> &Vec<Span>
+  {
+    &self.spans
+  }
+}
+
+/// コンパイル結果
+#[derive(Debug, Clone)]
+pub struct Program
+{
+  functions: Vec<Function>,
+
+  // トップレベルの宣言と文を実行する関数
+  init: usize,
+
+  constants: Vec<VariableType>,
+  names: Vec<String>,
+  globals: Vec<String>,
+  errors: Vec<RuntimeErrorKind>,
+}
+
+impl Program
+{
+  pub fn functions(&self) -> &Vec<Function>
+  {
+    &self.functions
+  }
+
+  pub fn init(&self) -> usize
+  {
+    self.init
+  }
+
+  pub fn constants(&self) -> &Vec<VariableType>
+  {
+    &self.constants
+  }
+
+  pub fn names(&self) -> &Vec<String>
+  {
+    &self.names
+  }
+
+  pub fn globals(&self) -> &Vec<String>
+  {
+    &self.globals
+  }
+
+  pub fn errors(&self) -> &Vec<RuntimeErrorKind>
+  {
+    &self.errors
+  }
+
+  pub fn function_index(&self, name: &str) -> Option<usize>
+  {
+    self.functions.iter().position(|function| function.name == name)
+  }
+}
+
+/// 変数の格納場所
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Place
+{
+  Local(usize),
+  Global(usize),
+}
+
+/// break と continue の飛び先を後から埋めるための記録
+#[derive(Default)]
+struct Loop
+{
+  breaks: Vec<usize>,
+  continues: Vec<usize>,
+}
+
+/// 構文木をバイトコードに変換する
+pub struct Compiler
+{
+  functions: Vec<Function>,
+  function_indices: HashMap<String, usize>,
+
+  constants: Vec<VariableType>,
+  names: Vec<String>,
+  name_indices: HashMap<String, usize>,
+  globals: Vec<String>,
+  global_indices: HashMap<String, usize>,
+  errors: Vec<RuntimeErrorKind>,
+
+  struct_definition: HashMap<String, HashMap<String, ValueType>>,
+
+  // コンパイル中の関数の状態. scopes が空の場合はトップレベル
+  code: Vec<Instruction>,
+  spans: Vec<Span>,
+  scopes: Vec<HashMap<String, usize>>,
+  next_slot: usize,
+  max_slots: usize,
+  loops: Vec<Loop>,
+}
+
+impl Compiler
+{
+  fn new() -> Self
+  {
+    Compiler
+    {
+      functions: Vec::new(),
+      function_indices: HashMap::new(),
+      constants: Vec::new(),
+      names: Vec::new(),
+      name_indices: HashMap::new(),
+      globals: Vec::new(),
+      global_indices: HashMap::new(),
+      errors: Vec::new(),
+      struct_definition: HashMap::new(),
+      code: Vec::new(),
+      spans: Vec::new(),
+      scopes: Vec::new(),
+      next_slot: 0,
+      max_slots: 0,
+      loops: Vec::new(),
+    }
+  }
+
+  /// 構文木をコンパイルする. 実行時にしか分からないエラーは Raise 命令として埋め込む
+  pub fn compile(roots: &[Rc<RefCell<Node>>]) -> Program
+  {
+    let mut compiler = Compiler::new();
+
+    // 定義より前にある呼び出しを解決するため, 先に関数と構造体を登録する
+    for root in roots.iter()
+    {
+      match root.borrow().val()
+      {
+        Some(Leaf::FunctionDefinition(function_definition)) => {
+          compiler.declare_function(function_definition);
+        }
+        Some(Leaf::StructDefinition(name, members)) => {
+          compiler.struct_definition(name, members);
+        }
+        _ => {}
+      }
+    }
+
+    for root in roots.iter()
+    {
+      compiler.statement(root);
+    }
+
+    let span = roots.last().map(|root| root.borrow().span().clone()).unwrap_or_default();
+    let void = compiler.constant(VariableType::Void);
+    compiler.emit(Instruction::Constant(void), &span);
+    compiler.emit(Instruction::Return, &span);
+
+    let mut init = Function::new("<global>".to_string(), Vec::new());
+    init.code = std::mem::take(&mut compiler.code);
+    init.spans = std::mem::take(&mut compiler.spans);
+    compiler.functions.push(init);
+
+    Program
+    {
+      init: compiler.functions.len() - 1,
+      functions: compiler.functions,
+      constants: compiler.constants,
+      names: compiler.names,
+      globals: compiler.globals,
+      errors: compiler.errors,
+    }
+  }
+
+  fn emit(&mut self, instruction: Instruction, span: &Span) -> usize
+  {
+    self.code.push(instruction);
+    self.spans.push(span.clone());
+    self.code.len() - 1
+  }
+
+  /// at にある分岐命令の飛び先を次の命令にする
+  fn patch(&mut self, at: usize)
+  {
+    let target = self.code.len();
+    self.patch_to(at, target);
+  }
+
+  fn patch_to(&mut self, at: usize, target: usize)
+  {
+    self.code[at] = match self.code[at]
+    {
+      Instruction::Jump(_) => Instruction::Jump(target),
+      Instruction::JumpIfFalse(_) => Instruction::JumpIfFalse(target),
+      Instruction::JumpIfFalseKeep(_) => Instruction::JumpIfFalseKeep(target),
+      Instruction::JumpIfTrueKeep(_) => Instruction::JumpIfTrueKeep(target),
+      Instruction::JumpIfNotScanned { index, .. } => Instruction::JumpIfNotScanned { index, target },
+      instruction => instruction,
+    };
+  }
+
+  fn raise(&mut self, kind: RuntimeErrorKind, span: &Span)
+  {
+    self.errors.push(kind);
+    self.emit(Instruction::Raise(self.errors.len() - 1), span);
+  }
+
+  fn constant(&mut self, value: VariableType) -> usize
+  {
+    if let Some(index) = self.constants.iter().position(|constant| constant == &value)
+    {
+      return index;
+    }
+
+    self.constants.push(value);
+    self.constants.len() - 1
+  }
+
+  fn name(&mut self, name: &String) -> usize
+  {
+    if let Some(index) = self.name_indices.get(name)
+    {
+      return *index;
+    }
+
+    self.names.push(name.clone());
+    self.name_indices.insert(name.clone(), self.names.len() - 1);
+    self.names.len() - 1
+  }
+
+  fn global(&mut self, name: &String) -> usize
+  {
+    if let Some(index) = self.global_indices.get(name)
+    {
+      return *index;
+    }
+
+    self.globals.push(name.clone());
+    self.global_indices.insert(name.clone(), self.globals.len() - 1);
+    self.globals.len() - 1
+  }
+
+  /// 変数名を解決する. ローカル変数になければグローバル変数とする
+  fn resolve(&mut self, name: &String) -> Place
+  {
+    for scope in self.scopes.iter().rev()
+    {
+      if let Some(slot) = scope.get(name)
+      {
+        return Place::Local(*slot);
+      }
+    }
+
+    Place::Global(self.global(name))
+  }
+
+  /// 変数を宣言する. トップレベルではグローバル変数になる
+  fn declare_variable(&mut self, name: &String) -> Place
+  {
+    if let Some(scope) = self.scopes.last_mut()
+    {
+      let slot = self.next_slot;
+      scope.insert(name.clone(), slot);
+      self.next_slot += 1;
+      self.max_slots = self.max_slots.max(self.next_slot);
+      Place::Local(slot)
+    } else {
+      Place::Global(self.global(name))
+    }
+  }
+
+  fn declare_function(&mut self, function_definition: &FunctionDefinition) -> usize
+  {
+    let name = function_definition.name();
+    let parameters = function_definition.arguments().clone();
+
+    // 同名の関数は後の定義で上書きする
+    if let Some(index) = self.function_indices.get(name)
+    {
+      self.functions[*index].parameters = parameters;
+      return *index;
+    }
+
+    self.functions.push(Function::new(name.clone(), parameters));
+    self.function_indices.insert(name.clone(), self.functions.len() - 1);
+    self.functions.len() - 1
+  }
+
+  fn struct_definition(&mut self, name: &str, members: &[Rc<RefCell<Node>>])
+  {
+    let mut struct_members = HashMap::new();
+    for member in members
+    {
+      if let Some(Leaf::Declaration(value_type)) = member.borrow().val()
+      {
+        if let Some(Leaf::Identifier(identifier)) = member.borrow().lhs().and_then(|lhs| lhs.borrow().val().cloned())
+        {
+          struct_members.insert(identifier, value_type.clone());
+        }
+      }
+    }
+    self.struct_definition.insert(name.to_string(), struct_members);
+  }
+
+  /// 構造体の初期値を作成する
+  fn default_struct(&self, name: &String) -> Result<VariableType, RuntimeErrorKind>
+  {
+    let struct_def = self.struct_definition.get(name)
+      .ok_or_else(|| RuntimeErrorKind::UndefinedStruct(name.clone()))?;
+
+    let mut members = HashMap::new();
+    for (member_name, member_type) in struct_def
+    {
+      let value = match member_type
+      {
+        ValueType::Int => VariableType::Int(0),
+        ValueType::Float => VariableType::Float(0.0),
+        ValueType::Char => VariableType::Char(0),
+        ValueType::Struct(nested_struct_name) => {
+          // ネストした構造体の初期化
+          let nested_struct_def = self.struct_definition.get(nested_struct_name)
+            .ok_or_else(|| RuntimeErrorKind::UndefinedStruct(nested_struct_name.clone()))?;
+          let mut nested_members = HashMap::new();
+          for (n_name, n_type) in nested_struct_def
+          {
+            match n_type
+            {
+              ValueType::Int => { nested_members.insert(n_name.clone(), VariableType::Int(0)); }
+              ValueType::Float => { nested_members.insert(n_name.clone(), VariableType::Float(0.0)); }
+              ValueType::Char => { nested_members.insert(n_name.clone(), VariableType::Char(0)); }
+              _ => {
+                return Err(RuntimeErrorKind::Unsupported("深いネストの構造体は未対応です".to_string()));
+              }
+            }
+          }
+          VariableType::Struct(Struct::new(nested_struct_name.clone(), nested_members))
+        }
+        _ => {
+          return Err(RuntimeErrorKind::TypeMismatch(format!("メンバ {} : {:?}", member_name, member_type)));
+        }
+      };
+      members.insert(member_name.clone(), value);
+    }
+
+    Ok(VariableType::Struct(Struct::new(name.clone(), members)))
+  }
+
+  fn statement(&mut self, node: &Rc<RefCell<Node>>)
+  {
+    let span = node.borrow().span().clone();
+    let val = match node.borrow().val()
+    {
+      Some(val) => val.clone(),
+      None => return,
+    };
+
+    match val
+    {
+      Leaf::Declaration(variable_type) =>
+        {
+          self.declaration(node, &variable_type, &span);
+        }
+      Leaf::FunctionDefinition(function_definition) =>
+        {
+          self.function_definition(&function_definition, &span);
+        }
+      Leaf::FunctionCall(function_call) =>
+        {
+          // 文として呼び出した場合は void でもよい
+          self.function_call(&function_call, &span);
+          self.emit(Instruction::Pop, &span);
+        }
+      Leaf::Return =>
+        {
+          match node.borrow().lhs()
+          {
+            Some(lhs) => self.expression(lhs),
+            None => {
+              let void = self.constant(VariableType::Void);
+              self.emit(Instruction::Constant(void), &span);
+            }
+          }
+
+          // トップレベルの return は値を捨てて次へ進む
+          if self.scopes.is_empty() {
+            self.emit(Instruction::Pop, &span);
+          } else {
+            self.emit(Instruction::Return, &span);
+          }
+        }
+      Leaf::Assignment =>
+        {
+          self.assignment(node, &span);
+        }
+      Leaf::IfStatement(condition) =>
+        {
+          self.expression(&condition);
+          let condition_span = condition.borrow().span().clone();
+          let else_jump = self.emit(Instruction::JumpIfFalse(0), &condition_span);
+
+          if let Some(lhs) = node.borrow().lhs()
+          {
+            self.statement(lhs);
+          }
+
+          if let Some(rhs) = node.borrow().rhs()
+          {
+            let end_jump = self.emit(Instruction::Jump(0), &span);
+            self.patch(else_jump);
+            self.statement(rhs);
+            self.patch(end_jump);
+          } else {
+            self.patch(else_jump);
+          }
+        }
+      Leaf::WhileStatement =>
+        {
+          let start = self.code.len();
+          if let Some(condition) = node.borrow().lhs()
+          {
+            self.expression(condition);
+            let condition_span = condition.borrow().span().clone();
+            let end_jump = self.emit(Instruction::JumpIfFalse(0), &condition_span);
+
+            self.loops.push(Loop::default());
+            if let Some(rhs) = node.borrow().rhs()
+            {
+              self.statement(rhs);
+            }
+            self.emit(Instruction::Jump(start), &span);
+            self.patch(end_jump);
+            self.finish_loop(start);
+          }
+        }
+      Leaf::ForStatement(for_statement) =>
+        {
+          self.statement(for_statement.initializer());
+
+          let start = self.code.len();
+          let condition = for_statement.condition();
+          self.expression(condition);
+          let condition_span = condition.borrow().span().clone();
+          let end_jump = self.emit(Instruction::JumpIfFalse(0), &condition_span);
+
+          self.loops.push(Loop::default());
+          self.statement(for_statement.statement());
+
+          // continue は更新式へ飛ぶ
+          let update = self.code.len();
+          self.statement(for_statement.update());
+          self.emit(Instruction::Jump(start), &span);
+          self.patch(end_jump);
+          self.finish_loop(update);
+        }
+      Leaf::Break | Leaf::Continue =>
+        {
+          if self.loops.is_empty()
+          {
+            self.raise(RuntimeErrorKind::BreakOutsideLoop, &span);
+            return;
+          }
+
+          let jump = self.emit(Instruction::Jump(0), &span);
+          let current_loop = self.loops.last_mut().unwrap();
+          if let Leaf::Break = val {
+            current_loop.breaks.push(jump);
+          } else {
+            current_loop.continues.push(jump);
+          }
+        }
+      Leaf::BlockItem(nodes) =>
+        {
+          self.block(&nodes);
+        }
+      Leaf::ArrayAssignment(index) =>
+        {
+          self.array_assignment(node, &index, &span);
+        }
+      Leaf::StructDefinition(name, members) =>
+        {
+          self.struct_definition(&name, &members);
+        }
+
+      // 式文は評価して値を捨てる
+      Leaf::StructMemberAccess | Leaf::Operator(_) | Leaf::UnaryExpression(_) |
+      Leaf::ParenthesizedExpression | Leaf::Identifier(_) | Leaf::Constant(_) | Leaf::StringLiteral(_) |
+      Leaf::ArrayAccess =>
+        {
+          self.expression(node);
+          self.emit(Instruction::Pop, &span);
+        }
+      _ => {
+        self.raise(RuntimeErrorKind::Unsupported(format!("未対応のノードです : {}", val)), &span);
+      }
+    }
+  }
+
+  /// ループ内の break と continue の飛び先を埋める
+  fn finish_loop(&mut self, continue_target: usize)
+  {
+    if let Some(finished) = self.loops.pop()
+    {
+      for jump in finished.breaks
+      {
+        self.patch(jump);
+      }
+      for jump in finished.continues
+      {
+        self.patch_to(jump, continue_target);
+      }
+    }
+  }
+
+  fn block(&mut self, nodes: &[Rc<RefCell<Node>>])
+  {
+    // トップレベルのブロックで宣言した変数はグローバル変数になる
+    let is_local = !self.scopes.is_empty();
+    let next_slot = self.next_slot;
+    if is_local
+    {
+      self.scopes.push(HashMap::new());
+    }
+
+    for node in nodes.iter()
+    {
+      self.statement(node);
+    }
+
+    if is_local
+    {
+      self.scopes.pop();
+      self.next_slot = next_slot;
+    }
+  }
+
+  fn declaration(&mut self, node: &Rc<RefCell<Node>>, variable_type: &ValueType, span: &Span)
+  {
+    let identifier = match node.borrow().lhs().and_then(|lhs| lhs.borrow().val().cloned())
+    {
+      Some(Leaf::Identifier(identifier)) => identifier,
+      val => {
+        self.raise(RuntimeErrorKind::Unsupported(format!("識別子ではありません : {:?}", val)), span);
+        return;
+      }
+    };
+    let name = self.name(&identifier);
+    let rhs = node.borrow().rhs().cloned();
+
+    match rhs
+    {
+      Some(rhs) => {
+        if let Some(Leaf::Array(size)) = rhs.borrow().val()
+        {
+          let element = match variable_type
+          {
+            ValueType::Int => VariableType::Int(0),
+            ValueType::Float => VariableType::Float(0.0),
+            ValueType::Char => VariableType::Char(0),
+            ValueType::Struct(_) => {
+              self.raise(RuntimeErrorKind::Unsupported("構造体の配列は未対応です".to_string()), span);
+              return;
+            }
+            ValueType::Void => {
+              self.raise(RuntimeErrorKind::TypeMismatch("void の配列は定義できません".to_string()), span);
+              return;
+            }
+            ValueType::Array(_, _) => {
+              self.raise(RuntimeErrorKind::Unsupported("多次元配列は未対応です".to_string()), span);
+              return;
+            }
+          };
+
+          // char の配列を文字列で初期化する場合は, 初期化済みの配列を複製する
+          match rhs.borrow().lhs().and_then(|lhs| lhs.borrow().val().cloned())
+          {
+            Some(Leaf::StringLiteral(string)) => {
+              let values = Interpreter::string_values(&string, *size);
+              let array = Array::new(identifier.clone(), element, values);
+              let array = self.constant(VariableType::Array(Rc::new(RefCell::new(array))));
+              self.emit(Instruction::CopyArray(array), span);
+            }
+            _ => {
+              let element = self.constant(element);
+              self.emit(Instruction::NewArray { name, size: *size, element }, span);
+            }
+          }
+        } else {
+          self.expression(&rhs);
+          match variable_type
+          {
+            ValueType::Int => {
+              self.emit(Instruction::ToInt(name), span);
+            }
+            ValueType::Float => {
+              self.emit(Instruction::ToFloat(name), span);
+            }
+            ValueType::Char => {
+              self.emit(Instruction::ToChar(name), span);
+            }
+            ValueType::Struct(_) => {
+              self.raise(RuntimeErrorKind::Unsupported("構造体の代入による初期化は未対応です".to_string()), span);
+              return;
+            }
+            ValueType::Void => {
+              self.raise(RuntimeErrorKind::TypeMismatch("void 型の変数は定義できません".to_string()), span);
+              return;
+            }
+            ValueType::Array(_, _) => {
+              self.raise(RuntimeErrorKind::Unsupported("配列の代入による初期化は未対応です".to_string()), span);
+              return;
+            }
+          }
+        }
+      }
+      None => {
+        // 初期値がない場合は 0 で初期化
+        let value = match variable_type
+        {
+          ValueType::Int => Ok(VariableType::Int(0)),
+          ValueType::Float => Ok(VariableType::Float(0.0)),
+          ValueType::Char => Ok(VariableType::Char(0)),
+          ValueType::Struct(struct_name) => self.default_struct(struct_name),
+          _ => Err(RuntimeErrorKind::TypeMismatch(format!("{:?}", variable_type))),
+        };
+        match value
+        {
+          Ok(value) => {
+            let value = self.constant(value);
+            self.emit(Instruction::Constant(value), span);
+          }
+          Err(kind) => {
+            self.raise(kind, span);
+            return;
+          }
+        }
+      }
+    }
+
+    // 初期化子の評価が終わってから変数を宣言する
+    match self.declare_variable(&identifier)
+    {
+      Place::Local(slot) => self.emit(Instruction::DefineLocal(slot), span),
+      Place::Global(global) => self.emit(Instruction::DefineGlobal(global), span),
+    };
+  }
+
+  fn function_definition(&mut self, function_definition: &FunctionDefinition, span: &Span)
+  {
+    let index = self.declare_function(function_definition);
+
+    // トップレベルの状態を退避して関数本体をコンパイルする
+    let code = std::mem::take(&mut self.code);
+    let spans = std::mem::take(&mut self.spans);
+    let scopes = std::mem::take(&mut self.scopes);
+    let loops = std::mem::take(&mut self.loops);
+    let next_slot = self.next_slot;
+    let max_slots = self.max_slots;
+
+    // 引数は先頭のスロットに置く
+    let mut parameters = HashMap::new();
+    for (slot, argument) in function_definition.arguments().iter().enumerate()
+    {
+      parameters.insert(argument.identify().clone(), slot);
+    }
+    self.next_slot = parameters.len();
+    self.max_slots = parameters.len();
+    self.scopes.push(parameters);
+
+    for node in function_definition.body().iter()
+    {
+      self.statement(node);
+    }
+
+    // return がない場合は void を返す
+    let void = self.constant(VariableType::Void);
+    self.emit(Instruction::Constant(void), span);
+    self.emit(Instruction::Return, span);
+
+    let function = &mut self.functions[index];
+    function.locals = self.max_slots;
+    function.code = std::mem::replace(&mut self.code, code);
+    function.spans = std::mem::replace(&mut self.spans, spans);
+    self.scopes = scopes;
+    self.loops = loops;
+    self.next_slot = next_slot;
+    self.max_slots = max_slots;
+
+    self.emit(Instruction::DefineFunction(index), span);
+  }
+
+  fn function_call(&mut self, function_call: &FunctionCall, span: &Span)
+  {
+    let name = function_call.name();
+    let index = match self.function_indices.get(name)
+    {
+      Some(index) => *index,
+      // ホストの関数は VM の作成後に登録されるため, 実行時に探す
+      None => {
+        for argument in function_call.arguments().iter()
+        {
+          self.expression(argument);
+        }
+        let argc = function_call.arguments().len();
+        if name == "scanf"
+        {
+          let name = self.name(name);
+          self.emit(Instruction::Scanf { name, argc }, span);
+          self.scanf_targets(function_call);
+          return;
+        }
+        let name = self.name(name);
+        self.emit(Instruction::CallExternal { name, argc }, span);
+        return;
+      }
+    };
+
+    let expected = self.functions[index].parameters.len();
+    let found = function_call.arguments().len();
+    if expected != found
+    {
+      self.raise(RuntimeErrorKind::ArgumentCountMismatch { function: name.clone(), expected, found }, span);
+      return;
+    }
+
+    for argument in function_call.arguments().iter()
+    {
+      self.expression(argument);
+    }
+    self.emit(Instruction::Call { function: index, argc: found }, span);
+  }
+
+  /// scanf で読み取った値を 2 番目以降の引数の変数か配列の要素に代入する
+  fn scanf_targets(&mut self, function_call: &FunctionCall)
+  {
+    let mut jumps = Vec::new();
+    for (index, target) in function_call.arguments().iter().skip(1).enumerate()
+    {
+      let span = target.borrow().span().clone();
+      jumps.push(self.emit(Instruction::JumpIfNotScanned { index, target: 0 }, &span));
+
+      let val = target.borrow().val().cloned();
+      let lhs = target.borrow().lhs().cloned();
+      let rhs = target.borrow().rhs().cloned();
+      match (val, lhs, rhs)
+      {
+        (Some(Leaf::Identifier(identifier)), _, _) => {
+          self.emit(Instruction::ScanfValue(index), &span);
+          match self.resolve(&identifier)
+          {
+            Place::Local(slot) => {
+              let name = self.name(&identifier);
+              self.emit(Instruction::StoreLocal { slot, name }, &span);
+            }
+            Place::Global(global) => {
+              self.emit(Instruction::StoreGlobal(global), &span);
+            }
+          }
+        }
+        (Some(Leaf::ArrayAccess), Some(lhs), Some(rhs)) => {
+          let identifier = match lhs.borrow().val().cloned()
+          {
+            Some(Leaf::Identifier(identifier)) => identifier,
+            val => {
+              self.raise(RuntimeErrorKind::Unsupported(format!("識別子ではありません : {:?}", val)), &span);
+              continue;
+            }
+          };
+          self.expression(&lhs);
+          self.expression(&rhs);
+          let name = self.name(&identifier);
+          let index_span = rhs.borrow().span().clone();
+          self.emit(Instruction::CheckIndex(name), &index_span);
+          self.emit(Instruction::ScanfValue(index), &span);
+          self.emit(Instruction::StoreIndex, &span);
+        }
+        _ => {
+          self.raise(RuntimeErrorKind::Unsupported(
+            "scanf の引数には変数か配列の要素を指定してください".to_string()), &span);
+        }
+      }
+    }
+
+    for jump in jumps
+    {
+      self.patch(jump);
+    }
+  }
+
+  fn assignment(&mut self, node: &Rc<RefCell<Node>>, span: &Span)
+  {
+    let (lhs, rhs) = match node.borrow().get_lhs_and_rhs()
+    {
+      Some((lhs, rhs)) => (lhs.clone(), rhs.clone()),
+      None => return,
+    };
+
+    self.expression(&rhs);
+
+    let lhs_val = lhs.borrow().val().cloned();
+    match lhs_val
+    {
+      Some(Leaf::Identifier(identifier)) => {
+        match self.resolve(&identifier)
+        {
+          Place::Local(slot) => {
+            let name = self.name(&identifier);
+            self.emit(Instruction::StoreLocal { slot, name }, span);
+          }
+          Place::Global(global) => {
+            self.emit(Instruction::StoreGlobal(global), span);
+          }
+        }
+      }
+      Some(Leaf::StructMemberAccess) => {
+        let member = match lhs.borrow().rhs().and_then(|rhs| rhs.borrow().val().cloned())
+        {
+          Some(Leaf::Identifier(member)) => member,
+          val => {
+            self.raise(RuntimeErrorKind::Unsupported(format!("識別子ではありません : {:?}", val)), span);
+            return;
+          }
+        };
+        let member = self.name(&member);
+
+        // 識別子でない場合は、今のところ代入をサポートしない（例：(p1).x = 10 など）
+        match lhs.borrow().lhs().and_then(|lhs| lhs.borrow().val().cloned())
+        {
+          Some(Leaf::Identifier(identifier)) => {
+            match self.resolve(&identifier)
+            {
+              Place::Local(slot) => {
+                let name = self.name(&identifier);
+                self.emit(Instruction::SetLocalMember { slot, name, member }, span);
+              }
+              Place::Global(global) => {
+                self.emit(Instruction::SetGlobalMember { global, member }, span);
+              }
+            }
+          }
+          _ => {
+            self.raise(RuntimeErrorKind::Unsupported(
+              "構造体への代入に失敗しました：左辺が識別子ではありません".to_string()), span);
+          }
+        }
+      }
+      _ => {
+        self.emit(Instruction::Pop, span);
+      }
+    }
+  }
+
+  fn array_assignment(&mut self, node: &Rc<RefCell<Node>>, index: &Rc<RefCell<Node>>, span: &Span)
+  {
+    let identifier = match node.borrow().lhs().and_then(|lhs| lhs.borrow().val().cloned())
+    {
+      Some(Leaf::Identifier(identifier)) => identifier,
+      val => {
+        self.raise(RuntimeErrorKind::Unsupported(format!("識別子ではありません : {:?}", val)), span);
+        return;
+      }
+    };
+
+    match self.resolve(&identifier)
+    {
+      Place::Local(slot) => self.emit(Instruction::LoadLocal(slot), span),
+      Place::Global(global) => self.emit(Instruction::LoadGlobal(global), span),
+    };
+
+    self.expression(index);
+    let name = self.name(&identifier);
+    let index_span = index.borrow().span().clone();
+    self.emit(Instruction::CheckIndex(name), &index_span);
+
+    match node.borrow().rhs()
+    {
+      Some(rhs) => {
+        self.expression(rhs);
+        self.emit(Instruction::StoreIndex, span);
+      }
+      None => {
+        self.emit(Instruction::Pop, span);
+        self.emit(Instruction::Pop, span);
+      }
+    }
+  }
+
+  fn expression(&mut self, node: &Rc<RefCell<Node>>)
+  {
+    let span = node.borrow().span().clone();
+    let val = node.borrow().val().cloned();
+    let lhs = node.borrow().lhs().cloned();
+    let rhs = node.borrow().rhs().cloned();
+
+    match (val, lhs, rhs)
+    {
+      (Some(Leaf::Operator(op)), Some(lhs), Some(rhs)) =>
+        {
+          if let Operator::LogicalAnd | Operator::LogicalOr = op
+          {
+            self.logical_operator(op, &lhs, &rhs);
+            return;
+          }
+
+          self.expression(&lhs);
+          self.expression(&rhs);
+          self.emit(Instruction::Binary(op), &span);
+        }
+      (Some(Leaf::Constant(value)), _, _) =>
+        {
+          let value = match value
+          {
+            Constant::Integer(val) => VariableType::Int(val),
+            Constant::Float(val) => VariableType::Float(val.into()),
+            Constant::Char(val) => VariableType::Char(val),
+          };
+          let value = self.constant(value);
+          self.emit(Instruction::Constant(value), &span);
+        }
+      (Some(Leaf::StringLiteral(string)), _, _) =>
+        {
+          // 文字列は '\0' で終わる char の配列. 実行するたびに複製する
+          let values = Interpreter::string_values(&string, string.len() + 1);
+          let array = Array::new(format!("{:?}", string), VariableType::Char(0), values);
+          let array = self.constant(VariableType::Array(Rc::new(RefCell::new(array))));
+          self.emit(Instruction::CopyArray(array), &span);
+        }
+      (Some(Leaf::Identifier(identifier)), _, _) =>
+        {
+          match self.resolve(&identifier)
+          {
+            Place::Local(slot) => self.emit(Instruction::LoadLocal(slot), &span),
+            Place::Global(global) => self.emit(Instruction::LoadGlobal(global), &span),
+          };
+        }
+      (Some(Leaf::UnaryExpression(op)), Some(lhs), _) =>
+        {
+          self.expression(&lhs);
+          self.emit(Instruction::Unary(op), &span);
+        }
+      (Some(Leaf::ParenthesizedExpression), Some(lhs), _) =>
+        {
+          self.expression(&lhs);
+        }
+      (Some(Leaf::FunctionCall(function_call)), _, _) =>
+        {
+          // 式の中では void の値は使えない
+          self.function_call(&function_call, &span);
+          let name = self.name(function_call.name());
+          self.emit(Instruction::CheckVoid(name), &span);
+        }
+      (Some(Leaf::ArrayAccess), Some(lhs), Some(rhs)) =>
+        {
+          self.expression(&lhs);
+          self.expression(&rhs);
+          let index_span = rhs.borrow().span().clone();
+          self.emit(Instruction::Index, &index_span);
+        }
+      (Some(Leaf::StructMemberAccess), Some(lhs), Some(rhs)) =>
+        {
+          self.expression(&lhs);
+          match rhs.borrow().val()
+          {
+            Some(Leaf::Identifier(member)) => {
+              let member = self.name(member);
+              self.emit(Instruction::Member(member), &span);
+            }
+            val => {
+              self.raise(RuntimeErrorKind::Unsupported(format!("識別子ではありません : {:?}", val)), &span);
+            }
+          }
+        }
+      (val, _, _) => {
+        self.raise(RuntimeErrorKind::Unsupported(format!("未対応のノードです : {:?}", val)), &span);
+      }
+    }
+  }
+
+  /// '&&' と '||'. 左辺で結果が決まる場合は右辺を実行しない
+  fn logical_operator(&mut self, op: Operator, lhs: &Rc<RefCell<Node>>, rhs: &Rc<RefCell<Node>>)
+  {
+    let lhs_span = lhs.borrow().span().clone();
+    let rhs_span = rhs.borrow().span().clone();
+
+    self.expression(lhs);
+    self.emit(Instruction::Test, &lhs_span);
+    let jump = if let Operator::LogicalAnd = op {
+      self.emit(Instruction::JumpIfFalseKeep(0), &lhs_span)
+    } else {
+      self.emit(Instruction::JumpIfTrueKeep(0), &lhs_span)
+    };
+
+    self.emit(Instruction::Pop, &rhs_span);
+    self.expression(rhs);
+    self.emit(Instruction::Test, &rhs_span);
+    self.patch(jump);
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+  use crate::lexical::Lexer;
+  use crate::parser::Parser;
+
+  fn compile_program(program: &str) -> Program {
+    let mut lexer = Lexer::new(program.to_string());
+    lexer.tokenize().unwrap();
+    let mut parser = Parser::with_spans(lexer.tokens().clone(), lexer.spans().clone());
+    let roots = parser.parse().unwrap();
+    Compiler::compile(&roots)
+  }
+
+  #[test]
+  fn test_local_slots() {
+    let program = compile_program("
+        int g = 1;
+        int f(int a, int b) {
+            int c = a;
+            {
+                int a = 2;
+                int d = a;
+            }
+            int e = b;
+            return g;
+        }
+    ");
+
+    let f = &program.functions()[program.function_index("f").unwrap()];
+    assert_eq!(f.parameters().len(), 2);
+
+    // 内側のブロックの a と d は抜けた後に e が再利用する
+    assert_eq!(f.locals(), 5);
+    let defines: Vec<usize> = f.code().iter()
+      .filter_map(|instruction| match instruction {
+        Instruction::DefineLocal(slot) => Some(*slot),
+        _ => None,
+      })
+      .collect();
+    assert_eq!(defines, vec![2, 3, 4, 3]);
+
+    let loads: Vec<usize> = f.code().iter()
+      .filter_map(|instruction| match instruction {
+        Instruction::LoadLocal(slot) => Some(*slot),
+        _ => None,
+      })
+      .collect();
+    assert_eq!(loads, vec![0, 3, 1]);
+
+    // g はグローバル変数として解決される
+    assert!(f.code().contains(&Instruction::LoadGlobal(0)));
+    assert_eq!(program.globals(), &vec!["g".to_string()]);
+  }
+
+  #[test]
+  fn test_constant_pool() {
+    let program = compile_program("
+        int x = 1 + 1;
+        float y = 1.5 * 1.5;
+        int main() { return 1; }
+    ");
+
+    // 同じ定数は一つにまとめる
+    let constants = program.constants();
+    assert_eq!(constants.iter().filter(|c| **c == VariableType::Int(1)).count(), 1);
+    assert_eq!(constants.iter().filter(|c| **c == VariableType::Float(1.5)).count(), 1);
+  }
+
+  #[test]
+  fn test_loop_jumps() {
+    let program = compile_program("
+        int main() {
+            int i;
+            for (i = 0; i < 10; i = i + 1) {
+                if (i == 5) { break; }
+                continue;
+            }
+            return i;
+        }
+    ");
+
+    let main = &program.functions()[program.function_index("main").unwrap()];
+    let code = main.code();
+
+    // break はループの外へ, continue は更新式へ飛ぶ
+    let end = code.iter().position(|instruction| matches!(instruction, Instruction::JumpIfFalse(_))).unwrap();
+    let end_target = match code[end] { Instruction::JumpIfFalse(target) => target, _ => unreachable!() };
+    let jumps: Vec<usize> = code.iter()
+      .filter_map(|instruction| match instruction {
+        Instruction::Jump(target) => Some(*target),
+        _ => None,
+      })
+      .collect();
+    assert!(jumps.contains(&end_target));
+    assert!(jumps.iter().all(|target| *target <= code.len()));
+  }
+}
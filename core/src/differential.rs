@@ -0,0 +1,149 @@
+//! インタプリタと LLVM JIT で samples 以下のプログラムを実行し, 結果が一致することを確かめる
+use crate::interpreter::{Interpreter, Variable, VariableType};
+use crate::lexical::Lexer;
+use crate::llvm_ir::{jit_run_with_globals, CompileOptions};
+use crate::parser::{Node, Parser};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// 実行結果. 戻り値と, グローバル変数を要素やメンバごとに文字列にしたもの
+#[derive(Debug, PartialEq)]
+struct Outcome
+{
+    return_value: String,
+    globals: BTreeMap<String, String>,
+}
+
+fn parse(path: &Path) -> Vec<Rc<RefCell<Node>>>
+{
+    let program = fs::read_to_string(path).unwrap();
+    let mut lexer = Lexer::with_file_name(program, &path.display().to_string());
+    lexer.tokenize().unwrap_or_else(|errors| panic!("{}: 字句解析に失敗しました : {:?}", path.display(), errors));
+
+    let mut parser = Parser::with_spans(lexer.tokens().clone(), lexer.spans().clone());
+    parser.parse().unwrap_or_else(|errors| panic!("{}: 構文解析に失敗しました : {:?}", path.display(), errors));
+    parser.roots().clone()
+}
+
+/// 型と値を文字列にする. float は f64 の値をそのまま比べる
+fn format_value(value: &VariableType) -> String
+{
+    match value {
+        VariableType::Int(value) => format!("int {}", value),
+        VariableType::Float(value) => format!("float {:?}", value),
+        VariableType::Char(value) => format!("char {}", value),
+        value => format!("{}", value),
+    }
+}
+
+/// 配列は要素ごと, 構造体はメンバごとに並べる
+fn flatten(name: String, value: &VariableType, globals: &mut BTreeMap<String, String>)
+{
+    match value {
+        VariableType::Array(array) => {
+            for (i, value) in array.borrow().values().iter().enumerate() {
+                flatten(format!("{}[{}]", name, i), value, globals);
+            }
+        }
+        VariableType::Struct(s) => {
+            for (member, value) in s.members().iter() {
+                flatten(format!("{}.{}", name, member), value, globals);
+            }
+        }
+        value => {
+            globals.insert(name, format_value(value));
+        }
+    }
+}
+
+fn outcome(return_value: String, variables: HashMap<String, Variable>) -> Outcome
+{
+    let mut globals = BTreeMap::new();
+    for (name, variable) in variables {
+        let value = match variable {
+            Variable::Value(value) => value,
+            Variable::Array(array) => VariableType::Array(array),
+            Variable::Struct(s) => VariableType::Struct(s),
+        };
+        flatten(name, &value, &mut globals);
+    }
+
+    Outcome { return_value, globals }
+}
+
+fn run_interpreter(path: &Path) -> Outcome
+{
+    let roots = parse(path);
+    let mut interpreter = Interpreter::new(&roots);
+    let return_value = match interpreter.run() {
+        Ok(VariableType::Void) => format_value(&VariableType::Int(0)),
+        Ok(value) => format_value(&value),
+        Err(error) => format!("error {}", error),
+    };
+
+    outcome(return_value, interpreter.global_variables())
+}
+
+fn run_jit(path: &Path) -> Outcome
+{
+    let roots = parse(path);
+    match jit_run_with_globals(&roots, &CompileOptions::default()) {
+        Ok((return_value, globals)) => outcome(format_value(&VariableType::Int(return_value)), globals),
+        Err(error) => outcome(format!("error {}", error), HashMap::new()),
+    }
+}
+
+/// 一致しない項目を 1 行ずつ並べる
+fn diff(interpreter: &Outcome, jit: &Outcome) -> Vec<String>
+{
+    let mut lines = Vec::new();
+    if interpreter.return_value != jit.return_value {
+        lines.push(format!("  戻り値        interpreter: {:<16} jit: {}", interpreter.return_value, jit.return_value));
+    }
+
+    let names: BTreeSet<&String> = interpreter.globals.keys().chain(jit.globals.keys()).collect();
+    for name in names {
+        let lhs = interpreter.globals.get(name);
+        let rhs = jit.globals.get(name);
+        if lhs != rhs {
+            lines.push(format!("  {:<13} interpreter: {:<16} jit: {}", name,
+                               lhs.map_or("(なし)", |value| value.as_str()),
+                               rhs.map_or("(なし)", |value| value.as_str())));
+        }
+    }
+
+    lines
+}
+
+fn samples() -> Vec<PathBuf>
+{
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("samples");
+    let mut paths: Vec<PathBuf> = fs::read_dir(&directory).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "txt"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn test_samples()
+{
+    let mut failures = Vec::new();
+    let paths = samples();
+    assert!(!paths.is_empty(), "サンプルがありません");
+
+    for path in paths.iter() {
+        let interpreter = run_interpreter(path);
+        let jit = run_jit(path);
+        let lines = diff(&interpreter, &jit);
+        if !lines.is_empty() {
+            failures.push(format!("{}\n{}", path.display(), lines.join("\n")));
+        }
+    }
+
+    assert!(failures.is_empty(), "インタプリタと JIT の結果が一致しません\n{}", failures.join("\n"));
+}
@@ -1,9 +1,12 @@
+use crate::builtin;
 use crate::interpreter::VariableType::Int;
-use crate::lexical::{Constant, Operator, UnaryOperator, ValueType};
-use crate::parser::{FunctionCall, FunctionDefinition, Leaf, Node};
+use crate::lexical::{Constant, Operator, Span, UnaryOperator, ValueType};
+use crate::parser::{Argument, FunctionCall, FunctionDefinition, Leaf, Node};
+use crate::resolver::{Binding, Resolver};
 use std::cell::{Ref, RefCell};
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::io::{BufRead, Write};
 use std::rc::Rc;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -35,6 +38,25 @@ impl Array
   {
     &self.values
   }
+
+  pub(crate) fn values_mut(&mut self) -> &mut Vec<VariableType>
+  {
+    &mut self.values
+  }
+
+  /// index の要素に配列の型に変換した値を代入する
+  pub fn set(&mut self, index: usize, value: VariableType) -> Result<(), RuntimeErrorKind>
+  {
+    let length = self.values.len();
+    match self.values.get_mut(index)
+    {
+      Some(element) => {
+        *element = Interpreter::store_value(&self.variable_type, value);
+        Ok(())
+      }
+      None => Err(RuntimeErrorKind::IndexOutOfBounds { array: self.name.clone(), index: index as i32, length }),
+    }
+  }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -42,6 +64,7 @@ pub enum VariableType
 {
   Int(i32),
   Float(f64),
+  Char(i8),
   Struct(Struct),
   Array(Rc<RefCell<Array>>),
   Void,
@@ -59,6 +82,7 @@ impl std::fmt::Display for VariableType
     {
       VariableType::Int(val) => write!(f, "{}", val),
       VariableType::Float(val) => write!(f, "{}", val),
+      VariableType::Char(val) => write!(f, "'{}'", (*val as u8).escape_ascii()),
       VariableType::Struct(s) => write!(f, "struct {}", s.name),
       VariableType::Array(a) => write!(f, "array {}", a.borrow().name),
       VariableType::Void => write!(f, "void"),
@@ -92,6 +116,11 @@ impl Struct
   {
     &self.members
   }
+
+  pub(crate) fn members_mut(&mut self) -> &mut HashMap<String, VariableType>
+  {
+    &mut self.members
+  }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -101,82 +130,346 @@ pub enum Variable {
   Struct(Struct),
 }
 
+/// 実行時エラーの種類
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeErrorKind
+{
+  DivisionByZero,
+  UndefinedVariable(String),
+  UndefinedFunction(String),
+  UndefinedStruct(String),
+  UndefinedMember(String),
+  ArgumentCountMismatch { function: String, expected: usize, found: usize },
+  NonIntegerIndex(String),
+  IndexOutOfBounds { array: String, index: i32, length: usize },
+  TypeMismatch(String),
+  VoidValue(String),
+  BreakOutsideLoop,
+  MissingMain,
+  Unsupported(String),
+  Io(String),
+  InvalidInput(String),
+}
+
+impl std::fmt::Display for RuntimeErrorKind
+{
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+  {
+    match self
+    {
+      RuntimeErrorKind::DivisionByZero => write!(f, "0で割ることはできません"),
+      RuntimeErrorKind::UndefinedVariable(name) => write!(f, "未定義の変数です : {}", name),
+      RuntimeErrorKind::UndefinedFunction(name) => write!(f, "関数が見つかりません : {}", name),
+      RuntimeErrorKind::UndefinedStruct(name) => write!(f, "未定義の構造体です : {}", name),
+      RuntimeErrorKind::UndefinedMember(name) => write!(f, "メンバ {} が見つかりません", name),
+      RuntimeErrorKind::ArgumentCountMismatch { function, expected, found } =>
+        write!(f, "引数の数が一致しません : {} は {} 個の引数を取りますが {} 個渡されました",
+               function, expected, found),
+      RuntimeErrorKind::NonIntegerIndex(value) => write!(f, "Array index は整数で指定してください : {}", value),
+      RuntimeErrorKind::IndexOutOfBounds { array, index, length } =>
+        write!(f, "配列の範囲外です : {}[{}] (要素数 {})", array, index, length),
+      RuntimeErrorKind::TypeMismatch(message) => write!(f, "未対応の型です : {}", message),
+      RuntimeErrorKind::VoidValue(name) => write!(f, "void は代入できません : {}", name),
+      RuntimeErrorKind::BreakOutsideLoop => write!(f, "ループの外で break, continue は使用できません"),
+      RuntimeErrorKind::MissingMain => write!(f, "main 関数が見つかりません"),
+      RuntimeErrorKind::Unsupported(message) => write!(f, "{}", message),
+      RuntimeErrorKind::Io(message) => write!(f, "入出力に失敗しました : {}", message),
+      RuntimeErrorKind::InvalidInput(message) => write!(f, "入力を読み取れません : {}", message),
+    }
+  }
+}
+
+/// 実行時エラー発生時に呼び出し中だった関数. main は呼び出し位置を持たない
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame
+{
+  function: String,
+  call_site: Option<Span>,
+}
+
+impl StackFrame
+{
+  pub fn new(function: String, call_site: Option<Span>) -> Self
+  {
+    StackFrame { function, call_site }
+  }
+
+  pub fn function(&self) -> &String
+  {
+    &self.function
+  }
+
+  pub fn call_site(&self) -> Option<&Span>
+  {
+    self.call_site.as_ref()
+  }
+}
+
+/// 実行時エラー. call_stack は外側の関数から順に並ぶ. main が無い場合などソース上の位置が無いものは span が None
 #[derive(Debug, Clone, PartialEq)]
-pub enum Scope
+pub struct RuntimeError
+{
+  kind: RuntimeErrorKind,
+  span: Option<Span>,
+  call_stack: Vec<StackFrame>,
+}
+
+impl RuntimeError
+{
+  pub fn new(kind: RuntimeErrorKind, span: Option<Span>, call_stack: Vec<StackFrame>) -> Self
+  {
+    RuntimeError { kind, span, call_stack }
+  }
+
+  pub fn kind(&self) -> &RuntimeErrorKind
+  {
+    &self.kind
+  }
+
+  pub fn span(&self) -> Option<&Span>
+  {
+    self.span.as_ref()
+  }
+
+  pub fn call_stack(&self) -> &Vec<StackFrame>
+  {
+    &self.call_stack
+  }
+
+  /// 外側の呼び出し履歴を先頭に付け足す
+  pub(crate) fn called_from(mut self, mut call_stack: Vec<StackFrame>) -> Self
+  {
+    call_stack.append(&mut self.call_stack);
+    self.call_stack = call_stack;
+    self
+  }
+}
+
+impl std::fmt::Display for RuntimeError
 {
-  Global,
-  Local,
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+  {
+    match &self.span
+    {
+      Some(span) => write!(f, "{}: {}", span, self.kind)?,
+      None => write!(f, "{}", self.kind)?,
+    }
+
+    // 内側の関数から表示する
+    for frame in self.call_stack.iter().rev()
+    {
+      match &frame.call_site
+      {
+        Some(call_site) => write!(f, "\n  関数 {} ({} で呼び出し)", frame.function, call_site)?,
+        None => write!(f, "\n  関数 {}", frame.function)?,
+      }
+    }
+
+    Ok(())
+  }
 }
 
+impl std::error::Error for RuntimeError {}
+
+/// ホストから登録された関数. 配列は参照で渡される
+pub(crate) type NativeFunction = Rc<dyn Fn(&[VariableType]) -> Result<VariableType, RuntimeError>>;
+
 pub struct Interpreter
 {
   roots: Vec<Rc<RefCell<Node>>>,
 
-  // すべての領域からアクセス可能な変数
-  global_variables: HashMap<String, Variable>,
+  // すべての領域からアクセス可能な変数. Resolver が割り当てた位置に置く
+  global_variables: Vec<Option<Variable>>,
+  global_names: Vec<String>,
 
-  // 関数の中でのみアクセス可能な変数
-  local_variables: Vec<Vec<HashMap<String, Variable>>>,
+  // 関数の中でのみアクセス可能な変数. 関数呼び出し, スコープ, スロットの順に並ぶ
+  local_variables: Vec<Vec<Vec<Variable>>>,
 
   function_definition: HashMap<String, FunctionDefinition>,
 
+  // register_native で登録された関数と引数の数
+  native_functions: HashMap<String, (usize, NativeFunction)>,
+
   struct_definition: HashMap<String, HashMap<String, ValueType>>,
 
-  scope: Scope,
+  // 実行中の関数呼び出し
+  call_stack: Vec<StackFrame>,
+
+  // getchar などの組み込み関数の入力元
+  input: Box<dyn BufRead>,
+
+  // printf などの組み込み関数の出力先
+  output: Box<dyn Write>,
 }
 
 impl Interpreter
 {
-  pub fn new(roots: &Vec<Rc<RefCell<Node>>>) -> Self
+  /// 組み込み関数の入力は空になる
+  pub fn new(roots: &[Rc<RefCell<Node>>]) -> Self
+  {
+    Self::with_input(roots, Box::new(std::io::empty()))
+  }
+
+  /// 組み込み関数の入力を input から読む
+  pub fn with_input(roots: &[Rc<RefCell<Node>>], input: Box<dyn BufRead>) -> Self
   {
     Interpreter
     {
-      roots: roots.clone(),
-      global_variables: HashMap::new(),
+      roots: roots.to_vec(),
+      global_variables: Vec::new(),
+      global_names: Vec::new(),
       local_variables: Vec::new(),
       function_definition: HashMap::new(),
+      native_functions: HashMap::new(),
       struct_definition: HashMap::new(),
-      scope: Scope::Global,
+      call_stack: Vec::new(),
+      input,
+      output: Box::new(std::io::stdout()),
     }
   }
 
-  pub fn global_variables(&self) -> &HashMap<String, Variable>
+  /// 組み込み関数の出力先を設定する. 初期値は標準出力
+  pub fn set_output(&mut self, output: Box<dyn Write>)
   {
-    &self.global_variables
+    self.output = output;
   }
 
-  pub fn run(&mut self) -> VariableType
+  /// ホストの関数を登録する. 同名の組み込み関数より優先され, ユーザが定義した関数が最優先になる
+  pub fn register_native<F>(&mut self, name: &str, arity: usize, function: F)
+  where
+    F: Fn(&[VariableType]) -> Result<VariableType, RuntimeError> + 'static,
   {
-    let mut roots = self.roots.clone();
-    for root in roots.iter()
+    self.native_functions.insert(name.to_string(), (arity, Rc::new(function)));
+  }
+
+  /// 定義済みのグローバル変数を名前で引けるようにして返す
+  pub fn global_variables(&self) -> HashMap<String, Variable>
+  {
+    self.global_names.iter().zip(self.global_variables.iter())
+      .filter_map(|(name, variable)| Some((name.clone(), variable.clone()?)))
+      .collect()
+  }
+
+  /// roots を読み込んで main を実行する
+  pub fn run(&mut self) -> Result<VariableType, RuntimeError>
+  {
+    let roots = self.roots.clone();
+    self.load(&roots)?;
+
+    // main 関数を呼び出し実行する
+    if !self.function_definition.contains_key("main")
     {
-      self.interpret_node(root);
+      return Err(self.runtime_error_at(RuntimeErrorKind::MissingMain, None));
     }
 
-    // main 関数を呼び出し実行する
-    if let Some(main) = self.function_definition.get("main")
+    self.call("main", &[])
+  }
+
+  /// 関数と構造体を登録し, グローバル変数を初期化する. 関数は実行しない
+  pub fn load(&mut self, roots: &[Rc<RefCell<Node>>]) -> Result<(), RuntimeError>
+  {
+    self.roots = roots.to_vec();
+    self.local_variables.clear();
+    self.call_stack.clear();
+    self.function_definition.clear();
+    self.struct_definition.clear();
+
+    // 識別子の参照先を実行前に決めておく
+    self.global_names = Resolver::resolve(&self.roots);
+    self.global_variables = vec![None; self.global_names.len()];
+
+    let roots = self.roots.clone();
+    for root in roots.iter()
     {
-      // main の function_call を作成
-      let function_call = FunctionCall::new("main".to_string());
+      self.interpret_node(root)?;
+    }
+
+    Ok(())
+  }
 
-      // main 関数を呼び出し
-      self.scope = Scope::Local;
-      let val = self.function_call(&function_call);
-      self.scope = Scope::Global;
+  /// 読み込み済みの関数かホストの関数を呼び出す. グローバル変数は呼び出しの間で保持される
+  pub fn call(&mut self, name: &str, arguments: &[VariableType]) -> Result<VariableType, RuntimeError>
+  {
+    self.local_variables.clear();
+    self.call_stack.clear();
 
-      if let VariableType::Return(val) = val
+    // ホストからの呼び出しにはソース上の位置が無い
+    let val = if let Some(function_definition) = self.function_definition.get(name).cloned()
+    {
+      self.check_argument_count(name, function_definition.arguments().len(), arguments.len(), None)?;
+      let mut new_variables = Vec::new();
+      for (parameter, argument) in function_definition.arguments().iter().zip(arguments)
       {
-        return *val;
+        new_variables.push(self.bind_argument(name, parameter, argument.clone(), None)?);
       }
-      return val;
+      self.call_definition(name, &function_definition, new_variables, None)?
+    } else if let Some((arity, function)) = self.native_functions.get(name).cloned()
+    {
+      self.check_argument_count(name, arity, arguments.len(), None)?;
+      self.call_native(name, function, arguments, None)?
     } else {
-      panic!("main 関数が見つかりません");
+      return Err(self.runtime_error_at(RuntimeErrorKind::UndefinedFunction(name.to_string()), None));
+    };
+
+    self.output.flush()
+      .map_err(|error| self.runtime_error_at(RuntimeErrorKind::Io(error.to_string()), None))?;
+
+    Ok(self.remove_return(val))
+  }
+
+  /// グローバル変数の値を返す. 配列は参照を返す
+  pub fn get_global(&self, name: &str) -> Option<VariableType>
+  {
+    let index = self.global_names.iter().position(|global| global == name)?;
+    match self.global_variables[index].as_ref()?
+    {
+      Variable::Value(value) => Some(value.clone()),
+      Variable::Array(array) => Some(VariableType::Array(array.clone())),
+      Variable::Struct(s) => Some(VariableType::Struct(s.clone())),
     }
   }
 
+  /// グローバル変数に宣言された型に変換した値を代入する
+  pub fn set_global(&mut self, name: &str, value: VariableType) -> Result<(), RuntimeError>
+  {
+    let variable = self.global_names.iter().position(|global| global == name)
+      .and_then(|index| self.global_variables[index].as_mut());
+
+    let mismatch = match (variable, value)
+    {
+      (None, _) => return Err(self.runtime_error_at(RuntimeErrorKind::UndefinedVariable(name.to_string()), None)),
+      (Some(Variable::Value(variable)), value @ (VariableType::Int(_) | VariableType::Float(_) | VariableType::Char(_))) =>
+        {
+          *variable = Self::store_value(variable, value);
+          return Ok(());
+        }
+      (Some(Variable::Array(array)), VariableType::Array(values))
+      if array.borrow().values.len() == values.borrow().values.len() =>
+        {
+          // 要素を配列の型に変換して写す. 同じ配列の場合も書き換えずに済むよう先に複製する
+          let values = values.borrow().values.clone();
+          let mut array = array.borrow_mut();
+          for (index, value) in values.into_iter().enumerate()
+          {
+            array.values[index] = Self::store_value(&array.variable_type, value);
+          }
+          return Ok(());
+        }
+      (Some(Variable::Struct(s)), VariableType::Struct(value)) if s.name == value.name =>
+        {
+          *s = value;
+          return Ok(());
+        }
+      (Some(_), value) => value,
+    };
+
+    Err(self.runtime_error_at(
+      RuntimeErrorKind::TypeMismatch(format!("{} に {} は代入できません", name, mismatch)), None))
+  }
+
   pub fn show_variables(&self)
   {
-    for (name, variable) in &self.global_variables
+    for (_name, variable) in self.global_variables()
     {
       match variable
       {
@@ -196,8 +489,21 @@ impl Interpreter
     }
   }
 
-  fn interpret_node(&mut self, node: &Rc<RefCell<Node>>) -> VariableType
+  /// 現在の呼び出し履歴を付けて実行時エラーを作成する
+  fn runtime_error(&self, kind: RuntimeErrorKind, span: &Span) -> RuntimeError
+  {
+    self.runtime_error_at(kind, Some(span))
+  }
+
+  /// ソース上の位置が無い場合もある実行時エラーを作成する
+  fn runtime_error_at(&self, kind: RuntimeErrorKind, span: Option<&Span>) -> RuntimeError
+  {
+    RuntimeError::new(kind, span.cloned(), self.call_stack.clone())
+  }
+
+  fn interpret_node(&mut self, node: &Rc<RefCell<Node>>) -> Result<VariableType, RuntimeError>
   {
+    let span = node.borrow().span().clone();
     if let Some(val) = node.clone().borrow().val()
     {
       match val
@@ -207,17 +513,24 @@ impl Interpreter
             // node の左側から変数名を取得
             if let Some(lhs) = node.borrow().lhs()
             {
-              let identifier = self.identifier_name(lhs);
+              let identifier = self.identifier_name(lhs)?;
+              let binding = lhs.borrow().binding();
 
               // node の右側から値を取得
               if let Some(rhs) = node.borrow().rhs()
               {
-                if let Leaf::Array(size) = rhs.borrow().val().unwrap()
+                if let Some(Leaf::Array(size)) = rhs.borrow().val()
                 {
-                  self.array_variable_definition(&variable_type, identifier,
-                                                 *size);
+                  // char の配列は文字列で初期化できる
+                  let initializer = match rhs.borrow().lhs().and_then(|lhs| lhs.borrow().val().cloned())
+                  {
+                    Some(Leaf::StringLiteral(string)) => Some(string),
+                    _ => None,
+                  };
+                  self.array_variable_definition(variable_type, identifier, binding,
+                                                 *size, initializer, &span)?;
                 } else {
-                  let mut value = self.statement(rhs);
+                  let mut value = self.statement(rhs)?;
 
                   // value が 定数ではなく Return の時中身を取り出す
                   if let VariableType::Return(val) = value
@@ -225,7 +538,7 @@ impl Interpreter
                     value = val.as_ref().clone();
                   }
 
-                  self.variable_definition(variable_type, identifier, value);
+                  self.variable_definition(variable_type, identifier, binding, value, &span)?;
                 }
               } else {
                 // 初期値がない場合は 0 で初期化
@@ -233,16 +546,20 @@ impl Interpreter
                 {
                   ValueType::Int =>
                     {
-                      self.insert_variable_int(identifier, 0);
+                      self.insert_variable_int(binding, 0);
                     }
                   ValueType::Float =>
                     {
-                      self.insert_variable_float(identifier, 0.0);
+                      self.insert_variable_float(binding, 0.0);
+                    }
+                  ValueType::Char =>
+                    {
+                      self.insert_variable(binding, Variable::Value(VariableType::Char(0)));
                     }
                   ValueType::Struct(struct_name) =>
                     {
                       // 構造体定義からメンバを取得
-                      let struct_def = self.struct_definition.get(struct_name).unwrap().clone();
+                      let struct_def = self.struct_definition(struct_name, &span)?;
                       let mut members = HashMap::new();
                       for (member_name, member_type) in struct_def
                       {
@@ -250,28 +567,39 @@ impl Interpreter
                         {
                           ValueType::Int => { members.insert(member_name, VariableType::Int(0)); }
                           ValueType::Float => { members.insert(member_name, VariableType::Float(0.0)); }
+                          ValueType::Char => { members.insert(member_name, VariableType::Char(0)); }
                           ValueType::Struct(nested_struct_name) => {
                             // ネストした構造体の初期化
-                            let nested_struct_def = self.struct_definition.get(&nested_struct_name).unwrap().clone();
+                            let nested_struct_def = self.struct_definition(&nested_struct_name, &span)?;
                             let mut nested_members = HashMap::new();
                             for (n_name, n_type) in nested_struct_def {
                                 match n_type {
                                     ValueType::Int => { nested_members.insert(n_name, VariableType::Int(0)); }
                                     ValueType::Float => { nested_members.insert(n_name, VariableType::Float(0.0)); }
-                                    _ => { panic!("深いネストの構造体は未対応です"); }
+                                    ValueType::Char => { nested_members.insert(n_name, VariableType::Char(0)); }
+                                    _ => {
+                                      return Err(self.runtime_error(
+                                        RuntimeErrorKind::Unsupported("深いネストの構造体は未対応です".to_string()),
+                                        &span));
+                                    }
                                 }
                             }
                             let nested_s = Struct::new(nested_struct_name, nested_members);
                             members.insert(member_name, VariableType::Struct(nested_s));
                           }
-                          _ => { panic!("未対応のメンバ型です : {:?}", member_type); }
+                          _ => {
+                            return Err(self.runtime_error(
+                              RuntimeErrorKind::TypeMismatch(format!("メンバ {} : {:?}", member_name, member_type)),
+                              &span));
+                          }
                         }
                       }
                       let s = Struct::new(struct_name.clone(), members);
-                      self.insert_variable(identifier, Variable::Struct(s));
+                      self.insert_variable(binding, Variable::Struct(s));
                     }
                   _ => {
-                    panic!("未対応の型です : {:?}", variable_type);
+                    return Err(self.runtime_error(
+                      RuntimeErrorKind::TypeMismatch(format!("{:?}", variable_type)), &span));
                   }
                 }
               }
@@ -286,7 +614,7 @@ impl Interpreter
         // 関数呼び出し
         Leaf::FunctionCall(function_call) =>
           {
-            self.function_call(function_call);
+            self.function_call(function_call, Some(span))?;
           }
 
         // return 文
@@ -294,10 +622,12 @@ impl Interpreter
           {
             if let Some(lhs) = node.borrow().lhs()
             {
-              let value = self.statement(lhs);
-              println!("return value : {:?}", value);
-              return VariableType::Return(Box::new(value));
+              let value = self.statement(lhs)?;
+              return Ok(VariableType::Return(Box::new(value)));
             }
+
+            // 戻り値のない return
+            return Ok(VariableType::Return(Box::new(VariableType::Void)));
           }
         Leaf::Assignment =>
           {
@@ -317,11 +647,11 @@ impl Interpreter
           }
         Leaf::Break =>
           {
-            return VariableType::Break;
+            return Ok(VariableType::Break);
           }
         Leaf::Continue =>
           {
-            return VariableType::Continue;
+            return Ok(VariableType::Continue);
           }
         Leaf::BlockItem(nodes) =>
           {
@@ -340,7 +670,7 @@ impl Interpreter
               {
                 if let Some(lhs) = member.borrow().lhs()
                 {
-                  let identifier = self.identifier_name(lhs);
+                  let identifier = self.identifier_name(lhs)?;
                   struct_members.insert(identifier, value_type.clone());
                 }
               }
@@ -351,82 +681,122 @@ impl Interpreter
           {
             return self.struct_member_access(node);
           }
+
+        // 式文 (-x; や (a); など) は評価して値を捨てる
+        Leaf::Operator(_) | Leaf::UnaryExpression(_) | Leaf::ParenthesizedExpression |
+        Leaf::Identifier(_) | Leaf::Constant(_) | Leaf::StringLiteral(_) | Leaf::ArrayAccess =>
+          {
+            self.statement(node)?;
+          }
         _ => {
-          panic!("未対応のノードです : {:?}", val);
+          return Err(self.runtime_error(
+            RuntimeErrorKind::Unsupported(format!("未対応のノードです : {}", val)), &span));
         }
       }
     }
-    VariableType::Void
+    Ok(VariableType::Void)
   }
 
-  fn array_assignment(&mut self, node: &Rc<RefCell<Node>>) -> VariableType
+  /// 構造体定義のメンバ一覧を取得する
+  fn struct_definition(&self, name: &String, span: &Span)
+                       -> Result<HashMap<String, ValueType>, RuntimeError>
   {
-    // アクセスするindex を計算する
-    if let Some(Leaf::ArrayAssignment(index_root)) =
-      node.borrow().val().clone()
+    match self.struct_definition.get(name)
     {
-      let index = self.statement(index_root);
-      let index = match index
-      {
-        VariableType::Int(val) => val,
-        _ => panic!("Array index は整数で指定してください")
-      };
+      Some(members) => Ok(members.clone()),
+      None => Err(self.runtime_error(RuntimeErrorKind::UndefinedStruct(name.clone()), span)),
+    }
+  }
+
+  /// Resolver が決めた位置から変数を取得する
+  fn find_variable(&self, binding: Option<Binding>) -> Option<&Variable>
+  {
+    match binding?
+    {
+      Binding::Local { depth, slot } => self.local_variables.last()?.get(depth)?.get(slot),
+      Binding::Global(index) => self.global_variables.get(index)?.as_ref(),
+    }
+  }
+
+  fn find_variable_mut(&mut self, binding: Option<Binding>) -> Option<&mut Variable>
+  {
+    match binding?
+    {
+      Binding::Local { depth, slot } => self.local_variables.last_mut()?.get_mut(depth)?.get_mut(slot),
+      Binding::Global(index) => self.global_variables.get_mut(index)?.as_mut(),
+    }
+  }
+
+  /// 添字を評価し, 配列の範囲内にあることを確認する
+  fn array_index(&mut self, array: &Rc<RefCell<Array>>, index_root: &Rc<RefCell<Node>>)
+                 -> Result<usize, RuntimeError>
+  {
+    let span = index_root.borrow().span().clone();
+    let index = self.statement(index_root)?;
+    let index = match Self::promote(self.remove_return(index))
+    {
+      VariableType::Int(val) => val,
+      other => {
+        return Err(self.runtime_error(RuntimeErrorKind::NonIntegerIndex(other.to_string()), &span));
+      }
+    };
+
+    let length = array.borrow().values.len();
+    if index < 0 || index as usize >= length
+    {
+      let array = array.borrow().name.clone();
+      return Err(self.runtime_error(
+        RuntimeErrorKind::IndexOutOfBounds { array, index, length }, &span));
+    }
+
+    Ok(index as usize)
+  }
 
+  fn array_assignment(&mut self, node: &Rc<RefCell<Node>>) -> Result<VariableType, RuntimeError>
+  {
+    let span = node.borrow().span().clone();
+    if let Some(Leaf::ArrayAssignment(index_root)) =
+      node.borrow().val().cloned()
+    {
       // 左辺に識別子があり, 変数として登録されていることを確認する
       if let Some(lhs) = node.borrow().lhs()
       {
-        let identifier = self.identifier_name(lhs);
+        let identifier = self.identifier_name(lhs)?;
+        let array = match self.find_variable(lhs.borrow().binding())
+        {
+          Some(Variable::Array(array)) => array.clone(),
+          Some(_) => {
+            return Err(self.runtime_error(
+              RuntimeErrorKind::TypeMismatch(format!("{} は配列ではありません", identifier)), &span));
+          }
+          None => {
+            return Err(self.runtime_error(RuntimeErrorKind::UndefinedVariable(identifier), &span));
+          }
+        };
+
+        // アクセスするindex を計算する
+        let index = self.array_index(&array, &index_root)?;
 
         if let Some(rhs) = node.borrow().rhs()
         {
-          let value = self.statement(rhs);
-          // ローカル変数から検索
-          if let Some(local_variables) = self.local_variables.last_mut()
-          {
-            // 最後のスコープから検索
-            for local_variable in local_variables.iter_mut().rev()
-            {
-              if let Some(variable) = local_variable.get_mut(&identifier)
-              {
-                if let Variable::Array(array) = variable
-                {
-                  array.borrow_mut().values[index as usize] = value;
-                  return VariableType::Void;
-                }
-              }
-            }
-            // グローバル変数から検索
-            if let Some(variable) = self.global_variables.get_mut(&identifier)
-            {
-              if let Variable::Array(array) = variable
-              {
-                array.borrow_mut().values[index as usize] = value;
-              } else {
-                panic!("Global 変数が見つかりません : {}", identifier);
-              }
-            } else {
-              panic!("Global 変数が見つかりません : {}", identifier);
-            }
-          }
-        } else {
-          panic!("右辺に識別子がありません");
+          let value = self.statement(rhs)?;
+          let value = Self::store_value(&array.borrow().variable_type, self.remove_return(value));
+          array.borrow_mut().values[index] = value;
         }
-      } else {
-        panic!("左辺に識別子がありません");
       }
     }
 
-    VariableType::Void
+    Ok(VariableType::Void)
   }
 
-  fn for_statement(&mut self, node: &Rc<RefCell<Node>>) -> VariableType
+  fn for_statement(&mut self, node: &Rc<RefCell<Node>>) -> Result<VariableType, RuntimeError>
   {
     // for_statement を取得
     if let Some(Leaf::ForStatement(for_statement)) = node.borrow().val()
     {
       // 初期化式を取得
       let initializer = for_statement.initializer();
-      self.interpret_node(initializer);
+      self.interpret_node(initializer)?;
 
       // 条件式を取得
       let condition = for_statement.condition();
@@ -439,13 +809,13 @@ impl Interpreter
 
       loop {
         // 条件式を評価
-        let is_continue = self.condition(condition);
+        let is_continue = self.condition(condition)?;
         if !is_continue {
           break;
         }
 
         // for 文の中身を実行
-        let result = self.interpret_node(statement);
+        let result = self.interpret_node(statement)?;
 
         match result
         {
@@ -454,207 +824,173 @@ impl Interpreter
           }
           VariableType::Continue => {
             // 更新式を実行してから次のループへ
-            self.interpret_node(update);
+            self.interpret_node(update)?;
             continue;
           }
           VariableType::Return(return_val) => {
-            return VariableType::Return(return_val);
+            return Ok(VariableType::Return(return_val));
           }
           _ => {}
         }
 
         // 更新式を実行
-        self.interpret_node(update);
+        self.interpret_node(update)?;
       }
     }
 
-    VariableType::Void
+    Ok(VariableType::Void)
   }
 
 
-  fn while_statement(&mut self, node: &Rc<RefCell<Node>>) -> VariableType
+  fn while_statement(&mut self, node: &Rc<RefCell<Node>>) -> Result<VariableType, RuntimeError>
   {
     // while 文の条件式を取得
     if let Some(condition_root) = node.borrow().lhs()
     {
-      let mut condition = true;
-      condition = self.condition(condition_root);
-
       // condition != 0 の場合は while 文の中身を実行
-      while condition {
+      while self.condition(condition_root)? {
         if let Some(rhs) = node.borrow().rhs()
         {
-          if let Some(Leaf::BlockItem(nodes)) = rhs.borrow().val()
+          let result = self.interpret_node(rhs)?;
+          match result
           {
-            let result = self.compound_statement(nodes, true);
-            match result
-            {
-              VariableType::Break => {
-                break;
-              }
-              VariableType::Continue => {
-                // そのまま次のループへ (条件評価へ)
-                continue;
-              }
-              VariableType::Return(return_val) => {
-                return VariableType::Return(return_val);
-              }
-              _ => {}
+            VariableType::Break => {
+              break;
             }
+            VariableType::Continue => {
+              // そのまま次のループへ (条件評価へ)
+              continue;
+            }
+            VariableType::Return(return_val) => {
+              return Ok(VariableType::Return(return_val));
+            }
+            _ => {}
           }
         }
-        condition = self.condition(condition_root);
       }
     }
 
-    VariableType::Void
+    Ok(VariableType::Void)
   }
 
-  fn condition(&mut self, node: &Rc<RefCell<Node>>) -> bool
+  fn condition(&mut self, node: &Rc<RefCell<Node>>) -> Result<bool, RuntimeError>
   {
-    let condition = self.statement(node);
+    let condition = self.statement(node)?;
+    self.is_true(condition, node.borrow().span())
+  }
 
-    match condition
+  fn is_true(&self, value: VariableType, span: &Span) -> Result<bool, RuntimeError>
+  {
+    Self::truth_value(self.remove_return(value)).map_err(|kind| self.runtime_error(kind, span))
+  }
+
+  /// 値を真偽値として評価する. 0 以外は真
+  pub(crate) fn truth_value(value: VariableType) -> Result<bool, RuntimeErrorKind>
+  {
+    match Self::promote(value)
     {
       VariableType::Int(val) => {
-        val != 0
+        Ok(val != 0)
       }
       VariableType::Float(val) => {
-        val != 0.0
+        Ok(val != 0.0)
       }
-      _ => {
-        panic!("while 文の条件式が対応していません");
+      other => {
+        Err(RuntimeErrorKind::TypeMismatch(format!("条件式に {} は使用できません", other)))
       }
     }
   }
 
-  fn selection_statement(&mut self, node: &Rc<RefCell<Node>>) -> VariableType
+  fn selection_statement(&mut self, node: &Rc<RefCell<Node>>) -> Result<VariableType, RuntimeError>
   {
     // if 文の条件式を取得
     if let Some(Leaf::IfStatement(expression)) = node.borrow().val()
     {
-      let condition = self.statement(expression);
-
       // condition != 0 の場合は if 文の中身を実行
-      let mut condition_value: i32 = 0;
-      match condition
-      {
-        VariableType::Int(val) => condition_value = val,
-        VariableType::Float(val) => condition_value = val as i32,
-        _ => {
-          panic!("if 文の条件式が対応していません");
-        }
-      }
-
-      if condition_value != 0
+      if self.condition(expression)?
       {
         if let Some(lhs) = node.borrow().lhs()
         {
-          if let Some(Leaf::BlockItem(nodes)) = lhs.borrow().val()
-          {
-            return self.compound_statement(nodes, true);
-          }
-        }
-      } else {
-        if let Some(rhs) = node.borrow().rhs()
-        {
-          // else のときと, else if のとき
-          if let Some(Leaf::IfStatement(_)) = rhs.borrow().val()
-          {
-            return self.selection_statement(rhs);
-          } else {
-            if let Some(Leaf::BlockItem(nodes)) = rhs.borrow().val()
-            {
-              return self.compound_statement(nodes, true);
-            }
-          }
+          return self.interpret_node(lhs);
         }
+      } else if let Some(rhs) = node.borrow().rhs()
+      {
+        // else のときと, else if のとき
+        return self.interpret_node(rhs);
       }
-    } else {
-      panic!("if 文の条件式が取得できません");
     }
 
-    VariableType::Void
+    Ok(VariableType::Void)
   }
 
-  fn variable_assignment(&mut self, node: &Rc<RefCell<Node>>) -> VariableType
+  fn variable_assignment(&mut self, node: &Rc<RefCell<Node>>) -> Result<VariableType, RuntimeError>
   {
+    let span = node.borrow().span().clone();
     if let Some(lhs) = node.borrow().lhs()
     {
       if let Some(rhs) = node.borrow().rhs()
       {
-        let mut value = self.statement(rhs);
-        if let VariableType::Return(val) = value
-        {
-          value = val.as_ref().clone();
-        }
+        let value = self.statement(rhs)?;
+        let value = self.remove_return(value);
 
         if let Some(Leaf::Identifier(identifier)) = lhs.borrow().val()
         {
           // identifier への代入
-          // ローカル変数から検索
-          if let Some(local_variables) = self.local_variables.last_mut()
+          match self.find_variable_mut(lhs.borrow().binding())
           {
-            // 最後のスコープから検索
-            for local_variable in local_variables.iter_mut().rev()
-            {
-              if let Some(variable) = local_variable.get_mut(identifier)
-              {
-                if let Variable::Value(variable) = variable
-                {
-                  *variable = value;
-                  return VariableType::Void;
-                }
+            Some(Variable::Value(variable)) => {
+              *variable = Self::store_value(variable, value);
+            }
+            Some(Variable::Struct(variable)) => {
+              if let VariableType::Struct(s) = value {
+                *variable = s;
+              } else {
+                return Err(self.runtime_error(
+                  RuntimeErrorKind::TypeMismatch(format!("構造体 {} に {} は代入できません", identifier, value)),
+                  &span));
               }
             }
-          }
-
-          // グローバル変数から検索
-          if let Some(variable) = self.global_variables.get_mut(identifier)
-          {
-            if let Variable::Value(variable) = variable
-            {
-              *variable = value;
-              return VariableType::Void;
+            Some(Variable::Array(_)) => {
+              return Err(self.runtime_error(
+                RuntimeErrorKind::TypeMismatch(format!("配列 {} には代入できません", identifier)), &span));
+            }
+            None => {
+              return Err(self.runtime_error(
+                RuntimeErrorKind::UndefinedVariable(identifier.clone()), &span));
             }
           }
         } else if let Some(Leaf::StructMemberAccess) = lhs.borrow().val() {
           // 構造体メンバへの代入
           if let Some(struct_node) = lhs.borrow().lhs() {
-            // 構造体ノードが識別子の場合だけでなく、より複雑な後置修飾式を評価できるように修正
-            let struct_val = self.statement(struct_node);
-            let mut struct_identifier: Option<String> = None;
-
-            // もし識別子なら、元の変数を特定する
-            if let Some(Leaf::Identifier(name)) = struct_node.borrow().val() {
-              struct_identifier = Some(name.clone());
-            }
-
             if let Some(member_node) = lhs.borrow().rhs() {
-              if let Some(Leaf::Identifier(member_name)) = member_node.borrow().val() {
-                // もし構造体が識別子なら、元の変数を更新する
-                if let Some(id) = struct_identifier {
-                  // ローカル変数から検索
-                  if let Some(local_variables) = self.local_variables.last_mut() {
-                    for local_variable in local_variables.iter_mut().rev() {
-                      if let Some(variable) = local_variable.get_mut(&id) {
-                        if let Variable::Struct(s) = variable {
-                          s.members.insert(member_name.clone(), value);
-                          return VariableType::Void;
-                        }
-                      }
-                    }
-                  }
-                  // グローバル変数から検索
-                  if let Some(variable) = self.global_variables.get_mut(&id) {
-                    if let Variable::Struct(s) = variable {
-                      s.members.insert(member_name.clone(), value);
-                      return VariableType::Void;
-                    }
-                  }
-                } else {
-                    // 識別子でない場合は、今のところ代入をサポートしない（例：(p1).x = 10 など）
-                    panic!("構造体への代入に失敗しました：左辺が識別子ではありません");
+              let member_name = self.identifier_name(member_node)?;
+
+              // 識別子でない場合は、今のところ代入をサポートしない（例：(p1).x = 10 など）
+              let id = match struct_node.borrow().val() {
+                Some(Leaf::Identifier(name)) => name.clone(),
+                _ => {
+                  return Err(self.runtime_error(
+                    RuntimeErrorKind::Unsupported(
+                      "構造体への代入に失敗しました：左辺が識別子ではありません".to_string()),
+                    &span));
+                }
+              };
+
+              match self.find_variable_mut(struct_node.borrow().binding()) {
+                Some(Variable::Struct(s)) => {
+                  let value = match s.members.get(&member_name)
+                  {
+                    Some(member) => Self::store_value(member, value),
+                    None => value,
+                  };
+                  s.members.insert(member_name, value);
+                }
+                Some(_) => {
+                  return Err(self.runtime_error(
+                    RuntimeErrorKind::TypeMismatch(format!("{} は構造体ではありません", id)), &span));
+                }
+                None => {
+                  return Err(self.runtime_error(RuntimeErrorKind::UndefinedVariable(id), &span));
                 }
               }
             }
@@ -663,124 +999,150 @@ impl Interpreter
       }
     }
 
-    VariableType::Void
+    Ok(VariableType::Void)
   }
 
-  fn array_variable_definition(&mut self, value_type: &ValueType, identifier: String, size: usize)
+  fn array_variable_definition(&mut self, value_type: &ValueType, identifier: String,
+                               binding: Option<Binding>, size: usize, initializer: Option<String>,
+                               span: &Span) -> Result<(), RuntimeError>
   {
-    let mut values = Vec::new();
-    for _ in 0..size
+    // 要素の初期値. 配列の型としても使う
+    let element = match value_type
     {
-      match value_type
-      {
-        ValueType::Int =>
-          {
-            values.push(VariableType::Int(0));
-          }
-        ValueType::Float =>
-          {
-            values.push(VariableType::Float(0.0));
-          }
-        ValueType::Struct(_) =>
-          {
-            panic!("構造体の配列は未対応です");
-          }
-        ValueType::Void => {
-          panic!("void の配列は定義できません");
+      ValueType::Int =>
+        {
+          VariableType::Int(0)
         }
-        ValueType::Array(_, _) =>
-          {
-            panic!("多次元配列は未対応です");
-          }
+      ValueType::Float =>
+        {
+          VariableType::Float(0.0)
+        }
+      ValueType::Char =>
+        {
+          VariableType::Char(0)
+        }
+      ValueType::Struct(_) =>
+        {
+          return Err(self.runtime_error(
+            RuntimeErrorKind::Unsupported("構造体の配列は未対応です".to_string()), span));
+        }
+      ValueType::Void => {
+        return Err(self.runtime_error(
+          RuntimeErrorKind::TypeMismatch("void の配列は定義できません".to_string()), span));
       }
-    }
+      ValueType::Array(_, _) =>
+        {
+          return Err(self.runtime_error(
+            RuntimeErrorKind::Unsupported("多次元配列は未対応です".to_string()), span));
+        }
+    };
+
+    let values = match initializer
+    {
+      Some(string) => Self::string_values(&string, size),
+      None => vec![element.clone(); size],
+    };
 
-    let array = Array::new(identifier.clone(), VariableType::Int(0), values);
-    self.insert_variable(identifier, Variable::Array(Rc::new(RefCell::new(array))));
+    let array = Array::new(identifier.clone(), element, values);
+    self.insert_variable(binding, Variable::Array(Rc::new(RefCell::new(array))));
+    Ok(())
   }
 
-  fn variable_definition(&mut self, value_type: &ValueType, identifier: String, value: VariableType)
+  fn variable_definition(&mut self, value_type: &ValueType, identifier: String,
+                         binding: Option<Binding>, value: VariableType, span: &Span) -> Result<(), RuntimeError>
   {
-    match value_type
+    match (value_type, value)
     {
-      ValueType::Int =>
+      (ValueType::Int, VariableType::Float(val)) =>
         {
-          match value
-          {
-            VariableType::Float(val) =>
-              {
-                self.insert_variable(identifier, Variable::Value(VariableType::Int(val as i32)));
-              }
-            VariableType::Int(val) =>
-              {
-                self.insert_variable(identifier, Variable::Value(VariableType::Int(val)));
-              }
-            _ => {
-              panic!("未対応の型です : {:?}", value);
-            }
-          }
+          self.insert_variable(binding, Variable::Value(VariableType::Int(val as i32)));
         }
-      ValueType::Float =>
+      (ValueType::Int, VariableType::Int(val)) =>
         {
-          match value
-          {
-            VariableType::Float(val) =>
-              {
-                self.insert_variable(identifier, Variable::Value(VariableType::Float(val)));
-              }
-            VariableType::Int(val) =>
-              {
-                self.insert_variable(identifier, Variable::Value(VariableType::Float(val as f64)));
-              }
-            _ => {
-              panic!("未対応の型です : {:?}", value);
-            }
-          }
+          self.insert_variable(binding, Variable::Value(VariableType::Int(val)));
         }
-      ValueType::Struct(_) =>
+      (ValueType::Float, VariableType::Float(val)) =>
         {
-          panic!("構造体の代入による初期化は未対応です");
+          self.insert_variable(binding, Variable::Value(VariableType::Float(val)));
         }
-      ValueType::Void => {
-        panic!("void 型の変数は定義できません");
+      (ValueType::Float, VariableType::Int(val)) =>
+        {
+          self.insert_variable(binding, Variable::Value(VariableType::Float(val as f64)));
+        }
+      (ValueType::Int, VariableType::Char(val)) =>
+        {
+          self.insert_variable(binding, Variable::Value(VariableType::Int(val as i32)));
+        }
+      (ValueType::Float, VariableType::Char(val)) =>
+        {
+          self.insert_variable(binding, Variable::Value(VariableType::Float(val as f64)));
+        }
+      (ValueType::Char, value @ (VariableType::Int(_) | VariableType::Float(_) | VariableType::Char(_))) =>
+        {
+          self.insert_variable(binding, Variable::Value(Self::store_as(value_type, value)));
+        }
+      (ValueType::Struct(_), _) =>
+        {
+          return Err(self.runtime_error(
+            RuntimeErrorKind::Unsupported("構造体の代入による初期化は未対応です".to_string()), span));
+        }
+      (ValueType::Void, _) => {
+        return Err(self.runtime_error(
+          RuntimeErrorKind::TypeMismatch("void 型の変数は定義できません".to_string()), span));
       }
-      ValueType::Array(_, _) =>
+      (ValueType::Array(_, _), _) =>
         {
-          panic!("配列の代入による初期化は未対応です");
+          return Err(self.runtime_error(
+            RuntimeErrorKind::Unsupported("配列の代入による初期化は未対応です".to_string()), span));
         }
+      (_, value) => {
+        return Err(self.runtime_error(
+          RuntimeErrorKind::TypeMismatch(format!("{} に {} は代入できません", identifier, value)), span));
+      }
     }
+
+    Ok(())
   }
 
-  fn insert_variable_int(&mut self, identifier: String, value: i32)
+  fn insert_variable_int(&mut self, binding: Option<Binding>, value: i32)
   {
-    self.insert_variable(identifier, Variable::Value(VariableType::Int(value)));
+    self.insert_variable(binding, Variable::Value(VariableType::Int(value)));
   }
 
-  fn insert_variable_float(&mut self, identifier: String, value: f64)
+  fn insert_variable_float(&mut self, binding: Option<Binding>, value: f64)
   {
-    self.insert_variable(identifier, Variable::Value(VariableType::Float(value)));
+    self.insert_variable(binding, Variable::Value(VariableType::Float(value)));
   }
 
-  fn insert_variable(&mut self, identifier: String, value: Variable)
+  /// Resolver が決めた位置に変数を置く
+  fn insert_variable(&mut self, binding: Option<Binding>, value: Variable)
   {
-    match self.scope
+    match binding
     {
-      Scope::Global =>
+      Some(Binding::Global(index)) =>
         {
-          self.global_variables.insert(identifier, value);
+          self.global_variables[index] = Some(value);
         }
-      Scope::Local =>
+      Some(Binding::Local { depth, slot }) =>
         {
-          if let Some(local_variables) = self.local_variables.last_mut()
+          if let Some(scope) = self.local_variables.last_mut().and_then(|scopes| scopes.get_mut(depth))
           {
-            local_variables.last_mut().unwrap().insert(identifier, value);
+            if slot < scope.len()
+            {
+              scope[slot] = value;
+            } else {
+              scope.resize(slot, Variable::Value(VariableType::Void));
+              scope.push(value);
+            }
           }
         }
+      None => {}
     }
   }
 
-  fn statement(&mut self, node: &Rc<RefCell<Node>>) -> VariableType
+  fn statement(&mut self, node: &Rc<RefCell<Node>>) -> Result<VariableType, RuntimeError>
   {
+    let span = node.borrow().span().clone();
     if let Some(val) = node.borrow().val()
     {
       match val
@@ -791,20 +1153,28 @@ impl Interpreter
             if let Some((lhs, rhs))
               = node.borrow().get_lhs_and_rhs()
             {
-              return self.operator(op, lhs, rhs);
+              return self.operator(op, lhs, rhs, &span);
             }
           }
 
         // 定数
         Leaf::Constant(value) =>
           {
-            return self.constant(value);
+            return Ok(Self::constant(value));
+          }
+
+        // 文字列は '\0' で終わる char の配列になる
+        Leaf::StringLiteral(string) =>
+          {
+            let values = Self::string_values(string, string.len() + 1);
+            let array = Array::new(format!("{:?}", string), VariableType::Char(0), values);
+            return Ok(VariableType::Array(Rc::new(RefCell::new(array))));
           }
 
         // 識別子
         Leaf::Identifier(identifier) =>
           {
-            return self.identifier(identifier);
+            return self.identifier(identifier, node.borrow().binding(), &span);
           }
 
         // 単項演算子
@@ -812,7 +1182,7 @@ impl Interpreter
           {
             if let Some(lhs) = node.borrow().lhs()
             {
-              return self.unary_expression(op, lhs);
+              return self.unary_expression(op, lhs, &span);
             }
           }
 
@@ -826,15 +1196,16 @@ impl Interpreter
           }
         Leaf::FunctionCall(function_call) =>
           {
-            let value = self.function_call(function_call);
+            let value = self.function_call(function_call, Some(span.clone()))?;
 
             // statement で void の場合はエラー
-            if let VariableType::Void = value
+            if let VariableType::Void = self.remove_return(value.clone())
             {
-              panic!("void は代入できません");
+              return Err(self.runtime_error(
+                RuntimeErrorKind::VoidValue(function_call.name().clone()), &span));
             }
 
-            return value;
+            return Ok(self.remove_return(value));
           }
         Leaf::ArrayAccess =>
           {
@@ -844,201 +1215,310 @@ impl Interpreter
           {
             return self.struct_member_access(node);
           }
-        _ => {
-          panic!("未対応のノードです : {:?}", val);
-        }
+        _ => {}
       }
     }
 
-    panic!("未対応のノードです");
+    Err(self.runtime_error(
+      RuntimeErrorKind::Unsupported(format!("未対応のノードです : {:?}", node.borrow().val())), &span))
   }
 
-  fn array_access(&mut self, node: &Rc<RefCell<Node>>) -> VariableType
+  fn array_access(&mut self, node: &Rc<RefCell<Node>>) -> Result<VariableType, RuntimeError>
   {
-    // ArrayAccess を取得
-    if let Some(Leaf::ArrayAccess) = node.borrow().val()
-    {}
-    else {
-      panic!("ArrayAccess が取得できません");
-    }
+    let span = node.borrow().span().clone();
 
     // 左辺に配列（または式）があることを確認する
-    if let Some(lhs) = node.borrow().lhs()
+    let lhs = match node.borrow().lhs()
     {
-      let mut array_val = self.statement(lhs);
-      // Return の場合は中身を取り出す
-      if let VariableType::Return(inner) = array_val {
-        array_val = inner.as_ref().clone();
+      Some(lhs) => lhs.clone(),
+      None => {
+        return Err(self.runtime_error(
+          RuntimeErrorKind::Unsupported("左辺に配列がありません".to_string()), &span));
       }
+    };
 
-      if let VariableType::Array(array) = array_val {
-        // 右辺に index があることを確認する
-        if let Some(rhs) = node.borrow().rhs()
-        {
-          let index = self.statement(rhs);
-          let index = match index
-          {
-            VariableType::Int(val) => val,
-            _ => panic!("Array index は整数で指定してください")
-          };
-          return array.borrow().values[index as usize].clone();
-        } else {
-          panic!("右辺に index がありません");
-        }
-      } else {
-        panic!("配列ではありません : {:?}", array_val);
+    let array_val = self.statement(&lhs)?;
+    // Return の場合は中身を取り出す
+    let array = match self.remove_return(array_val)
+    {
+      VariableType::Array(array) => array,
+      other => {
+        return Err(self.runtime_error(
+          RuntimeErrorKind::TypeMismatch(format!("配列ではありません : {}", other)), &span));
       }
-    } else {
-      panic!("左辺に配列がありません");
-    }
+    };
+
+    // 右辺に index があることを確認する
+    let rhs = match node.borrow().rhs()
+    {
+      Some(rhs) => rhs.clone(),
+      None => {
+        return Err(self.runtime_error(
+          RuntimeErrorKind::Unsupported("右辺に index がありません".to_string()), &span));
+      }
+    };
+
+    let index = self.array_index(&array, &rhs)?;
+    let value = array.borrow().values[index].clone();
+    Ok(value)
   }
 
-  fn struct_member_access(&mut self, node: &Rc<RefCell<Node>>) -> VariableType
+  fn struct_member_access(&mut self, node: &Rc<RefCell<Node>>) -> Result<VariableType, RuntimeError>
   {
-    // StructMemberAccess を取得
-    if let Some(Leaf::StructMemberAccess) = node.borrow().val()
-    {}
-    else {
-      panic!("StructMemberAccess が取得できません");
-    }
+    let span = node.borrow().span().clone();
 
     // 左辺に構造体があることを確認する
     if let Some(lhs) = node.borrow().lhs()
     {
-      let mut struct_val = self.statement(lhs);
+      let struct_val = self.statement(lhs)?;
       // struct_val が Return の場合は中身を取り出す
-      if let VariableType::Return(inner) = struct_val {
-          struct_val = inner.as_ref().clone();
-      }
+      let s = match self.remove_return(struct_val)
+      {
+        VariableType::Struct(s) => s,
+        other => {
+          return Err(self.runtime_error(
+            RuntimeErrorKind::TypeMismatch(format!("構造体ではありません : {}", other)), &span));
+        }
+      };
 
-      if let VariableType::Struct(s) = struct_val {
-          // 右辺にメンバ名があることを確認する
-          if let Some(rhs) = node.borrow().rhs()
-          {
-            if let Some(Leaf::Identifier(member_name)) = rhs.borrow().val()
-            {
-              return s.members.get(member_name).expect(&format!("メンバ {} が見つかりません", member_name)).clone();
-            }
-          }
-      } else {
-          panic!("構造体ではありません : {:?}", struct_val);
+      // 右辺にメンバ名があることを確認する
+      if let Some(rhs) = node.borrow().rhs()
+      {
+        let member_name = self.identifier_name(rhs)?;
+        return match s.members.get(&member_name)
+        {
+          Some(member) => Ok(member.clone()),
+          None => Err(self.runtime_error(RuntimeErrorKind::UndefinedMember(member_name), &span)),
+        };
       }
     }
 
-    panic!("構造体メンバアクセスに失敗しました");
+    Err(self.runtime_error(
+      RuntimeErrorKind::Unsupported("構造体メンバアクセスに失敗しました".to_string()), &span))
   }
 
-  fn function_call(&mut self, function_call: &FunctionCall) -> VariableType
+  /// 関数を呼び出す. call_site は呼び出し位置で, main の場合は None
+  fn function_call(&mut self, function_call: &FunctionCall, call_site: Option<Span>)
+                   -> Result<VariableType, RuntimeError>
   {
     let name = function_call.name();
-    let function_definitions = self.function_definition.clone();
+    let span = call_site.as_ref();
     //println!("function_call : {}", name);
 
-    if let Some(function_definition) = function_definitions.get(name)
+    let function_definition = match self.function_definition.get(name)
     {
-      let mut new_variables: HashMap<String, Variable> = HashMap::new();
+      Some(function_definition) => function_definition.clone(),
+      // 同名の関数が定義されていなければホストの関数, 組み込み関数の順に探す
+      None if self.native_functions.contains_key(name) => {
+        return self.native_call(function_call, call_site);
+      }
+      None if builtin::is_builtin(name) => {
+        return self.builtin_call(function_call, span);
+      }
+      None => {
+        return Err(self.runtime_error_at(RuntimeErrorKind::UndefinedFunction(name.clone()), span));
+      }
+    };
 
-      // 引数がある場合計算する
-      let function_arguments = function_call.arguments();
+    // 引数の数と function-definition の引数リストの数が一致することを確認する
+    let function_arguments = function_call.arguments();
+    self.check_argument_count(name, function_definition.arguments().len(), function_arguments.len(), span)?;
 
-      // 引数がある場合は引数を計算してローカル変数に追加
-      if !function_arguments.is_empty()
-      {
-        // 引数の数と function-definition の引数リストの数が一致することを確認する
-        if function_arguments.len() != function_definition.arguments().len()
-        {
-          panic!("引数の数が一致しません");
-        }
+    // 引数を計算してローカル変数に追加
+    let mut new_variables: Vec<Variable> = Vec::new();
+    for (argument, parameter) in function_arguments.iter().zip(function_definition.arguments())
+    {
+      let argument_value = self.statement(argument)?;
+      // value が 定数ではなく Return の時中身を取り出す
+      let argument_value = self.remove_return(argument_value);
+      new_variables.push(self.bind_argument(name, parameter, argument_value, Some(argument.borrow().span()))?);
+    }
 
-        for (i, argument) in function_arguments.iter().enumerate()
-        {
-          let mut argument_value = self.statement(argument);
-          let argument_name = function_definition.arguments()[i].identify().clone();
-          let argument_type = function_definition.arguments()[i].type_specifier();
+    self.call_definition(name, &function_definition, new_variables, call_site)
+  }
 
-          // 引数をローカル変数に追加
-          if let Some(local_variables) = self.local_variables.last_mut()
-          {
-            match argument_type
-            {
-              ValueType::Array(_, _) =>
-                {
-                  if let VariableType::Array(array) = argument_value
-                  {
-                    new_variables.insert(argument_name, Variable::Array(array));
-                  } else {
-                    panic!("配列が期待されていますが、別の型が渡されました");
-                  }
-                }
-              ValueType::Struct(_) =>
-                {
-                  if let VariableType::Struct(s) = argument_value
-                  {
-                    // 構造体の値渡し（コピー）
-                    new_variables.insert(argument_name, Variable::Struct(s.clone()));
-                  } else {
-                    panic!("構造体が期待されていますが、別の型が渡されました");
-                  }
-                }
-              _ =>
-                {
-                  // value が 定数ではなく Return の時中身を取り出す
-                  if let VariableType::Return(val) = argument_value
-                  {
-                    argument_value = val.as_ref().clone();
-                  }
-                  new_variables.insert(argument_name, Variable::Value(argument_value));
-                }
-            }
-          }
-        }
-      }
+  fn check_argument_count(&self, name: &str, expected: usize, found: usize, span: Option<&Span>)
+                          -> Result<(), RuntimeError>
+  {
+    if expected != found
+    {
+      return Err(self.runtime_error_at(RuntimeErrorKind::ArgumentCountMismatch {
+        function: name.to_string(),
+        expected,
+        found,
+      }, span));
+    }
+    Ok(())
+  }
 
-      // 新しくローカル変数を追加
-      self.local_variables.push(Vec::new());
-      self.local_variables.last_mut().unwrap().push(new_variables);
+  /// 引数の値を仮引数の型に合わせてローカル変数にする
+  fn bind_argument(&self, name: &str, parameter: &Argument, value: VariableType, span: Option<&Span>)
+                   -> Result<Variable, RuntimeError>
+  {
+    match (parameter.type_specifier(), value)
+    {
+      (ValueType::Array(_, _), VariableType::Array(array)) => Ok(Variable::Array(array)),
+      // 構造体の値渡し（コピー）
+      (ValueType::Struct(_), VariableType::Struct(s)) => Ok(Variable::Struct(s)),
+      (ValueType::Array(_, _), _) | (ValueType::Struct(_), _) |
+      (_, VariableType::Array(_)) | (_, VariableType::Struct(_)) =>
+        {
+          Err(self.runtime_error_at(
+            RuntimeErrorKind::TypeMismatch(
+              format!("{} の引数 {} に渡された値の型が一致しません", name, parameter.identify())),
+            span))
+        }
+      (value_type, value) => Ok(Variable::Value(Self::store_as(value_type, value))),
+    }
+  }
 
-      let return_value = self.compound_statement(function_definition.body(),
-                                                 false);
+  /// 引数を束縛済みのユーザ定義関数を実行する
+  fn call_definition(&mut self, name: &str, function_definition: &FunctionDefinition,
+                     new_variables: Vec<Variable>, call_site: Option<Span>) -> Result<VariableType, RuntimeError>
+  {
+    let span = call_site.clone();
 
-      // ローカル変数を削除
-      self.local_variables.pop();
+    // 新しくローカル変数を追加
+    self.call_stack.push(StackFrame::new(name.to_string(), call_site));
+    self.local_variables.push(Vec::new());
+    self.local_variables.last_mut().unwrap().push(new_variables);
 
+    let return_value = match self.compound_statement(function_definition.body(), false)
+    {
       // return が Break の場合は エラー
-      if let VariableType::Break = return_value
-      {
-        panic!("関数内で break は使用できません");
+      Ok(VariableType::Break) | Ok(VariableType::Continue) => {
+        Err(self.runtime_error_at(RuntimeErrorKind::BreakOutsideLoop, span.as_ref()))
       }
+      return_value => return_value,
+    };
 
-      if let VariableType::Return(val) = return_value
-      {
-        return VariableType::Return(val);
-      }
+    // ローカル変数を削除
+    self.local_variables.pop();
+    self.call_stack.pop();
 
-      return_value
-    } else {
-      panic!("関数が見つかりません : {}", name);
+    return_value
+  }
+
+  fn native_call(&mut self, function_call: &FunctionCall, call_site: Option<Span>)
+                 -> Result<VariableType, RuntimeError>
+  {
+    let name = function_call.name();
+    let (arity, function) = self.native_functions[name].clone();
+    self.check_argument_count(name, arity, function_call.arguments().len(), call_site.as_ref())?;
+
+    let mut arguments = Vec::new();
+    for argument in function_call.arguments().iter()
+    {
+      let argument_value = self.statement(argument)?;
+      arguments.push(self.remove_return(argument_value));
+    }
+
+    self.call_native(name, function, &arguments, call_site)
+  }
+
+  fn call_native(&mut self, name: &str, function: NativeFunction, arguments: &[VariableType],
+                 call_site: Option<Span>) -> Result<VariableType, RuntimeError>
+  {
+    // ホストが返したエラーはそのまま返し, 呼び出し履歴だけ外側に付け足す
+    self.call_stack.push(StackFrame::new(name.to_string(), call_site));
+    let return_value = function(arguments).map_err(|error| error.called_from(self.call_stack.clone()));
+    self.call_stack.pop();
+
+    return_value
+  }
+
+  fn builtin_call(&mut self, function_call: &FunctionCall, span: Option<&Span>) -> Result<VariableType, RuntimeError>
+  {
+    let mut arguments = Vec::new();
+    for argument in function_call.arguments().iter()
+    {
+      let argument_value = self.statement(argument)?;
+      arguments.push(self.remove_return(argument_value));
+    }
+
+    if function_call.name() != "scanf"
+    {
+      return builtin::call(function_call.name(), &arguments, self.input.as_mut(), self.output.as_mut())
+        .map_err(|kind| self.runtime_error_at(kind, span));
+    }
+
+    // scanf は読み取った値を 2 番目以降の引数の変数に代入する
+    let values = match builtin::scanf(&arguments, self.input.as_mut())
+    {
+      Ok(Some(values)) => values,
+      Ok(None) => return Ok(VariableType::Int(-1)),
+      Err(kind) => return Err(self.runtime_error_at(kind, span)),
+    };
+    let count = values.len() as i32;
+    for (target, value) in function_call.arguments()[1..].iter().zip(values)
+    {
+      self.store_to(target, value)?;
+    }
+    Ok(VariableType::Int(count))
+  }
+
+  /// 変数か配列の要素に値を代入する
+  fn store_to(&mut self, target: &Rc<RefCell<Node>>, value: VariableType) -> Result<(), RuntimeError>
+  {
+    let span = target.borrow().span().clone();
+    let leaf = target.borrow().val().cloned();
+    match leaf
+    {
+      Some(Leaf::Identifier(identifier)) =>
+        {
+          match self.find_variable_mut(target.borrow().binding())
+          {
+            Some(Variable::Value(variable)) => {
+              *variable = Self::store_value(variable, value);
+              Ok(())
+            }
+            Some(_) => Err(self.runtime_error(
+              RuntimeErrorKind::TypeMismatch(format!("{} には代入できません", identifier)), &span)),
+            None => Err(self.runtime_error(RuntimeErrorKind::UndefinedVariable(identifier), &span)),
+          }
+        }
+      Some(Leaf::ArrayAccess) =>
+        {
+          let (Some(lhs), Some(rhs)) = (target.borrow().lhs().cloned(), target.borrow().rhs().cloned()) else {
+            return Err(self.runtime_error(
+              RuntimeErrorKind::Unsupported("配列の要素ではありません".to_string()), &span));
+          };
+          let array = match self.statement(&lhs)?
+          {
+            VariableType::Array(array) => array,
+            other => {
+              return Err(self.runtime_error(
+                RuntimeErrorKind::TypeMismatch(format!("配列ではありません : {}", other)), &span));
+            }
+          };
+          let index = self.array_index(&array, &rhs)?;
+          let value = Self::store_value(&array.borrow().variable_type, value);
+          array.borrow_mut().values[index] = value;
+          Ok(())
+        }
+      _ => Err(self.runtime_error(
+        RuntimeErrorKind::Unsupported("scanf の引数には変数か配列の要素を指定してください".to_string()), &span)),
     }
   }
 
   fn compound_statement(&mut self, nodes: &Vec<Rc<RefCell<Node>>>,
-                        is_generate_local_variables: bool) -> VariableType
+                        is_generate_local_variables: bool) -> Result<VariableType, RuntimeError>
   {
     if is_generate_local_variables
     {
       if let Some(local_variables) = self.local_variables.last_mut()
       {
-        local_variables.push(HashMap::new());
+        local_variables.push(Vec::new());
       }
     }
 
-    let mut return_value = VariableType::Void;
+    let mut return_value = Ok(VariableType::Void);
     for statement in nodes.iter()
     {
       return_value = self.interpret_node(statement);
       match return_value
       {
-        VariableType::Return(_) | VariableType::Break | VariableType::Continue => {
+        Ok(VariableType::Return(_)) | Ok(VariableType::Break) | Ok(VariableType::Continue) | Err(_) => {
           break;
         }
         _ => {}
@@ -1053,87 +1533,58 @@ impl Interpreter
       }
     }
 
-    return_value
+    return_value
+  }
+
+  fn unary_expression(&mut self, op: &UnaryOperator, lhs: &Rc<RefCell<Node>>, span: &Span)
+                      -> Result<VariableType, RuntimeError>
+  {
+    let lhs = self.statement(lhs)?;
+    let lhs = self.remove_return(lhs);
+    Self::unary_operator(op, lhs).map_err(|kind| self.runtime_error(kind, span))
   }
 
-  fn unary_expression(&mut self, op: &UnaryOperator, lhs: &Rc<RefCell<Node>>) -> VariableType
+  /// 単項演算子を計算する
+  pub(crate) fn unary_operator(op: &UnaryOperator, value: VariableType)
+                               -> Result<VariableType, RuntimeErrorKind>
   {
-    let lhs = self.statement(lhs);
-    match op
+    match (op, Self::promote(value))
     {
-      UnaryOperator::Minus =>
+      (UnaryOperator::Minus, VariableType::Int(val)) =>
         {
-          match lhs
-          {
-            VariableType::Int(val) =>
-              {
-                Int(-val)
-              }
-            VariableType::Float(val) =>
-              {
-                VariableType::Float(-val)
-              }
-            _ => {
-              panic!("未対応の型です");
-            }
-          }
+          Ok(Int(val.wrapping_neg()))
         }
-      UnaryOperator::LogicalNot =>
+      (UnaryOperator::Minus, VariableType::Float(val)) =>
         {
-          match lhs
-          {
-            VariableType::Int(val) =>
-              {
-                Int(if val == 0 { 1 } else { 0 })
-              }
-            VariableType::Float(val) =>
-              {
-                Int(if val == 0.0 { 1 } else { 0 })
-              }
-            _ => {
-              panic!("未対応の型です");
-            }
-          }
+          Ok(VariableType::Float(-val))
+        }
+      (UnaryOperator::LogicalNot, VariableType::Int(val)) =>
+        {
+          Ok(Int(if val == 0 { 1 } else { 0 }))
+        }
+      (UnaryOperator::LogicalNot, VariableType::Float(val)) =>
+        {
+          Ok(Int(if val == 0.0 { 1 } else { 0 }))
         }
-      _ => {
-        panic!("未対応の演算子です : {:?}", op);
+      (op, value) => {
+        Err(RuntimeErrorKind::TypeMismatch(format!("{:?} {}", op, value)))
       }
     }
   }
 
-  fn identifier(&mut self, identifier: &String) -> VariableType
+  fn identifier(&mut self, identifier: &str, binding: Option<Binding>, span: &Span)
+                -> Result<VariableType, RuntimeError>
   {
-    // ローカル変数から検索.最後のスコープから検索する
-    if let Some(local_variables) = self.local_variables.last_mut()
+    match self.find_variable(binding)
     {
-      for variable in local_variables.iter().rev()
-      {
-        if let Some(variable) = variable.get(identifier)
-        {
-          match variable
-          {
-            Variable::Value(value) => return value.clone(),
-            Variable::Array(array) => return VariableType::Array(array.clone()),
-            Variable::Struct(s) => return VariableType::Struct(s.clone()),
-          }
-        }
-      }
-    }
-
-    // グローバル変数から検索
-    if let Some(variable) = self.global_variables.get(identifier) {
-      match variable
-      {
-        Variable::Value(value) => return value.clone(),
-        Variable::Array(array) => return VariableType::Array(array.clone()),
-        Variable::Struct(s) => return VariableType::Struct(s.clone()),
-      }
+      Some(Variable::Value(value)) => Ok(value.clone()),
+      Some(Variable::Array(array)) => Ok(VariableType::Array(array.clone())),
+      Some(Variable::Struct(s)) => Ok(VariableType::Struct(s.clone())),
+      None => Err(self.runtime_error(RuntimeErrorKind::UndefinedVariable(identifier.to_string()), span)),
     }
-
-    panic!("未定義の変数です : {}", identifier);
   }
 
-  fn constant(&mut self, value: &Constant) -> VariableType
+  fn constant(value: &Constant) -> VariableType
   {
     match value
     {
@@ -1145,515 +1596,466 @@ impl Interpreter
         {
           VariableType::Float((*val).into())
         }
-      _ => {
-        panic!("未対応の定数です : {:?}", value);
-      }
+      Constant::Char(val) =>
+        {
+          VariableType::Char(*val)
+        }
     }
   }
 
 
-  fn operator(&mut self, op: &Operator, lhs: &Rc<RefCell<Node>>, rhs: &Rc<RefCell<Node>>) -> VariableType
+  fn operator(&mut self, op: &Operator, lhs: &Rc<RefCell<Node>>, rhs: &Rc<RefCell<Node>>,
+              span: &Span) -> Result<VariableType, RuntimeError>
   {
-    let mut lhs = self.statement(lhs);
-    let mut rhs = self.statement(rhs);
-    let mut result: VariableType = Int(0);
+    // '&&' と '||' は左辺の値で結果が決まる場合は右辺を評価しない
+    if let Operator::LogicalAnd | Operator::LogicalOr = op
+    {
+      return self.logical_operator(op, lhs, rhs);
+    }
+
+    let lhs = self.statement(lhs)?;
+    let rhs = self.statement(rhs)?;
 
     // 左右の値からreturn を除去する
-    lhs = self.remove_return(lhs);
-    rhs = self.remove_return(rhs);
+    let lhs = self.remove_return(lhs);
+    let rhs = self.remove_return(rhs);
+
+    let result = Self::binary_operator(op, lhs, rhs);
+    result.map_err(|kind| self.runtime_error(kind, span))
+  }
 
+  /// 二項演算子を計算する. '&&' と '||' は短絡評価のため呼び出し側で処理する
+  pub(crate) fn binary_operator(op: &Operator, lhs: VariableType, rhs: VariableType)
+                                -> Result<VariableType, RuntimeErrorKind>
+  {
+    let lhs = Self::promote(lhs);
+    let rhs = Self::promote(rhs);
     match op
     {
-      Operator::LogicalOr =>
-        {
-          result = self.logical_or(lhs, rhs);
-        }
-      Operator::LogicalAnd =>
-        {
-          result = self.logical_and(lhs, rhs);
-        }
-      Operator::Equal =>
-        {
-          result = self.equal(lhs, rhs);
-        }
-      Operator::NotEqual =>
-        {
-          result = self.not_equal(lhs, rhs);
-        }
-      Operator::LessThan =>
-        {
-          result = self.less_than(lhs, rhs);
-        }
-      Operator::GreaterThan =>
-        {
-          result = self.greater_than(lhs, rhs);
-        }
-      Operator::LessThanOrEqual =>
-        {
-          result = self.less_than_or_equal(lhs, rhs);
-        }
-      Operator::GreaterThanOrEqual =>
-        {
-          result = self.greater_than_or_equal(lhs, rhs);
-        }
-      Operator::Plus =>
-        {
-          result = self.add(lhs, rhs);
-        }
-      Operator::Minus =>
-        {
-          result = self.sub(lhs, rhs);
-        }
-      Operator::Multiply =>
-        {
-          result = self.mul(lhs, rhs);
-        }
-      Operator::Divide =>
-        {
-          result = self.div(lhs, rhs);
-        }
-      Operator::Modulo =>
-        {
-          result = self.remainder(lhs, rhs);
-        }
-      _ => {
-        panic!("未対応の演算子です : {:?}", op);
-      }
+      Operator::Equal => Self::equal(lhs, rhs),
+      Operator::NotEqual => Self::not_equal(lhs, rhs),
+      Operator::LessThan => Self::less_than(lhs, rhs),
+      Operator::GreaterThan => Self::greater_than(lhs, rhs),
+      Operator::LessThanOrEqual => Self::less_than_or_equal(lhs, rhs),
+      Operator::GreaterThanOrEqual => Self::greater_than_or_equal(lhs, rhs),
+      Operator::Plus => Self::add(lhs, rhs),
+      Operator::Minus => Self::sub(lhs, rhs),
+      Operator::Multiply => Self::mul(lhs, rhs),
+      Operator::Divide => Self::div(lhs, rhs),
+      Operator::Modulo => Self::remainder(lhs, rhs),
+      _ => Err(RuntimeErrorKind::Unsupported(format!("未対応の演算子です : {:?}", op))),
+    }
+  }
+
+  /// 計算の前に char を int に変換する
+  pub(crate) fn promote(value: VariableType) -> VariableType
+  {
+    match value
+    {
+      VariableType::Char(val) => VariableType::Int(val as i32),
+      value => value,
+    }
+  }
+
+  /// 代入先が char の場合は値を char に切り詰め, char を int や float に代入する場合は拡張する.
+  /// それ以外はそのまま代入する
+  pub(crate) fn store_value(target: &VariableType, value: VariableType) -> VariableType
+  {
+    match (target, value)
+    {
+      (VariableType::Char(_), VariableType::Int(val)) => VariableType::Char(val as i8),
+      (VariableType::Char(_), VariableType::Float(val)) => VariableType::Char(val as i8),
+      (VariableType::Int(_), VariableType::Char(val)) => VariableType::Int(val as i32),
+      (VariableType::Float(_), VariableType::Char(val)) => VariableType::Float(val as f64),
+      (_, value) => value,
     }
+  }
+
+  /// value_type の変数に代入する値にする. 配列と構造体はそのまま返す
+  pub(crate) fn store_as(value_type: &ValueType, value: VariableType) -> VariableType
+  {
+    match value_type
+    {
+      ValueType::Int => Self::store_value(&VariableType::Int(0), value),
+      ValueType::Float => Self::store_value(&VariableType::Float(0.0), value),
+      ValueType::Char => Self::store_value(&VariableType::Char(0), value),
+      _ => value,
+    }
+  }
 
-    result
+  /// 文字列を size 個の char にする. 足りない分は '\0' で埋める
+  pub(crate) fn string_values(string: &str, size: usize) -> Vec<VariableType>
+  {
+    let mut values: Vec<VariableType> = string.bytes().map(|c| VariableType::Char(c as i8)).collect();
+    values.resize(size, VariableType::Char(0));
+    values
   }
 
   // 加算演算子　'+'
-  fn add(&mut self, lhs: VariableType, rhs: VariableType) -> VariableType
+  fn add(lhs: VariableType, rhs: VariableType) -> Result<VariableType, RuntimeErrorKind>
   {
-    match (lhs.clone(), rhs.clone())
+    match (lhs, rhs)
     {
       (VariableType::Int(lhs), VariableType::Int(rhs)) =>
         {
-          Int(lhs + rhs)
+          Ok(Int(lhs.wrapping_add(rhs)))
         }
       (VariableType::Int(lhs), VariableType::Float(rhs)) =>
         {
-          VariableType::Float(lhs as f64 + rhs)
+          Ok(VariableType::Float(lhs as f64 + rhs))
         }
       (VariableType::Float(lhs), VariableType::Int(rhs)) =>
         {
-          VariableType::Float(lhs + rhs as f64)
+          Ok(VariableType::Float(lhs + rhs as f64))
         }
       (VariableType::Float(lhs), VariableType::Float(rhs)) =>
         {
-          VariableType::Float(lhs + rhs)
+          Ok(VariableType::Float(lhs + rhs))
         }
-      _ => {
-        panic!("未対応の型です : {:?}, {:?}", lhs, rhs);
+      (lhs, rhs) => {
+        Err(RuntimeErrorKind::TypeMismatch(format!("{} と {}", lhs, rhs)))
       }
     }
   }
 
   // 減算演算子　'-'
-  fn sub(&mut self, lhs: VariableType, rhs: VariableType) -> VariableType
+  fn sub(lhs: VariableType, rhs: VariableType) -> Result<VariableType, RuntimeErrorKind>
   {
-    match (lhs.clone(), rhs.clone())
+    match (lhs, rhs)
     {
       (VariableType::Int(lhs), VariableType::Int(rhs)) =>
         {
-          Int(lhs - rhs)
+          Ok(Int(lhs.wrapping_sub(rhs)))
         }
       (VariableType::Int(lhs), VariableType::Float(rhs)) =>
         {
-          VariableType::Float(lhs as f64 - rhs)
+          Ok(VariableType::Float(lhs as f64 - rhs))
         }
       (VariableType::Float(lhs), VariableType::Int(rhs)) =>
         {
-          VariableType::Float(lhs - rhs as f64)
+          Ok(VariableType::Float(lhs - rhs as f64))
         }
       (VariableType::Float(lhs), VariableType::Float(rhs)) =>
         {
-          VariableType::Float(lhs - rhs)
+          Ok(VariableType::Float(lhs - rhs))
         }
-      _ => {
-        panic!("未対応の型です : {:?}, {:?}", lhs, rhs);
+      (lhs, rhs) => {
+        Err(RuntimeErrorKind::TypeMismatch(format!("{} と {}", lhs, rhs)))
       }
     }
   }
 
   // 乗算演算子　'*'
-  fn mul(&mut self, lhs: VariableType, rhs: VariableType) -> VariableType
+  fn mul(lhs: VariableType, rhs: VariableType) -> Result<VariableType, RuntimeErrorKind>
   {
     match (lhs, rhs)
     {
       (VariableType::Int(lhs), VariableType::Int(rhs)) =>
         {
-          Int(lhs * rhs)
+          Ok(Int(lhs.wrapping_mul(rhs)))
         }
       (VariableType::Int(lhs), VariableType::Float(rhs)) =>
         {
-          VariableType::Float(lhs as f64 * rhs)
+          Ok(VariableType::Float(lhs as f64 * rhs))
         }
       (VariableType::Float(lhs), VariableType::Int(rhs)) =>
         {
-          VariableType::Float(lhs * rhs as f64)
+          Ok(VariableType::Float(lhs * rhs as f64))
         }
       (VariableType::Float(lhs), VariableType::Float(rhs)) =>
         {
-          VariableType::Float(lhs * rhs)
+          Ok(VariableType::Float(lhs * rhs))
         }
-      _ => {
-        panic!("未対応の型です");
+      (lhs, rhs) => {
+        Err(RuntimeErrorKind::TypeMismatch(format!("{} と {}", lhs, rhs)))
       }
     }
   }
 
   // 除算演算子　'/'
-  fn div(&mut self, lhs: VariableType, rhs: VariableType) -> VariableType
+  fn div(lhs: VariableType, rhs: VariableType) -> Result<VariableType, RuntimeErrorKind>
   {
     // 右辺値が0の場合はエラー
-    match rhs
+    if let VariableType::Int(0) | VariableType::Float(0.0) = rhs
     {
-      VariableType::Int(val) if val == 0 =>
-        {
-          panic!("0で割ることはできません");
-        }
-      VariableType::Float(val) if val == 0.0 =>
-        {
-          panic!("0で割ることはできません");
-        }
-      _ => {}
+      return Err(RuntimeErrorKind::DivisionByZero);
     }
 
     match (lhs, rhs)
     {
       (VariableType::Int(lhs), VariableType::Int(rhs)) =>
         {
-          Int(lhs / rhs)
+          Ok(Int(lhs.wrapping_div(rhs)))
         }
       (VariableType::Int(lhs), VariableType::Float(rhs)) =>
         {
-          VariableType::Float(lhs as f64 / rhs)
+          Ok(VariableType::Float(lhs as f64 / rhs))
         }
       (VariableType::Float(lhs), VariableType::Int(rhs)) =>
         {
-          VariableType::Float(lhs / rhs as f64)
+          Ok(VariableType::Float(lhs / rhs as f64))
         }
       (VariableType::Float(lhs), VariableType::Float(rhs)) =>
         {
-          VariableType::Float(lhs / rhs)
+          Ok(VariableType::Float(lhs / rhs))
         }
-      _ => {
-        panic!("未対応の型です");
+      (lhs, rhs) => {
+        Err(RuntimeErrorKind::TypeMismatch(format!("{} と {}", lhs, rhs)))
       }
     }
   }
 
   // 余り演算子　'%'
-  fn remainder(&mut self, lhs: VariableType, rhs: VariableType) -> VariableType
+  fn remainder(lhs: VariableType, rhs: VariableType) -> Result<VariableType, RuntimeErrorKind>
   {
     // 右辺値が0の場合はエラー
-    match rhs
+    if let VariableType::Int(0) | VariableType::Float(0.0) = rhs
     {
-      VariableType::Int(val) if val == 0 =>
-        {
-          panic!("0で割ることはできません");
-        }
-      VariableType::Float(val) if val == 0.0 =>
-        {
-          panic!("0で割ることはできません");
-        }
-      _ => {}
+      return Err(RuntimeErrorKind::DivisionByZero);
     }
 
     match (lhs, rhs)
     {
       (VariableType::Int(lhs), VariableType::Int(rhs)) =>
         {
-          Int(lhs % rhs)
+          Ok(Int(lhs.wrapping_rem(rhs)))
         }
       (VariableType::Int(lhs), VariableType::Float(rhs)) =>
         {
-          Int(lhs % rhs as i32)
+          // 右辺を整数にすると 0 になる場合もエラー
+          Ok(Int(lhs.checked_rem(rhs as i32).ok_or(RuntimeErrorKind::DivisionByZero)?))
         }
       (VariableType::Float(lhs), VariableType::Int(rhs)) =>
         {
-          Int((lhs % rhs as f64) as i32)
+          Ok(Int((lhs % rhs as f64) as i32))
         }
       (VariableType::Float(lhs), VariableType::Float(rhs)) =>
         {
-          Int((lhs % rhs) as i32)
+          Ok(Int((lhs % rhs) as i32))
         }
-      _ => {
-        panic!("未対応の型です");
+      (lhs, rhs) => {
+        Err(RuntimeErrorKind::TypeMismatch(format!("{} と {}", lhs, rhs)))
       }
     }
   }
 
   // 同値演算子　'=='
-  fn equal(&mut self, lhs: VariableType, rhs: VariableType) -> VariableType
+  fn equal(lhs: VariableType, rhs: VariableType) -> Result<VariableType, RuntimeErrorKind>
   {
     match (lhs, rhs)
     {
       (VariableType::Int(lhs), VariableType::Int(rhs)) =>
         {
           let result = lhs == rhs;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Int(lhs), VariableType::Float(rhs)) =>
         {
           let result = lhs == rhs as i32;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Float(lhs), VariableType::Int(rhs)) =>
         {
           let result = lhs == rhs as f64;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Float(lhs), VariableType::Float(rhs)) =>
         {
           let result = lhs == rhs;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
-      _ => {
-        panic!("未対応の型です");
+      (lhs, rhs) => {
+        Err(RuntimeErrorKind::TypeMismatch(format!("{} と {}", lhs, rhs)))
       }
     }
   }
 
   // 否定演算子　'!='
-  fn not_equal(&mut self, lhs: VariableType, rhs: VariableType) -> VariableType
+  fn not_equal(lhs: VariableType, rhs: VariableType) -> Result<VariableType, RuntimeErrorKind>
   {
     match (lhs, rhs)
     {
       (VariableType::Int(lhs), VariableType::Int(rhs)) =>
         {
           let result = lhs != rhs;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Int(lhs), VariableType::Float(rhs)) =>
         {
           let result = lhs != rhs as i32;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Float(lhs), VariableType::Int(rhs)) =>
         {
           let result = lhs != rhs as f64;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Float(lhs), VariableType::Float(rhs)) =>
         {
           let result = lhs != rhs;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
-      _ => {
-        panic!("未対応の型です");
+      (lhs, rhs) => {
+        Err(RuntimeErrorKind::TypeMismatch(format!("{} と {}", lhs, rhs)))
       }
     }
   }
 
   // 小なり演算子　'<'
-  fn less_than(&mut self, lhs: VariableType, rhs: VariableType) -> VariableType
+  fn less_than(lhs: VariableType, rhs: VariableType) -> Result<VariableType, RuntimeErrorKind>
   {
     match (lhs, rhs)
     {
       (VariableType::Int(lhs), VariableType::Int(rhs)) =>
         {
           let result = lhs < rhs;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Int(lhs), VariableType::Float(rhs)) =>
         {
           let result = lhs < rhs as i32;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Float(lhs), VariableType::Int(rhs)) =>
         {
           let result = lhs < rhs as f64;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Float(lhs), VariableType::Float(rhs)) =>
         {
           let result = lhs < rhs;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
-      _ => {
-        panic!("未対応の型です");
+      (lhs, rhs) => {
+        Err(RuntimeErrorKind::TypeMismatch(format!("{} と {}", lhs, rhs)))
       }
     }
   }
 
   // 大なり演算子　'>'
-  fn greater_than(&mut self, lhs: VariableType, rhs: VariableType) -> VariableType
+  fn greater_than(lhs: VariableType, rhs: VariableType) -> Result<VariableType, RuntimeErrorKind>
   {
     match (lhs, rhs)
     {
       (VariableType::Int(lhs), VariableType::Int(rhs)) =>
         {
           let result = lhs > rhs;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Int(lhs), VariableType::Float(rhs)) =>
         {
           let result = lhs > rhs as i32;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Float(lhs), VariableType::Int(rhs)) =>
         {
           let result = lhs > rhs as f64;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Float(lhs), VariableType::Float(rhs)) =>
         {
           let result = lhs > rhs;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
-      _ => {
-        panic!("未対応の型です");
+      (lhs, rhs) => {
+        Err(RuntimeErrorKind::TypeMismatch(format!("{} と {}", lhs, rhs)))
       }
     }
   }
 
   // 小なりイコール演算子　'<='
-  fn less_than_or_equal(&mut self, lhs: VariableType, rhs: VariableType) -> VariableType
+  fn less_than_or_equal(lhs: VariableType, rhs: VariableType) -> Result<VariableType, RuntimeErrorKind>
   {
     match (lhs, rhs)
     {
       (VariableType::Int(lhs), VariableType::Int(rhs)) =>
         {
           let result = lhs <= rhs;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Int(lhs), VariableType::Float(rhs)) =>
         {
           let result = lhs <= rhs as i32;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Float(lhs), VariableType::Int(rhs)) =>
         {
           let result = lhs <= rhs as f64;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Float(lhs), VariableType::Float(rhs)) =>
         {
           let result = lhs <= rhs;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
-      _ => {
-        panic!("未対応の型です");
+      (lhs, rhs) => {
+        Err(RuntimeErrorKind::TypeMismatch(format!("{} と {}", lhs, rhs)))
       }
     }
   }
 
   // 大なりイコール演算子　'>='
-  fn greater_than_or_equal(&mut self, lhs: VariableType, rhs: VariableType) -> VariableType
+  fn greater_than_or_equal(lhs: VariableType, rhs: VariableType) -> Result<VariableType, RuntimeErrorKind>
   {
     match (lhs, rhs)
     {
       (VariableType::Int(lhs), VariableType::Int(rhs)) =>
         {
           let result = lhs >= rhs;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Int(lhs), VariableType::Float(rhs)) =>
         {
           let result = lhs >= rhs as i32;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Float(lhs), VariableType::Int(rhs)) =>
         {
           let result = lhs >= rhs as f64;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
       (VariableType::Float(lhs), VariableType::Float(rhs)) =>
         {
           let result = lhs >= rhs;
-          Int(if result { 1 } else { 0 })
+          Ok(Int(if result { 1 } else { 0 }))
         }
-      _ => {
-        panic!("未対応の型です");
+      (lhs, rhs) => {
+        Err(RuntimeErrorKind::TypeMismatch(format!("{} と {}", lhs, rhs)))
       }
     }
   }
 
-  // 論理和　'||'
-  fn logical_or(&mut self, lhs: VariableType, rhs: VariableType) -> VariableType
+  // 論理和 '||', 論理積 '&&'. 結果は 0 か 1 の int になる
+  fn logical_operator(&mut self, op: &Operator, lhs: &Rc<RefCell<Node>>, rhs: &Rc<RefCell<Node>>)
+                      -> Result<VariableType, RuntimeError>
   {
-    match (lhs, rhs)
-    {
-      (VariableType::Int(lhs), VariableType::Int(rhs)) =>
-        {
-          let result = lhs != 0 || rhs != 0;
-          Int(if result { 1 } else { 0 })
-        }
-      (VariableType::Int(lhs), VariableType::Float(rhs)) =>
-        {
-          let result = lhs != 0 || rhs != 0.0;
-          Int(if result { 1 } else { 0 })
-        }
-      (VariableType::Float(lhs), VariableType::Int(rhs)) =>
-        {
-          let result = lhs != 0.0 || rhs != 0;
-          Int(if result { 1 } else { 0 })
-        }
-      (VariableType::Float(lhs), VariableType::Float(rhs)) =>
-        {
-          let result = lhs != 0.0 || rhs != 0.0;
-          Int(if result { 1 } else { 0 })
-        }
-      _ => {
-        panic!("未対応の型です");
-      }
-    }
-  }
+    let lhs_value = self.statement(lhs)?;
+    let lhs_value = self.is_true(lhs_value, lhs.borrow().span())?;
 
-  // 論理積　'&&'
-  fn logical_and(&mut self, lhs: VariableType, rhs: VariableType) -> VariableType
-  {
-    match (lhs, rhs)
+    // '||' で左辺が真, '&&' で左辺が偽の場合は右辺を評価しない
+    match (op, lhs_value)
     {
-      (VariableType::Int(lhs), VariableType::Int(rhs)) =>
-        {
-          let result = lhs != 0 && rhs != 0;
-          Int(if result { 1 } else { 0 })
-        }
-      (VariableType::Int(lhs), VariableType::Float(rhs)) =>
-        {
-          let result = lhs != 0 && rhs != 0.0;
-          Int(if result { 1 } else { 0 })
-        }
-      (VariableType::Float(lhs), VariableType::Int(rhs)) =>
-        {
-          let result = lhs != 0.0 && rhs != 0;
-          Int(if result { 1 } else { 0 })
-        }
-      (VariableType::Float(lhs), VariableType::Float(rhs)) =>
-        {
-          let result = lhs != 0.0 && rhs != 0.0;
-          Int(if result { 1 } else { 0 })
-        }
-      _ => {
-        panic!("未対応の型です");
-      }
+      (Operator::LogicalOr, true) => return Ok(Int(1)),
+      (Operator::LogicalAnd, false) => return Ok(Int(0)),
+      _ => {}
     }
+
+    let rhs_value = self.statement(rhs)?;
+    let rhs_value = self.is_true(rhs_value, rhs.borrow().span())?;
+    Ok(Int(if rhs_value { 1 } else { 0 }))
   }
 
-  fn identifier_name(&self, node: &Rc<RefCell<Node>>) -> String
+  fn identifier_name(&self, node: &Rc<RefCell<Node>>) -> Result<String, RuntimeError>
   {
-    let mut identifier = String::new();
-    if let Some(val) = node.borrow().val()
+    match node.borrow().val()
     {
-      match val
-      {
-        Leaf::Identifier(name) => {
-          identifier = name.clone();
-        }
-        _ => {
-          panic!("識別子ではありません : {:?}", val);
-        }
-      }
+      Some(Leaf::Identifier(name)) => Ok(name.clone()),
+      val => Err(self.runtime_error(
+        RuntimeErrorKind::Unsupported(format!("識別子ではありません : {:?}", val)), node.borrow().span())),
     }
-    identifier
   }
 
-  fn remove_return(&mut self, leaf: VariableType) -> VariableType
+  fn remove_return(&self, leaf: VariableType) -> VariableType
   {
     match leaf
     {
@@ -1667,20 +2069,23 @@ impl Interpreter
 mod tests
 {
   use crate::interpreter::VariableType::{Float, Int};
-  use crate::interpreter::{Array, Interpreter, Variable, VariableType};
+  use crate::interpreter::{Array, Interpreter, RuntimeError, RuntimeErrorKind, StackFrame, Struct, Variable, VariableType};
   use crate::parser::Parser;
+  use std::cell::RefCell;
   use std::collections::HashMap;
-  use crate::lexical::Lexer;
+  use std::io::Write;
+  use std::rc::Rc;
+  use crate::lexical::{Lexer, Span};
 
   fn run_program(program: &str) -> (VariableType, HashMap<String, Variable>) {
     let mut lexer = Lexer::new(program.to_string());
-    lexer.tokenize();
+    lexer.tokenize().unwrap();
     let tokens = lexer.tokens().clone();
     let mut parser = Parser::new(tokens);
-    parser.parse();
+    parser.parse().unwrap();
     let mut interpreter = Interpreter::new(parser.roots());
-    let val = interpreter.run();
-    (val, interpreter.global_variables().clone())
+    let val = interpreter.run().unwrap();
+    (val, interpreter.global_variables())
   }
 
   #[test]
@@ -2184,9 +2589,9 @@ mod tests
     // show_variables coverage
     let program = "int x = 10; int main() { return x; }";
     let mut lexer = Lexer::new(program.to_string());
-    lexer.tokenize();
+    lexer.tokenize().unwrap();
     let mut parser = Parser::new(lexer.tokens().clone());
-    parser.parse();
+    parser.parse().unwrap();
     let interpreter = Interpreter::new(parser.roots());
     interpreter.show_variables();
   }
@@ -2282,4 +2687,474 @@ mod tests
     // 1 + 3 + 5 + 7 + 9 = 25
     assert_eq!(val, Int(25));
   }
-}
\ No newline at end of file
+
+  fn run_error(program: &str) -> RuntimeError {
+    let mut lexer = Lexer::with_file_name(program.to_string(), "test.c");
+    lexer.tokenize().unwrap();
+    let mut parser = Parser::with_spans(lexer.tokens().clone(), lexer.spans().clone());
+    parser.parse().unwrap();
+    let mut interpreter = Interpreter::new(parser.roots());
+    interpreter.run().unwrap_err()
+  }
+
+  #[test]
+  fn test_runtime_error_call_stack() {
+    let program = "int divide(int a, int b) {
+    return a / b;
+}
+int calc(int x) {
+    return divide(x, x - 2);
+}
+int main() {
+    return calc(2);
+}
+";
+    let error = run_error(program);
+    assert_eq!(error.kind(), &RuntimeErrorKind::DivisionByZero);
+    let span = error.span().unwrap();
+    assert_eq!((span.line(), span.column()), (2, 12));
+
+    // 外側の関数から順に呼び出し位置とともに並ぶ
+    let frames: Vec<(String, Option<(usize, usize)>)> = error.call_stack().iter()
+      .map(|frame| (frame.function().clone(),
+                    frame.call_site().map(|span| (span.line(), span.column()))))
+      .collect();
+    assert_eq!(frames, vec![
+      ("main".to_string(), None),
+      ("calc".to_string(), Some((8, 12))),
+      ("divide".to_string(), Some((5, 12))),
+    ]);
+
+    let message = error.to_string();
+    assert!(message.starts_with("test.c:2:12: 0で割ることはできません"), "{}", message);
+    assert!(message.contains("関数 divide (test.c:5:12 で呼び出し)"), "{}", message);
+  }
+
+  #[test]
+  fn test_runtime_errors() {
+    let error = run_error("int main() { int x = 10; return x % 0; }");
+    assert_eq!(error.kind(), &RuntimeErrorKind::DivisionByZero);
+    let error = run_error("int main() { float x = 1.5; return 10 / (x - x); }");
+    assert_eq!(error.kind(), &RuntimeErrorKind::DivisionByZero);
+
+    let error = run_error("int main() { return y + 1; }");
+    assert_eq!(error.kind(), &RuntimeErrorKind::UndefinedVariable("y".to_string()));
+
+    let error = run_error("int main() { y = 1; return 0; }");
+    assert_eq!(error.kind(), &RuntimeErrorKind::UndefinedVariable("y".to_string()));
+
+    let error = run_error("int f(int a, int b) { return a + b; } int main() { return f(1); }");
+    assert_eq!(error.kind(), &RuntimeErrorKind::ArgumentCountMismatch {
+      function: "f".to_string(),
+      expected: 2,
+      found: 1,
+    });
+
+    let error = run_error("int main() { return g(); }");
+    assert_eq!(error.kind(), &RuntimeErrorKind::UndefinedFunction("g".to_string()));
+
+    let error = run_error("int main() { int a[3]; return a[1.5]; }");
+    assert_eq!(error.kind(), &RuntimeErrorKind::NonIntegerIndex("1.5".to_string()));
+
+    let error = run_error("int main() { int a[3]; a[3] = 1; return 0; }");
+    assert_eq!(error.kind(), &RuntimeErrorKind::IndexOutOfBounds {
+      array: "a".to_string(),
+      index: 3,
+      length: 3,
+    });
+
+    let error = run_error("int main() { int a[3]; return a[-1]; }");
+    assert_eq!(error.kind(), &RuntimeErrorKind::IndexOutOfBounds {
+      array: "a".to_string(),
+      index: -1,
+      length: 3,
+    });
+
+    // ソース上の位置が無いエラーは位置を表示しない
+    let error = run_error("int x = 1;");
+    assert_eq!(error.kind(), &RuntimeErrorKind::MissingMain);
+    assert_eq!(error.span(), None);
+    assert_eq!(error.to_string(), "main 関数が見つかりません");
+  }
+
+  #[test]
+  fn test_short_circuit() {
+    let program = "
+        int count = 0;
+        int touch() {
+            count = count + 1;
+            return 5;
+        }
+        int and_false = 0 && touch();
+        int or_true = 2 || touch();
+        int and_true = 3 && touch();
+        int or_false = 0.0 || touch();
+        int main() { return 0; }
+    ";
+    let (_, globals) = run_program(program);
+
+    // 右辺が評価されるのは and_true と or_false の 2 回だけ
+    assert_eq!(globals.get("count").unwrap(), &Variable::Value(Int(2)));
+    assert_eq!(globals.get("and_false").unwrap(), &Variable::Value(Int(0)));
+    assert_eq!(globals.get("or_true").unwrap(), &Variable::Value(Int(1)));
+    assert_eq!(globals.get("and_true").unwrap(), &Variable::Value(Int(1)));
+    assert_eq!(globals.get("or_false").unwrap(), &Variable::Value(Int(1)));
+  }
+
+  #[test]
+  fn test_short_circuit_guards_array_access() {
+    let program = "
+        int main() {
+            int a[3];
+            int i;
+            int found = 0;
+            a[2] = 7;
+            for (i = 0; i < 5 && a[i] == 0; i = i + 1) {
+            }
+            if (i == 5 || a[i] == 7) {
+                found = 1;
+            }
+            return found * 10 + i;
+        }
+    ";
+    let (val, _) = run_program(program);
+    assert_eq!(val, Int(12));
+  }
+
+  #[test]
+  fn test_char_and_strings() {
+    let program = r#"
+        char message[8] = "hi\n";
+        char letter = 'a' + 1;
+        char wrapped = 200;
+        int code = 'A';
+        int length(char s[8]) {
+            int i = 0;
+            while (s[i] != '\0') { i = i + 1; }
+            return i;
+        }
+        int main() {
+            char local[] = "abc";
+            local[0] = 'x' + 256;
+            return length(message) * 100 + local[0];
+        }
+    "#;
+    let (val, globals) = run_program(program);
+    assert_eq!(val, Int(3 * 100 + 'x' as i32));
+    assert_eq!(globals.get("letter").unwrap(), &Variable::Value(VariableType::Char(b'b' as i8)));
+    assert_eq!(globals.get("wrapped").unwrap(), &Variable::Value(VariableType::Char(-56)));
+    assert_eq!(globals.get("code").unwrap(), &Variable::Value(Int(65)));
+
+    // 文字列は '\0' で終わる char の配列になり, 残りは '\0' で埋まる
+    let values: Vec<VariableType> = "hi\n\0\0\0\0\0".bytes().map(|c| VariableType::Char(c as i8)).collect();
+    let expected = Array::new("message".to_string(), VariableType::Char(0), values);
+    match globals.get("message").unwrap() {
+      Variable::Array(array) => assert_eq!(*array.borrow(), expected),
+      variable => panic!("配列ではありません : {:?}", variable),
+    }
+    assert_eq!(VariableType::Char(b'\n' as i8).to_string(), "'\\n'");
+  }
+
+  /// テストで出力を読み出せるように共有する出力先
+  #[derive(Clone, Default)]
+  struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+  impl Write for SharedOutput
+  {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize>
+    {
+      self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+      Ok(())
+    }
+  }
+
+  fn run_with_output(program: &str) -> (Result<VariableType, RuntimeError>, String) {
+    run_with_io(program, "")
+  }
+
+  fn run_with_io(program: &str, input: &str) -> (Result<VariableType, RuntimeError>, String) {
+    let mut lexer = Lexer::new(program.to_string());
+    lexer.tokenize().unwrap();
+    let tokens = lexer.tokens().clone();
+    let mut parser = Parser::new(tokens);
+    parser.parse().unwrap();
+    let output = SharedOutput::default();
+    let input = std::io::Cursor::new(input.as_bytes().to_vec());
+    let mut interpreter = Interpreter::with_input(parser.roots(), Box::new(input));
+    interpreter.set_output(Box::new(output.clone()));
+    let val = interpreter.run();
+    let output = String::from_utf8(output.0.borrow().clone()).unwrap();
+    (val, output)
+  }
+
+  #[test]
+  fn test_builtin_output() {
+    let program = r#"
+        char name[8] = "nagato";
+        int main() {
+            int i;
+            for (i = 0; i < 3; i = i + 1) {
+                putchar('a' + i);
+            }
+            putchar('\n');
+            puts(name);
+            int n = printf("%s: %d %f %c%%\n", "value", 42, 0.5, name[0]);
+            return n;
+        }
+    "#;
+    let (val, output) = run_with_output(program);
+    assert_eq!(val, Ok(Int(22)));
+    assert_eq!(output, "abc\nnagato\nvalue: 42 0.500000 n%\n");
+
+    // ユーザが定義した関数が組み込み関数より優先される
+    let program = "
+        int putchar(int c) { return c + 1; }
+        int main() { return putchar(1); }
+    ";
+    let (val, output) = run_with_output(program);
+    assert_eq!(val, Ok(Int(2)));
+    assert_eq!(output, "");
+
+    let (val, _) = run_with_output(r#"int main() { printf("%d %d", 1); return 0; }"#);
+    let error = val.unwrap_err();
+    assert_eq!(*error.kind(), RuntimeErrorKind::ArgumentCountMismatch {
+      function: "printf".to_string(),
+      expected: 3,
+      found: 2,
+    });
+    assert_eq!(error.call_stack().len(), 1);
+  }
+
+  #[test]
+  fn test_builtin_input() {
+    // 1 行目の個数だけ数を読み, 合計と最大値を出力する. 最後に残りの文字数を返す
+    let program = r#"
+        int values[4];
+        int main() {
+            int n;
+            float scale;
+            if (scanf("%d %f", n, scale) != 2) {
+                return -1;
+            }
+            int i;
+            int max = 0;
+            float sum = 0.0;
+            for (i = 0; i < n; i = i + 1) {
+                values[i] = read_int();
+                sum = sum + values[i] * scale;
+                if (values[i] > max) { max = values[i]; }
+            }
+            printf("%f %d\n", sum, max);
+            int rest = 0;
+            while (getchar() != -1) { rest = rest + 1; }
+            return rest;
+        }
+    "#;
+    let cases = [
+      ("3 0.5\n4 8 2\n", Ok(Int(1)), "7.000000 8\n"),
+      ("2 2.0 10\n-3 ab", Ok(Int(3)), "14.000000 10\n"),
+      ("", Ok(Int(-1)), ""),
+    ];
+    for (input, expected, expected_output) in cases
+    {
+      let (val, output) = run_with_io(program, input);
+      assert_eq!(val, expected, "入力 : {:?}", input);
+      assert_eq!(output, expected_output, "入力 : {:?}", input);
+    }
+
+    // 入力が尽きた場合は -1 を返す
+    let program = "
+        int main() {
+            int a[2];
+            scanf(\"%d,%d\", a[0], a[1]);
+            return a[0] * 10 + a[1] + read_int() * 100 + (read_float() < 0.0);
+        }
+    ";
+    let (val, _) = run_with_io(program, "4,5");
+    assert_eq!(val, Ok(Int(-54)));
+
+    let (val, _) = run_with_io("int main() { return read_int(); }", "x");
+    assert!(matches!(val.unwrap_err().kind(), RuntimeErrorKind::InvalidInput(_)));
+    let (val, _) = run_with_io("int main() { return scanf(\"%d\", 1); }", "1");
+    assert!(matches!(val.unwrap_err().kind(), RuntimeErrorKind::Unsupported(_)));
+  }
+
+  #[test]
+  fn test_native_functions() {
+    let program = r#"
+        int scores[3];
+        int main() {
+            fill(scores, 7);
+            int total = sum(scores[0], scores[1], scores[2]);
+            puts("host");
+            tick();
+            tick();
+            return total + tick();
+        }
+    "#;
+    let mut lexer = Lexer::new(program.to_string());
+    lexer.tokenize().unwrap();
+    let mut parser = Parser::new(lexer.tokens().clone());
+    parser.parse().unwrap();
+    let mut interpreter = Interpreter::new(parser.roots());
+
+    // 配列は参照で渡されるので, 書き換えはスクリプトの変数に反映される
+    interpreter.register_native("fill", 2, |arguments| {
+      if let [VariableType::Array(array), value] = arguments {
+        let length = array.borrow().values().len();
+        for index in 0..length {
+          array.borrow_mut().set(index, value.clone()).unwrap();
+        }
+      }
+      Ok(VariableType::Void)
+    });
+    interpreter.register_native("sum", 3, |arguments| {
+      let total = arguments.iter().map(|value| match value {
+        Int(value) => *value,
+        _ => 0,
+      }).sum();
+      Ok(Int(total))
+    });
+    let ticks = Rc::new(std::cell::Cell::new(0));
+    let counter = ticks.clone();
+    interpreter.register_native("tick", 0, move |_| {
+      counter.set(counter.get() + 1);
+      Ok(Int(counter.get()))
+    });
+    // 組み込み関数より優先される
+    let printed = Rc::new(RefCell::new(Vec::new()));
+    let host_output = printed.clone();
+    interpreter.register_native("puts", 1, move |arguments| {
+      host_output.borrow_mut().push(arguments[0].clone());
+      Ok(Int(0))
+    });
+
+    assert_eq!(interpreter.run(), Ok(Int(24)));
+    assert_eq!(ticks.get(), 3);
+    assert_eq!(printed.borrow().len(), 1);
+    let scores = match interpreter.global_variables().get("scores") {
+      Some(Variable::Array(array)) => array.borrow().values().clone(),
+      variable => panic!("配列ではありません : {:?}", variable),
+    };
+    assert_eq!(scores, vec![Int(7), Int(7), Int(7)]);
+
+    // 引数の数の確認と, ホストが返したエラーへの呼び出し履歴の付け足し
+    let program = "
+        int call(int n) { return fail(n); }
+        int main() { return call(1) + sum(1); }
+    ";
+    let mut lexer = Lexer::new(program.to_string());
+    lexer.tokenize().unwrap();
+    let mut parser = Parser::new(lexer.tokens().clone());
+    parser.parse().unwrap();
+    let mut interpreter = Interpreter::new(parser.roots());
+    interpreter.register_native("sum", 2, |_| Ok(Int(0)));
+    let host_span = Span::new("host.rs".into(), 10, 5, 10, 20);
+    let fail_span = host_span.clone();
+    interpreter.register_native("fail", 1, move |arguments| {
+      Err(RuntimeError::new(RuntimeErrorKind::Unsupported(format!("失敗 : {}", arguments[0])),
+                            Some(fail_span.clone()), vec![StackFrame::new("host_check".to_string(), None)]))
+    });
+    let error = interpreter.run().unwrap_err();
+    assert_eq!(*error.kind(), RuntimeErrorKind::Unsupported("失敗 : 1".to_string()));
+    assert_eq!(error.span(), Some(&host_span));
+    let functions: Vec<&String> = error.call_stack().iter().map(|frame| frame.function()).collect();
+    assert_eq!(functions, vec!["main", "call", "fail", "host_check"]);
+
+    interpreter.register_native("fail", 1, |_| Ok(Int(0)));
+    let error = interpreter.run().unwrap_err();
+    assert_eq!(*error.kind(), RuntimeErrorKind::ArgumentCountMismatch {
+      function: "sum".to_string(),
+      expected: 2,
+      found: 1,
+    });
+  }
+
+  #[test]
+  fn test_host_calls() {
+    let program = "
+        int calls = 0;
+        float rate = 1.5;
+        int history[3];
+        struct Point { int x; int y; };
+        struct Point origin;
+        int fibo(int n) {
+            if (n < 2) { return n; }
+            return fibo(n - 1) + fibo(n - 2);
+        }
+        float apply(int value) {
+            calls = calls + 1;
+            history[calls % 3] = value;
+            return value * rate;
+        }
+        int total(int values[3]) {
+            return values[0] + values[1] + values[2];
+        }
+    ";
+    let mut lexer = Lexer::new(program.to_string());
+    lexer.tokenize().unwrap();
+    let mut parser = Parser::new(lexer.tokens().clone());
+    parser.parse().unwrap();
+
+    // main が無くても読み込める
+    let mut interpreter = Interpreter::new(&Vec::new());
+    interpreter.load(parser.roots()).unwrap();
+    assert_eq!(interpreter.get_global("calls"), Some(Int(0)));
+    assert_eq!(interpreter.call("fibo", &[Int(10)]), Ok(Int(55)));
+
+    // グローバル変数は呼び出しの間で保持される
+    assert_eq!(interpreter.call("apply", &[Int(4)]), Ok(Float(6.0)));
+    interpreter.set_global("rate", Float(2.0)).unwrap();
+    assert_eq!(interpreter.get_global("rate"), Some(Float(2.0)));
+    assert_eq!(interpreter.call("apply", &[Int(5)]), Ok(Float(10.0)));
+    assert_eq!(interpreter.get_global("calls"), Some(Int(2)));
+
+    // 配列は参照で渡される
+    let history = match interpreter.get_global("history") {
+      Some(VariableType::Array(array)) => array,
+      value => panic!("配列ではありません : {:?}", value),
+    };
+    assert_eq!(history.borrow().values(), &vec![Int(0), Int(4), Int(5)]);
+    assert_eq!(interpreter.call("total", &[VariableType::Array(history.clone())]), Ok(Int(9)));
+    let values = Array::new("values".to_string(), VariableType::Char(0), vec![VariableType::Char(1), Int(2), Int(3)]);
+    interpreter.set_global("history", VariableType::Array(Rc::new(RefCell::new(values)))).unwrap();
+    assert_eq!(history.borrow().values(), &vec![Int(1), Int(2), Int(3)]);
+
+    let origin = match interpreter.get_global("origin") {
+      Some(VariableType::Struct(s)) => s,
+      value => panic!("構造体ではありません : {:?}", value),
+    };
+    let moved = Struct::new(origin.name().clone(), HashMap::from([("x".to_string(), Int(3)), ("y".to_string(), Int(4))]));
+    interpreter.set_global("origin", VariableType::Struct(moved.clone())).unwrap();
+    assert_eq!(interpreter.get_global("origin"), Some(VariableType::Struct(moved)));
+
+    assert_eq!(interpreter.get_global("unknown"), None);
+    assert_eq!(*interpreter.set_global("unknown", Int(1)).unwrap_err().kind(),
+               RuntimeErrorKind::UndefinedVariable("unknown".to_string()));
+    assert!(matches!(interpreter.set_global("calls", VariableType::Array(history.clone())).unwrap_err().kind(),
+                     RuntimeErrorKind::TypeMismatch(_)));
+    assert_eq!(*interpreter.call("main", &[]).unwrap_err().kind(),
+               RuntimeErrorKind::UndefinedFunction("main".to_string()));
+    assert_eq!(*interpreter.call("fibo", &[]).unwrap_err().kind(), RuntimeErrorKind::ArgumentCountMismatch {
+      function: "fibo".to_string(),
+      expected: 1,
+      found: 0,
+    });
+    assert!(matches!(interpreter.call("total", &[Int(1)]).unwrap_err().kind(), RuntimeErrorKind::TypeMismatch(_)));
+
+    // ホストの関数も呼び出せる
+    interpreter.register_native("twice", 1, |arguments| match arguments {
+      [Int(value)] => Ok(Int(value * 2)),
+      _ => Ok(Int(0)),
+    });
+    assert_eq!(interpreter.call("twice", &[Int(21)]), Ok(Int(42)));
+
+    // 読み込み直すとグローバル変数は初期化される
+    interpreter.load(parser.roots()).unwrap();
+    assert_eq!(interpreter.get_global("calls"), Some(Int(0)));
+  }
+}
@@ -1,4 +1,7 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 /// BNFに基づく演算子の定義
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -62,6 +65,7 @@ pub enum ValueType {
     Void,
     Int,
     Float,
+    Char,
     Struct(String),
     Array(Box<ValueType>, usize),
 }
@@ -70,6 +74,7 @@ pub enum ValueType {
 pub enum Constant {
     Integer(i32),
     Float(f32),
+    Char(i8),
 }
 
 /// トークン
@@ -78,6 +83,8 @@ pub enum Token {
     // 識別子やリテラル
     Identifier(String),        // 変数や関数名
     Constant(Constant),         // 定数
+    StringLiteral(String),      // 文字列リテラル. エスケープシーケンスは展開済み
+    CharLiteral(char),          // 文字リテラル. ASCII の 1 文字
 
     // 型指定子
     Type(ValueType),                // 型指定子
@@ -122,6 +129,7 @@ impl Token
         match keyword {
             "int" => Some(Token::Type(ValueType::Int)),
             "float" => Some(Token::Type(ValueType::Float)),
+            "char" => Some(Token::Type(ValueType::Char)),
             "void" => Some(Token::Type(ValueType::Void)),
             "if" => Some(Token::If),
             "else" => Some(Token::Else),
@@ -132,10 +140,11 @@ impl Token
             "for" => Some(Token::For),
             "struct" => Some(Token::Struct),
 
-            // 数値の場合
-            _ if keyword.parse::<i64>().is_ok() =>
+            // 数値の場合. 数字で始まらないもの (inf, nan など) は識別子として扱う
+            _ if !Token::is_numeric(keyword) => None,
+            _ if keyword.parse::<i32>().is_ok() =>
                 Some(Token::Constant(Constant::Integer(keyword.parse::<i32>().unwrap()))),
-            _ if keyword.parse::<f64>().is_ok() =>
+            _ if Token::is_floating_constant(keyword) && keyword.parse::<f32>().is_ok() =>
                 Some(Token::Constant(Constant::Float(keyword.parse::<f32>().unwrap()))),
             _ => None,
         }
@@ -144,56 +153,415 @@ impl Token
     pub fn is_floating_constant(keyword: &str) -> bool {
         keyword.contains('.')
     }
+
+    /// 数字で始まる文字列は数値として扱う
+    pub fn is_numeric(keyword: &str) -> bool {
+        keyword.starts_with(|c: char| c.is_ascii_digit())
+    }
+}
+
+/// 字句解析のエラー
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexError
+{
+    UnknownCharacter(char, Span),          // 解釈できない文字
+    MalformedNumber(String, Span),         // 数値として解釈できない数字列
+    UnterminatedBlockComment(Span),        // "*/" で閉じられていない "/*"
+    BadDirective(String, Span),            // 未対応または不正なプリプロセッサ指令
+    BadMacroCall(String, Span),            // 関数形式のマクロの呼び出しが不正
+    UnterminatedConditional(Span),         // #endif で閉じられていない #if
+    IncludeNotFound(String, Span),         // #include のファイルが見つからない
+    RecursiveInclude(String, Span),        // #include が循環している
+    MalformedLiteral(String, Span),        // 不正なエスケープシーケンスや, 1 文字でない文字リテラル
+    UnterminatedLiteral(Span),             // 行末までに閉じられていない文字列や文字
+}
+
+impl LexError
+{
+    pub fn span(&self) -> &Span {
+        match self {
+            LexError::UnknownCharacter(_, span) => span,
+            LexError::MalformedNumber(_, span) => span,
+            LexError::UnterminatedBlockComment(span) => span,
+            LexError::BadDirective(_, span) => span,
+            LexError::BadMacroCall(_, span) => span,
+            LexError::UnterminatedConditional(span) => span,
+            LexError::IncludeNotFound(_, span) => span,
+            LexError::RecursiveInclude(_, span) => span,
+            LexError::MalformedLiteral(_, span) => span,
+            LexError::UnterminatedLiteral(span) => span,
+        }
+    }
+}
+
+impl std::fmt::Display for LexError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        match self
+        {
+            LexError::UnknownCharacter(c, span) => write!(f, "{}: 不明な文字です : {:?}", span, c),
+            LexError::MalformedNumber(number, span) => write!(f, "{}: 不正な数値です : {}", span, number),
+            LexError::UnterminatedBlockComment(span) => write!(f, "{}: コメントが閉じられていません", span),
+            LexError::BadDirective(directive, span) => write!(f, "{}: 不正なプリプロセッサ指令です : {}", span, directive),
+            LexError::BadMacroCall(message, span) => write!(f, "{}: 不正なマクロの呼び出しです : {}", span, message),
+            LexError::UnterminatedConditional(span) => write!(f, "{}: #endif がありません", span),
+            LexError::IncludeNotFound(file, span) => write!(f, "{}: インクルードするファイルが見つかりません : {}", span, file),
+            LexError::RecursiveInclude(file, span) => write!(f, "{}: インクルードが循環しています : {}", span, file),
+            LexError::MalformedLiteral(literal, span) => write!(f, "{}: 不正なリテラルです : {}", span, literal),
+            LexError::UnterminatedLiteral(span) => write!(f, "{}: リテラルが閉じられていません", span),
+        }
+    }
+}
+
+impl std::error::Error for LexError {}
+
+/// ソースコード上の範囲. 行と列は 1 始まり
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Span
+{
+    file: Rc<str>,
+    line: usize,
+    column: usize,
+    end_line: usize,
+    end_column: usize,
+}
+
+impl Span
+{
+    pub fn new(file: Rc<str>, line: usize, column: usize, end_line: usize, end_column: usize) -> Self {
+        Span {
+            file,
+            line,
+            column,
+            end_line,
+            end_column,
+        }
+    }
+
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn end_line(&self) -> usize {
+        self.end_line
+    }
+
+    pub fn end_column(&self) -> usize {
+        self.end_column
+    }
+
+    /// self の先頭から end の末尾までを覆う範囲を返す
+    pub fn to(&self, end: &Span) -> Span {
+        Span {
+            file: self.file.clone(),
+            line: self.line,
+            column: self.column,
+            end_line: end.end_line,
+            end_column: end.end_column,
+        }
+    }
+}
+
+impl std::fmt::Display for Span
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// 文字ごとの元のソース上の位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Location
+{
+    file: usize,
+    line: usize,
+    column: usize,
 }
 
+/// マクロ. start_line の次の行から end_line の前の行まで有効.
+/// 行番号はコメントを取り除き, インクルードしたファイルの行を含めて数えた 1 始まりの行番号で,
+/// -D で定義したマクロは 0 行目とする
 #[derive(Debug, Clone)]
 pub struct Macro
 {
     name: String,
+    parameters: Option<Vec<String>>,  // 関数形式のマクロの引数名. オブジェクト形式の場合は None
     value: String,
-    start_line: usize,
-    end_line: usize,
+    start_line: usize,  // #define の行
+    end_line: usize,    // #undef か再定義の行. なければ最終行の次 (前処理中は usize::MAX)
 }
 
+impl Macro
+{
+    fn is_active(&self, line_number: usize) -> bool
+    {
+        self.start_line < line_number && line_number < self.end_line
+    }
+}
+
+/// プリプロセッサが扱うトークン
+#[derive(Debug, Clone)]
+struct PpToken
+{
+    text: String,
+    locations: Vec<Location>,  // text の各文字の元のソース上の位置
+    line: usize,               // マクロの有効範囲を調べるための行番号
+    leading_space: bool,       // 直前に空白があるか
+    hide_set: Vec<String>,     // 展開済みのため, このトークンでは展開しないマクロ名
+}
+
+impl PpToken
+{
+    fn is_identifier(&self) -> bool
+    {
+        self.text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+    }
+
+    /// self の位置で text のトークンを作る
+    fn with_text(&self, text: String) -> PpToken
+    {
+        PpToken {
+            locations: vec![self.locations[0]; text.chars().count()],
+            text,
+            line: self.line,
+            leading_space: true,
+            hide_set: Vec::new(),
+        }
+    }
+
+    /// # 演算子. トークン列を文字列リテラルにする
+    fn stringize(tokens: &[PpToken]) -> String
+    {
+        let mut text = String::from('"');
+        for (i, token) in tokens.iter().enumerate() {
+            if i > 0 && token.leading_space {
+                text.push(' ');
+            }
+
+            // 文字列と文字の中の '"' と '\\' はエスケープする
+            if token.text.starts_with('"') || token.text.starts_with('\'') {
+                for c in token.text.chars() {
+                    if c == '"' || c == '\\' {
+                        text.push('\\');
+                    }
+                    text.push(c);
+                }
+            } else {
+                text.push_str(&token.text);
+            }
+        }
+        text.push('"');
+        text
+    }
+
+    /// ## 演算子. lhs と rhs の先頭のトークンをつなげて字句を読み直す
+    fn paste(lhs: PpToken, rhs: Vec<PpToken>) -> Vec<PpToken>
+    {
+        let mut rhs = rhs.into_iter();
+        let first = match rhs.next() {
+            Some(first) => first,
+            None => return vec![lhs],
+        };
+        if lhs.text.is_empty() {
+            return std::iter::once(first).chain(rhs).collect();
+        }
+
+        let text = lhs.text + &first.text;
+        let locations = vec![lhs.locations[0]; text.chars().count()];
+        let mut tokens = Lexer::pp_tokenize(&text, &locations, lhs.line);
+        tokens.extend(rhs);
+        tokens
+    }
+
+    /// トークン列を空白を保ったまま文字列に戻す
+    fn join(tokens: &[PpToken]) -> String
+    {
+        let mut text = String::new();
+        for token in tokens {
+            if token.leading_space && !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(&token.text);
+        }
+        text
+    }
+}
+
+
+/// 2 文字で 1 つのトークンになる記号
+const PP_PUNCTUATORS: [&str; 7] = ["##", "==", "!=", "<=", ">=", "&&", "||"];
+
+/// #if から #endif までの状態
+#[derive(Debug, Clone)]
+struct Conditional
+{
+    span: Span,       // #if の位置
+    active: bool,     // 今の分岐が有効か
+    taken: bool,      // 有効な分岐がすでにあったか
+    has_else: bool,   // #else を読んだか
+}
 
 #[derive(Debug, Clone)]
 pub struct Lexer {
     sentence: String,
     position: usize,
     tokens: Vec<Token>,
+    spans: Vec<Span>,          // tokens と同じ順番で各トークンの位置を保持する
     token_str: String,
-    line_num: usize, // プログラムの行数
+    token_start: usize,        // token_str の先頭文字の位置
+    line_num: usize, // 前処理した行数. インクルードしたファイルの行も含む
     macros: Vec<Macro>,
+    conditionals: Vec<Conditional>,
+    include_directories: Vec<PathBuf>,  // #include のファイルを探すディレクトリ
+    include_stack: Vec<PathBuf>,        // インクルード中のファイル. 循環の検出に使う
+    include_guards: HashMap<PathBuf, String>,  // インクルードガードのマクロ名
+    once_files: HashSet<PathBuf>,       // #pragma once を含むファイル
+    files: Vec<Rc<str>>,
+    locations: Vec<Location>,  // sentence の各文字が元のソースのどこにあったか
+    errors: Vec<LexError>,
 }
 
 impl Lexer
 {
     pub fn new(sentence: String) -> Lexer {
+        Lexer::with_file_name(sentence, "<input>")
+    }
 
-        // 文字列の行数を取得
-        let line_num = sentence.lines().count();
+    /// エラー表示などで使うファイル名を指定して Lexer を作成する
+    pub fn with_file_name(sentence: String, file_name: &str) -> Lexer {
+        let locations = Lexer::locations_of(&sentence, 0);
 
         Lexer {
             sentence,
             position: 0,
             tokens: Vec::new(),
+            spans: Vec::new(),
             token_str: String::new(),
-            line_num,
+            token_start: 0,
+            line_num: 0,
             macros: Vec::new(),
+            conditionals: Vec::new(),
+            include_directories: Vec::new(),
+            include_stack: Vec::new(),
+            include_guards: HashMap::new(),
+            once_files: HashSet::new(),
+            files: vec![Rc::from(file_name)],
+            locations,
+            errors: Vec::new(),
         }
     }
 
+    /// #include のファイルを探すディレクトリを追加する.
+    /// "file" はインクルードするファイルのディレクトリの次に, <file> はここだけを探す
+    pub fn add_include_directory<P: Into<PathBuf>>(&mut self, directory: P)
+    {
+        self.include_directories.push(directory.into());
+    }
+
+    /// text の各文字の行と列を記録する
+    fn locations_of(text: &str, file: usize) -> Vec<Location>
+    {
+        let mut locations = Vec::new();
+        let mut line = 1;
+        let mut column = 1;
+        for c in text.chars() {
+            locations.push(Location { file, line, column });
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+
+        locations
+    }
+
     fn reset_position(&mut self)
     {
         self.position = 0;
         self.token_str.clear();
         self.tokens.clear();
+        self.spans.clear();
+    }
+
+    /// text を位置情報付きで行ごとに分割する. 各行は末尾の改行を含む
+    fn split_lines(text: &str, text_locations: &[Location]) -> Vec<(String, Vec<Location>)>
+    {
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        let mut locations = Vec::new();
+
+        for (c, location) in text.chars().zip(text_locations.iter()) {
+            if c == '\n' {
+                // CRLF の '\r' は取り除く
+                if line.ends_with('\r') {
+                    line.pop();
+                    locations.pop();
+                }
+                line.push(c);
+                locations.push(*location);
+                lines.push((line, locations));
+                line = String::new();
+                locations = Vec::new();
+            } else {
+                line.push(c);
+                locations.push(*location);
+            }
+        }
+
+        if !line.is_empty() {
+            lines.push((line, locations));
+        }
+
+        lines
+    }
+
+    /// 文字の位置から Span を作成する
+    fn span_of(&self, start: usize, end: usize) -> Span
+    {
+        match (self.locations.get(start), self.locations.get(end)) {
+            (Some(start), Some(end)) => self.span_at(start, end),
+            _ => Span::default(),
+        }
+    }
+
+    fn span_at(&self, start: &Location, end: &Location) -> Span
+    {
+        Span::new(self.files[start.file].clone(), start.line, start.column, end.line, end.column)
+    }
+
+    /// start 番目の文字から直前に読んだ文字までをトークンとして追加する
+    fn push_token(&mut self, token: Token, start: usize)
+    {
+        let span = self.span_of(start, self.position - 1);
+        self.tokens.push(token);
+        self.spans.push(span);
     }
 
     fn remove_comments(&mut self)
+    {
+        let sentence = std::mem::take(&mut self.sentence);
+        let locations = std::mem::take(&mut self.locations);
+        (self.sentence, self.locations) = self.strip_comments(&sentence, &locations);
+    }
+
+    /// text からコメントを取り除く
+    fn strip_comments(&mut self, text: &str, locations: &[Location]) -> (String, Vec<Location>)
     {
         let mut new_sentence = String::new();
-        let chars: Vec<char> = self.sentence.chars().collect();
+        let mut new_locations = Vec::new();
+        let chars: Vec<char> = text.chars().collect();
         let mut i = 0;
 
         while i < chars.len() {
@@ -208,6 +576,7 @@ impl Lexer
             // 2. "/* ... */" コメントの検知
             else if i + 1 < chars.len() && chars[i] == '/' && chars[i + 1] == '*' {
                 // "/*" が始まったら、"*/" が出るか入力末まで読み飛ばす
+                let start = i;
                 i += 2;
                 while i + 1 < chars.len() && !(chars[i] == '*' && chars[i + 1] == '/') {
                     i += 1;
@@ -215,169 +584,735 @@ impl Lexer
                 // "*/" の分も読み飛ばす (まだ入力が続いていれば)
                 if i + 1 < chars.len() {
                     i += 2;
+                } else {
+                    let span = self.span_at(&locations[start], &locations[start + 1]);
+                    self.errors.push(LexError::UnterminatedBlockComment(span));
+                    i = chars.len();
+                }
+            }
+            // 3. 文字列と文字の中はコメントとして扱わない. 閉じられていない場合は字句解析で報告する
+            else if chars[i] == '"' || chars[i] == '\'' {
+                let quote = chars[i];
+                new_sentence.push(chars[i]);
+                new_locations.push(locations[i]);
+                i += 1;
+                while i < chars.len() && chars[i] != quote && chars[i] != '\n' {
+                    if chars[i] == '\\' && i + 1 < chars.len() && chars[i + 1] != '\n' {
+                        new_sentence.push(chars[i]);
+                        new_locations.push(locations[i]);
+                        i += 1;
+                    }
+                    new_sentence.push(chars[i]);
+                    new_locations.push(locations[i]);
+                    i += 1;
+                }
+                if i < chars.len() && chars[i] == quote {
+                    new_sentence.push(chars[i]);
+                    new_locations.push(locations[i]);
+                    i += 1;
                 }
             }
-            // 3. 通常の文字として読み込む
+            // 4. 通常の文字として読み込む
             else {
                 new_sentence.push(chars[i]);
+                new_locations.push(locations[i]);
                 i += 1;
             }
         }
 
-        self.sentence = new_sentence;
+        (new_sentence, new_locations)
+    }
+
+    /// 1 行をプリプロセッサのトークンに分割する. 改行と空白はトークンに含めない
+    fn pp_tokenize(line: &str, locations: &[Location], line_number: usize) -> Vec<PpToken>
+    {
+        let chars: Vec<char> = line.chars().collect();
+        let mut tokens = Vec::new();
+        let mut leading_space = false;
+        let mut i = 0;
+
+        while i < chars.len() {
+            let start = i;
+            let c = chars[i];
+            if c.is_ascii_whitespace() {
+                leading_space = true;
+                i += 1;
+                continue;
+            }
+
+            if c.is_ascii_alphabetic() || c == '_' {
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+            } else if c.is_ascii_digit() {
+                // 数字で始まり英数字と '.' が続くものは 1 つの数値として扱う
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                    i += 1;
+                }
+            } else if c == '"' || c == '\'' {
+                // 文字列と文字の中身は置換しない
+                i += 1;
+                while i < chars.len() && chars[i] != c {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                i = (i + 1).min(chars.len());
+            } else if i + 1 < chars.len() && PP_PUNCTUATORS.contains(&&*chars[i..i + 2].iter().collect::<String>()) {
+                i += 2;
+            } else {
+                i += 1;
+            }
+
+            tokens.push(PpToken {
+                text: chars[start..i].iter().collect(),
+                locations: locations[start..i].to_vec(),
+                line: line_number,
+                leading_space,
+                hide_set: Vec::new(),
+            });
+            leading_space = false;
+        }
+
+        tokens
+    }
+
+    /// プリプロセッサ指令を処理する. tokens は先頭の '#' を含む 1 行分のトークン
+    fn directive(&mut self, tokens: &[PpToken], line_number: usize, output: &mut Vec<PpToken>)
+    {
+        // '#' だけの行は何もしない
+        let name = match tokens.get(1) {
+            Some(name) => name,
+            None => return,
+        };
+
+        match name.text.as_str() {
+            "if" | "ifdef" | "ifndef" | "elif" | "else" | "endif" => self.conditional_directive(tokens, line_number),
+            // 無効な分岐の中の指令は読み飛ばす
+            _ if !self.is_active() => {}
+            "define" => self.macro_define(tokens, line_number),
+            "undef" => self.macro_undef(tokens, line_number),
+            "include" => self.include(tokens, line_number, output),
+            "pragma" => {
+                // #pragma once 以外は無視する
+                if tokens.len() == 3 && tokens[2].text == "once" {
+                    let file = self.include_stack.last().unwrap().clone();
+                    self.once_files.insert(file);
+                }
+            }
+            _ => {
+                let span = self.span_between(&tokens[0], name);
+                self.errors.push(LexError::BadDirective(format!("#{}", name.text), span));
+            }
+        }
+    }
+
+    /// 条件付きコンパイルで, 今の行が有効かどうか
+    fn is_active(&self) -> bool
+    {
+        self.conditionals.last().is_none_or(|conditional| conditional.active)
+    }
+
+    /// #if, #ifdef, #ifndef, #elif, #else, #endif を処理する
+    fn conditional_directive(&mut self, tokens: &[PpToken], line_number: usize)
+    {
+        let name = &tokens[1];
+        let span = self.span_between(&tokens[0], name);
+        match name.text.as_str() {
+            "if" | "ifdef" | "ifndef" => {
+                // 外側が無効な場合は条件を評価せず, どの分岐も有効にしない
+                let parent = self.is_active();
+                let condition = parent && self.condition(tokens, line_number);
+                self.conditionals.push(Conditional {
+                    span,
+                    active: condition,
+                    taken: condition || !parent,
+                    has_else: false,
+                });
+            }
+            "elif" | "else" => {
+                let (taken, has_else) = match self.conditionals.last().filter(|c| c.span.file() == span.file()) {
+                    Some(conditional) => (conditional.taken, conditional.has_else),
+                    None => (true, true),
+                };
+                if has_else {
+                    self.errors.push(LexError::BadDirective(format!("#{}", name.text), span));
+                    return;
+                }
+
+                let condition = !taken && (name.text == "else" || self.condition(tokens, line_number));
+                let conditional = self.conditionals.last_mut().unwrap();
+                conditional.active = condition;
+                conditional.taken |= condition;
+                conditional.has_else = name.text == "else";
+            }
+            _ => {
+                if self.conditionals.last().is_some_and(|c| c.span.file() == span.file()) {
+                    self.conditionals.pop();
+                } else {
+                    self.errors.push(LexError::BadDirective("#endif".to_string(), span));
+                }
+            }
+        }
+    }
+
+    /// #if, #ifdef, #ifndef, #elif の条件を評価する. 不正な条件はエラーを記録して偽とする
+    fn condition(&mut self, tokens: &[PpToken], line_number: usize) -> bool
+    {
+        let directive = tokens[1].text.clone();
+        let span = self.span_between(&tokens[0], &tokens[tokens.len() - 1]);
+        let result = match directive.as_str() {
+            "ifdef" | "ifndef" => match tokens.get(2) {
+                Some(name) if name.is_identifier() && tokens.len() == 3 => {
+                    let defined = self.find_macro(&name.text, line_number).is_some();
+                    Ok(defined == (directive == "ifdef"))
+                }
+                _ => Err(()),
+            },
+            _ => self.if_condition(&tokens[2..], line_number),
+        };
+
+        result.unwrap_or_else(|_| {
+            let text = PpToken::join(&tokens[2..]);
+            self.errors.push(LexError::BadDirective(format!("#{} {}", directive, text), span));
+            false
+        })
+    }
+
+    /// #if の条件. defined を置き換えてからマクロを展開し, 整数の定数式として評価する
+    fn if_condition(&mut self, tokens: &[PpToken], line_number: usize) -> Result<bool, ()>
+    {
+        let mut replaced = Vec::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            if tokens[i].text != "defined" {
+                replaced.push(tokens[i].clone());
+                i += 1;
+                continue;
+            }
+
+            // defined 名前 または defined(名前)
+            let texts: Vec<&str> = tokens[i + 1..].iter().take(3).map(|token| token.text.as_str()).collect();
+            let (name, length) = match texts.as_slice() {
+                ["(", name, ")", ..] => (*name, 4),
+                [name, ..] if *name != "(" => (*name, 2),
+                _ => return Err(()),
+            };
+            if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                return Err(());
+            }
+
+            let defined = self.find_macro(name, line_number).is_some();
+            replaced.push(tokens[i].with_text(if defined { "1" } else { "0" }.to_string()));
+            i += length;
+        }
+
+        let tokens = self.macro_replace(replaced);
+        let mut expression = ConstantExpression { tokens: &tokens, position: 0 };
+        let value = expression.logical_or()?;
+        if expression.position != tokens.len() {
+            return Err(());
+        }
+
+        Ok(value != 0)
+    }
+
+    /// -D で指定する形式 (名前=値 または 名前) でマクロを定義する. 値を省略した場合は 1 になる.
+    /// tokenize の前に呼び出す
+    pub fn define(&mut self, definition: &str) -> Result<(), LexError>
+    {
+        let (name, value) = definition.split_once('=').unwrap_or((definition, "1"));
+        let directive = format!("#define {} {}", name, value);
+
+        // エラーの位置は <command line> の列で表す
+        let file = match self.files.iter().position(|file| &**file == "<command line>") {
+            Some(file) => file,
+            None => {
+                self.files.push(Rc::from("<command line>"));
+                self.files.len() - 1
+            }
+        };
+        let locations: Vec<Location> = (1..=directive.chars().count())
+            .map(|column| Location { file, line: 1, column })
+            .collect();
+
+        // 0 行目に定義したものとして, ソースのすべての行で有効にする
+        let tokens = Lexer::pp_tokenize(&directive, &locations, 0);
+        let count = self.errors.len();
+        self.macro_define(&tokens, 0);
+        match self.errors.drain(count..).next() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
     }
 
     /// マクロ定義
     /// #define マクロ名 マクロの定義
-    fn macro_define(&mut self, line_count: usize)
+    /// #define マクロ名(引数, ...) マクロの定義
+    fn macro_define(&mut self, tokens: &[PpToken], line_number: usize)
     {
-        // '#' は既に読み込まれているので、define を読み込む
-        let mut define = String::new();
-        loop
-        {
-            let c = match self.next_char()
-            {
-                Some(c) => c,
-                None => break,
-            };
+        let macro_name = match tokens.get(2) {
+            Some(name) if name.is_identifier() => name.text.clone(),
+            name => {
+                let text = name.map_or(String::new(), |name| name.text.clone());
+                let span = self.span_between(&tokens[0], name.unwrap_or(&tokens[1]));
+                self.errors.push(LexError::BadDirective(format!("#define {}", text), span));
+                return;
+            }
+        };
+
+        // マクロ名の直後に空白なしで '(' がある場合は関数形式のマクロ
+        let (parameters, body_start) = match tokens.get(3) {
+            Some(paren) if paren.text == "(" && !paren.leading_space => {
+                match Lexer::macro_parameters(&tokens[4..]) {
+                    Some((parameters, length)) => (Some(parameters), 4 + length),
+                    None => {
+                        let span = self.span_between(&tokens[0], &tokens[tokens.len() - 1]);
+                        self.errors.push(LexError::BadDirective(format!("#define {}(", macro_name), span));
+                        return;
+                    }
+                }
+            }
+            _ => (None, 3),
+        };
 
-            if c == ' '
-            {
-                break;
+        // マクロの定義を取得する
+        let macro_value = PpToken::join(&tokens[body_start..]);
+
+        // 再定義した場合は以前の定義をここで終わらせる
+        self.end_macro(&macro_name, line_number);
+        self.macros.push(Macro {
+            name: macro_name,
+            parameters,
+            value: macro_value,
+            start_line: line_number,
+            end_line: usize::MAX,
+        });
+    }
+
+    /// 関数形式のマクロの引数名を読み込む. tokens は '(' の次から始まる.
+    /// 引数名と ')' までのトークン数を返す
+    fn macro_parameters(tokens: &[PpToken]) -> Option<(Vec<String>, usize)>
+    {
+        let mut parameters: Vec<String> = Vec::new();
+        if tokens.first()?.text == ")" {
+            return Some((parameters, 1));
+        }
+
+        let mut i = 0;
+        loop {
+            let name = tokens.get(i)?;
+            if !name.is_identifier() || parameters.contains(&name.text) {
+                return None;
             }
+            parameters.push(name.text.clone());
 
-            define.push(c);
+            match tokens.get(i + 1)?.text.as_str() {
+                "," => i += 2,
+                ")" => return Some((parameters, i + 2)),
+                _ => return None,
+            }
         }
+    }
 
-        if define != "define"
-        {
-            panic!("Unknown macro: {:?}", define);
+    /// マクロ定義の取り消し
+    /// #undef マクロ名
+    fn macro_undef(&mut self, tokens: &[PpToken], line_number: usize)
+    {
+        match tokens.get(2) {
+            Some(name) if name.is_identifier() => self.end_macro(&name.text, line_number),
+            name => {
+                let text = name.map_or(String::new(), |name| name.text.clone());
+                let span = self.span_between(&tokens[0], name.unwrap_or(&tokens[1]));
+                self.errors.push(LexError::BadDirective(format!("#undef {}", text), span));
+            }
         }
+    }
 
-        // マクロ名を取得する
-        let mut macro_name = String::new();
-        loop
-        {
-            let c = match self.next_char()
-            {
-                Some(c) => c,
-                None => break,
+    /// line_number の行で有効な name のマクロを line_number の行で終わらせる
+    fn end_macro(&mut self, name: &str, line_number: usize)
+    {
+        for m in self.macros.iter_mut() {
+            if m.name == name && m.is_active(line_number) {
+                m.end_line = line_number;
+            }
+        }
+    }
+
+    /// line_number の行で有効なマクロを探す
+    fn find_macro(&self, name: &str, line_number: usize) -> Option<&Macro>
+    {
+        self.macros.iter().find(|m| m.name == name && m.is_active(line_number))
+    }
+
+    /// トークン列のマクロを展開する.
+    /// 展開結果は後ろのトークンと合わせて再走査し, 展開中のマクロ名は hide_set で再展開を防ぐ
+    fn macro_replace(&mut self, tokens: Vec<PpToken>) -> Vec<PpToken>
+    {
+        let mut input: VecDeque<PpToken> = tokens.into();
+        let mut output = Vec::new();
+
+        while let Some(token) = input.pop_front() {
+            let found = if token.is_identifier() && !token.hide_set.contains(&token.text) {
+                self.find_macro(&token.text, token.line).cloned()
+            } else {
+                None
             };
 
-            if c == ' '
-            {
-                break;
+            let m = match found {
+                Some(m) => m,
+                None => {
+                    output.push(token);
+                    continue;
+                }
+            };
+
+            let mut hide_set = token.hide_set.clone();
+            let arguments = match &m.parameters {
+                None => None,
+                Some(parameters) => {
+                    // 関数形式のマクロは後ろに '(' が続く場合のみ展開する
+                    let paren = match input.iter().position(|t| t.text != "\n") {
+                        Some(i) if input[i].text == "(" => i,
+                        _ => {
+                            output.push(token);
+                            continue;
+                        }
+                    };
+                    input.drain(..=paren);
+
+                    let (arguments, right_paren) = match Lexer::macro_arguments(&mut input) {
+                        Some(result) => result,
+                        None => {
+                            let span = self.span_between(&token, &token);
+                            self.errors.push(LexError::BadMacroCall(format!("{} の ')' がありません", m.name), span));
+                            continue;
+                        }
+                    };
+
+                    // F() は引数なしとして扱う
+                    let arguments = if parameters.is_empty() && arguments.len() == 1 && arguments[0].is_empty() {
+                        Vec::new()
+                    } else {
+                        arguments
+                    };
+                    if arguments.len() != parameters.len() {
+                        let span = self.span_between(&token, &right_paren);
+                        self.errors.push(LexError::BadMacroCall(
+                            format!("{} の引数は {} 個ですが {} 個渡されました", m.name, parameters.len(), arguments.len()), span));
+                        continue;
+                    }
+
+                    // ')' の hide_set にもあるマクロ名だけを引き継ぐ
+                    hide_set.retain(|name| right_paren.hide_set.contains(name));
+                    Some(arguments)
+                }
+            };
+            hide_set.push(m.name.clone());
+
+            let replacement = self.substitute(&m, arguments.as_deref(), &token);
+            for mut t in replacement.into_iter().rev() {
+                // 前後のトークンとつながらないように空白を入れる
+                t.leading_space = true;
+                for name in hide_set.iter() {
+                    if !t.hide_set.contains(name) {
+                        t.hide_set.push(name.clone());
+                    }
+                }
+                input.push_front(t);
+            }
+        }
+
+        output
+    }
+
+    /// '(' の次から対応する ')' までを読み込み, ',' で区切った引数と ')' を返す
+    fn macro_arguments(input: &mut VecDeque<PpToken>) -> Option<(Vec<Vec<PpToken>>, PpToken)>
+    {
+        let mut arguments = vec![Vec::new()];
+        let mut depth = 0;
+        let mut leading_space = false;
+
+        loop {
+            let mut token = input.pop_front()?;
+            match token.text.as_str() {
+                // 引数中の改行は空白として扱う
+                "\n" => {
+                    leading_space = true;
+                    continue;
+                }
+                ")" if depth == 0 => return Some((arguments, token)),
+                "," if depth == 0 => {
+                    arguments.push(Vec::new());
+                    leading_space = false;
+                    continue;
+                }
+                "(" => depth += 1,
+                ")" => depth -= 1,
+                _ => {}
             }
 
-            macro_name.push(c);
+            token.leading_space |= leading_space;
+            leading_space = false;
+            arguments.last_mut().unwrap().push(token);
         }
+    }
 
-        // マクロの定義を取得する
-        let mut macro_value = String::new();
-        loop
-        {
-            let c = match self.next_char()
-            {
-                Some(c) => c,
-                None => break,
-            };
+    /// マクロの定義中の引数を置き換え, '#' と '##' を処理する.
+    /// 定義のトークンはマクロ名の位置を, 引数のトークンは引数の位置を引き継ぐ
+    fn substitute(&mut self, m: &Macro, arguments: Option<&[Vec<PpToken>]>, invocation: &PpToken) -> Vec<PpToken>
+    {
+        let locations = vec![invocation.locations[0]; m.value.chars().count()];
+        let body = Lexer::pp_tokenize(&m.value, &locations, invocation.line);
+        let argument = |token: Option<&PpToken>| -> Option<&Vec<PpToken>> {
+            let text = &token?.text;
+            let index = m.parameters.as_ref()?.iter().position(|name| name == text)?;
+            arguments.map(|arguments| &arguments[index])
+        };
+
+        let mut result: Vec<PpToken> = Vec::new();
+        let mut i = 0;
+        while i < body.len() {
+            let token = &body[i];
+
+            // # 引数 は引数を文字列にする
+            if token.text == "#" && m.parameters.is_some() {
+                if let Some(tokens) = argument(body.get(i + 1)) {
+                    result.push(invocation.with_text(PpToken::stringize(tokens)));
+                    i += 2;
+                    continue;
+                }
+            }
+
+            // A ## B は A の最後と B の先頭のトークンをつなげる
+            if token.text == "##" && !result.is_empty() && i + 1 < body.len() {
+                let rhs = match argument(body.get(i + 1)) {
+                    Some(tokens) => tokens.clone(),
+                    None => vec![body[i + 1].clone()],
+                };
+                let lhs = result.pop().unwrap();
+                result.extend(PpToken::paste(lhs, rhs));
+                i += 2;
+                continue;
+            }
+
+            match argument(Some(token)) {
+                // ## の左側の引数は展開しない. 空の引数は結合用に空のトークンを置く
+                Some(tokens) if body.get(i + 1).is_some_and(|next| next.text == "##") => {
+                    if tokens.is_empty() {
+                        result.push(invocation.with_text(String::new()));
+                    } else {
+                        result.extend(tokens.iter().cloned());
+                    }
+                }
+                // それ以外の引数は先に展開してから置き換える
+                Some(tokens) => {
+                    let tokens = tokens.clone();
+                    result.extend(self.macro_replace(tokens));
+                }
+                None => result.push(token.clone()),
+            }
+            i += 1;
+        }
+
+        result.retain(|token| !token.text.is_empty());
+        result
+    }
+
+    /// 2 つのトークンの間を覆う Span を作成する
+    fn span_between(&self, start: &PpToken, end: &PpToken) -> Span
+    {
+        self.span_at(&start.locations[0], &end.locations[end.locations.len() - 1])
+    }
+
+    // プリプロセッサ指令を処理し, マクロを置換する
+    fn preprocess(&mut self)
+    {
+        let mut tokens = Vec::new();
+        let file = PathBuf::from(&*self.files[0]);
+        let file = fs::canonicalize(&file).unwrap_or(file);
+        let lines = Lexer::split_lines(&self.sentence, &self.locations);
+
+        self.line_num = 0;
+        self.include_stack.push(file);
+        self.preprocess_lines(lines, &mut tokens);
+        self.include_stack.pop();
+
+        // 最後まで有効なマクロの範囲を確定する
+        for m in self.macros.iter_mut() {
+            if m.end_line == usize::MAX {
+                m.end_line = self.line_num + 1;
+            }
+        }
+
+        let tokens = self.macro_replace(tokens);
+
+        self.sentence.clear();
+        self.locations.clear();
+        for token in tokens {
+            if token.leading_space {
+                self.sentence.push(' ');
+                self.locations.push(token.locations[0]);
+            }
+            self.sentence.push_str(&token.text);
+            self.locations.extend(token.locations);
+        }
+    }
 
-            if c == '\n'
-            {
-                break;
+    /// 1 つのファイルの行を処理し, 出力するトークンを output に追加する
+    fn preprocess_lines(&mut self, lines: Vec<(String, Vec<Location>)>, output: &mut Vec<PpToken>)
+    {
+        let depth = self.conditionals.len();
+
+        // 行の先頭が '#' の行はプリプロセッサ指令として扱い, 出力からは取り除く
+        // 無効な分岐の行は字句解析の前に取り除く
+        for (line, locations) in lines
+        {
+            self.line_num += 1;
+            let line_number = self.line_num;
+            let line_tokens = Lexer::pp_tokenize(&line, &locations, line_number);
+            if line_tokens.first().is_some_and(|token| token.text == "#") {
+                self.directive(&line_tokens, line_number, output);
+            } else if self.is_active() {
+                output.extend(line_tokens);
             }
 
-            macro_value.push(c);
+            // 改行は位置情報ごと残す. 改行のない最後の行も次のファイルの行とつながらないようにする
+            output.push(PpToken {
+                text: "\n".to_string(),
+                locations: vec![locations[locations.len() - 1]],
+                line: line_number,
+                leading_space: false,
+                hide_set: Vec::new(),
+            });
         }
 
-        self.macros.push(Macro {
-            name: macro_name,
-            value: macro_value,
-            start_line: line_count,
-            end_line: self.line_num,
-        });
+        // ファイルの中で閉じられていない #if
+        for conditional in self.conditionals.split_off(depth) {
+            self.errors.push(LexError::UnterminatedConditional(conditional.span));
+        }
     }
 
-    // 定義したマクロを元に置換
-    fn macro_replace(&mut self)
+    /// ファイルのインクルード
+    /// #include "ファイル名" または #include <ファイル名>
+    fn include(&mut self, tokens: &[PpToken], line_number: usize, output: &mut Vec<PpToken>)
     {
-        if self.macros.is_empty() {
-            return;
+        let span = self.span_between(&tokens[0], &tokens[tokens.len() - 1]);
+
+        // どちらの形式でもない場合はマクロを展開してから読む
+        let mut operand = tokens[2..].to_vec();
+        if !operand.first().is_some_and(|token| token.text.starts_with('"') || token.text == "<") {
+            operand = self.macro_replace(operand);
         }
 
-        let mut new_sentence = String::new();
+        let (name, quoted) = match operand.as_slice() {
+            [token] if token.text.len() >= 2 && token.text.starts_with('"') && token.text.ends_with('"') =>
+                (token.text[1..token.text.len() - 1].to_string(), true),
+            [first, name @ .., last] if first.text == "<" && last.text == ">" && !name.is_empty() =>
+                (name.iter().map(|token| token.text.as_str()).collect(), false),
+            _ => {
+                let text = PpToken::join(&tokens[2..]);
+                self.errors.push(LexError::BadDirective(format!("#include {}", text), span));
+                return;
+            }
+        };
 
-        // マクロの定義後から undef までの行を取得し置換する
-        let lines = self.sentence.lines().collect::<Vec<&str>>();
-        for line in lines.iter()
-        {
-            let mut is_replaced = false;
-            for m in &self.macros
-            {
-                if line.contains(&m.name)
-                {
-                    let replaced_line = line.replace(&m.name, &m.value);
-                    new_sentence.push_str(&replaced_line);
-                    new_sentence.push('\n');
-                    is_replaced = true;
-                    break;
-                }
+        // "file" はインクルードするファイルのディレクトリから先に探す
+        let mut directories = Vec::new();
+        if quoted {
+            let file = &self.files[tokens[0].locations[0].file];
+            directories.push(Path::new(&**file).parent().unwrap_or(Path::new("")).to_path_buf());
+        }
+        directories.extend(self.include_directories.iter().cloned());
+
+        let path = match directories.iter().map(|directory| directory.join(&name)).find(|path| path.is_file()) {
+            Some(path) => path,
+            None => {
+                self.errors.push(LexError::IncludeNotFound(name, span));
+                return;
             }
+        };
+        let file = fs::canonicalize(&path).unwrap_or(path.clone());
 
-            if !is_replaced
-            {
-                new_sentence.push_str(line);
-                new_sentence.push('\n');
+        // #pragma once のファイルと, ガードのマクロが定義済みのファイルは読み飛ばす
+        if self.once_files.contains(&file) {
+            return;
+        }
+        if let Some(guard) = self.include_guards.get(&file) {
+            if self.find_macro(guard, line_number).is_some() {
+                return;
+            }
+        }
+        if self.include_stack.contains(&file) {
+            self.errors.push(LexError::RecursiveInclude(name, span));
+            return;
+        }
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => {
+                self.errors.push(LexError::IncludeNotFound(name, span));
+                return;
             }
+        };
+
+        // トークンの位置はインクルードしたファイル上の位置で表す
+        self.files.push(Rc::from(path.display().to_string()));
+        let locations = Lexer::locations_of(&text, self.files.len() - 1);
+        let (text, locations) = self.strip_comments(&text, &locations);
+        let lines = Lexer::split_lines(&text, &locations);
+        if let Some(guard) = Lexer::include_guard(&lines) {
+            self.include_guards.insert(file.clone(), guard);
         }
 
-        self.sentence = new_sentence;
+        self.include_stack.push(file);
+        self.preprocess_lines(lines, output);
+        self.include_stack.pop();
     }
 
-    // マクロを見つけて置換する
-    fn preprocess(&mut self)
+    /// ファイル全体が #ifndef 名前 (または #if !defined(名前)) と #endif で囲まれている場合, その名前を返す
+    fn include_guard(lines: &[(String, Vec<Location>)]) -> Option<String>
     {
-        // マクロは行の先頭に書かれていない場合はエラーとする
-        let mut line_count = 0;
-
-        loop
-        {
-            let c = match self.next_char()
-            {
-                Some(c) => c,
-                None => break,
-            };
-
-            match c
-            {
-                '\n' => {
-                    // 行末まで読み込んだら値をクリアする.
-                    self.token_str.clear();
-                    line_count += 1;
-                }
-                '#' => {
-                    // マクロの定義
-                    if self.token_str.trim().is_empty()
-                    {
-                        // 行の先頭の場合はマクロ定義
-                        self.macro_define(line_count);
+        let lines: Vec<Vec<String>> = lines.iter()
+            .map(|(line, locations)| Lexer::pp_tokenize(line, locations, 0).into_iter().map(|token| token.text).collect())
+            .filter(|texts: &Vec<String>| !texts.is_empty())
+            .collect();
+
+        let first: Vec<&str> = lines.first()?.iter().map(|text| text.as_str()).collect();
+        let guard = match first.as_slice() {
+            ["#", "ifndef", name] => name.to_string(),
+            ["#", "if", "!", "defined", "(", name, ")"] | ["#", "if", "!", "defined", name] => name.to_string(),
+            _ => return None,
+        };
+
+        // 最初の #if に対応する #endif が最後の行にある
+        let mut depth = 0;
+        for (i, texts) in lines.iter().enumerate() {
+            if texts.len() < 2 || texts[0] != "#" {
+                continue;
+            }
+            match texts[1].as_str() {
+                "if" | "ifdef" | "ifndef" => depth += 1,
+                "endif" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return (i == lines.len() - 1).then_some(guard);
                     }
                 }
-                _ => {
-                    self.add_char(c);
-                }
+                _ => {}
             }
         }
 
-        // マクロを置換
-        self.macro_replace();
-
-        // # で始まる行をすべて削除
-        self.sentence = self.sentence.lines().filter(|line| !line.trim().starts_with("#")).collect::<Vec<&str>>().join("\n");
+        None
     }
 
-    pub fn tokenize(&mut self)
+    /// ソースコードをトークンに分割する.
+    /// エラーがあっても最後まで読み進め, 見つかったエラーをすべて返す
+    pub fn tokenize(&mut self) -> Result<(), Vec<LexError>>
     {
+        self.errors.clear();
+
         // コメントを削除
         self.reset_position();
         self.remove_comments();
@@ -390,6 +1325,7 @@ impl Lexer
         self.reset_position();
 
         loop {
+            let start = self.position;
             let c = match self.next_char() {
                 Some(c) => c,
                 None => break,
@@ -413,10 +1349,10 @@ impl Lexer
                             self.add_char(c);
                         } else {
                             self.add_token();
-                            self.tokens.push(Token::Dot);
+                            self.push_token(Token::Dot, start);
                         }
                     }
-                ' ' =>
+                ' ' | '\t' | '\r' =>
                     {
                         self.add_token();
                     }
@@ -424,47 +1360,52 @@ impl Lexer
                     {
                         self.add_token();
 
-                        self.tokens.push(Token::LeftParen);
+                        self.push_token(Token::LeftParen, start);
                     }
                 ')' =>
                     {
                         self.add_token();
-                        self.tokens.push(Token::RightParen);
+                        self.push_token(Token::RightParen, start);
                     }
                 '{' =>
                     {
                         self.add_token();
-                        self.tokens.push(Token::LeftBrace);
+                        self.push_token(Token::LeftBrace, start);
                     }
                 '}' =>
                     {
                         self.add_token();
-                        self.tokens.push(Token::RightBrace);
+                        self.push_token(Token::RightBrace, start);
                     }
                 ';' =>
                     {
                         self.add_token();
-                        self.tokens.push(Token::Semicolon);
+                        self.push_token(Token::Semicolon, start);
                     }
                 ',' =>
                     {
                         self.add_token();
-                        self.tokens.push(Token::Comma);
+                        self.push_token(Token::Comma, start);
                     }
                 '[' =>
                     {
                         self.add_token();
-                        self.tokens.push(Token::LeftBracket);
+                        self.push_token(Token::LeftBracket, start);
                     }
                 ']' =>
                     {
                         self.add_token();
-                        self.tokens.push(Token::RightBracket);
+                        self.push_token(Token::RightBracket, start);
                     }
                 '\n' =>
                     {
                         self.add_token();
                     }
+                '"' | '\'' =>
+                    {
+                        self.add_token();
+                        self.literal(c, start);
+                    }
                 '=' =>
                     {
                         // 次のトークンを取得して、'=' かどうか判定
@@ -473,13 +1414,15 @@ impl Lexer
                             Some('=') =>
                                 {
                                     self.add_token();
-                                    self.tokens.push(Token::Operator(Operator::Equal));
+                                    self.push_token(Token::Operator(Operator::Equal), start);
                                 }
                             _ =>
                                 {
                                     self.add_token();
-                                    self.tokens.push(Token::Assign);
-                                    self.back_char();
+                                    if next_char.is_some() {
+                                        self.back_char();
+                                    }
+                                    self.push_token(Token::Assign, start);
                                 }
                         }
                     }
@@ -491,11 +1434,16 @@ impl Lexer
                             Some('|') =>
                                 {
                                     self.add_token();
-                                    self.tokens.push(Token::Operator(Operator::LogicalOr));
+                                    self.push_token(Token::Operator(Operator::LogicalOr), start);
                                 }
                             _ =>
                                 {
-                                    panic!("Unknown character: {:?}", c);
+                                    // 単独の '|', '&' は未対応
+                                    self.add_token();
+                                    if next_char.is_some() {
+                                        self.back_char();
+                                    }
+                                    self.unknown_character(c, start);
                                 }
                         }
                     }
@@ -507,13 +1455,15 @@ impl Lexer
                             Some('=') =>
                                 {
                                     self.add_token();
-                                    self.tokens.push(Token::Operator(Operator::NotEqual));
+                                    self.push_token(Token::Operator(Operator::NotEqual), start);
                                 }
                             _ =>
                                 {
                                     self.add_token();
-                                    self.tokens.push(Token::UnaryOperator(UnaryOperator::LogicalNot));
-                                    self.back_char();
+                                    if next_char.is_some() {
+                                        self.back_char();
+                                    }
+                                    self.push_token(Token::UnaryOperator(UnaryOperator::LogicalNot), start);
                                 }
                         }
                     }
@@ -521,30 +1471,26 @@ impl Lexer
                     {
                         self.add_token();
 
-                        self.tokens.push(Token::Operator(match c {
+                        self.push_token(Token::Operator(match c {
                             '+' => Operator::Plus,
                             '*' => Operator::Multiply,
                             '/' => Operator::Divide,
                             '%' => Operator::Modulo,
                             _ => unreachable!(),
-                        }));
+                        }), start);
                     }
                 '-' =>
                     {
                         self.add_token();
 
                         // 一個前のトークンが Identifier か定数の場合は Operator::Minus
-                        if let Some(token) = self.tokens.last() {
-                            match token {
-                                Token::Identifier(_) | Token::Constant(_) => {
-                                    self.tokens.push(Token::Operator(Operator::Minus));
-                                }
-                                _ => {
-                                    self.tokens.push(Token::UnaryOperator(UnaryOperator::Minus));
-                                }
+                        match self.tokens.last() {
+                            Some(Token::Identifier(_) | Token::Constant(_) | Token::StringLiteral(_) | Token::CharLiteral(_)) => {
+                                self.push_token(Token::Operator(Operator::Minus), start);
+                            }
+                            _ => {
+                                self.push_token(Token::UnaryOperator(UnaryOperator::Minus), start);
                             }
-                        } else {
-                            self.tokens.push(Token::UnaryOperator(UnaryOperator::Minus));
                         }
                     }
                 '&' =>
@@ -555,11 +1501,16 @@ impl Lexer
                             Some('&') =>
                                 {
                                     self.add_token();
-                                    self.tokens.push(Token::Operator(Operator::LogicalAnd));
+                                    self.push_token(Token::Operator(Operator::LogicalAnd), start);
                                 }
                             _ =>
                                 {
-                                    panic!("Unknown character: {:?}", c);
+                                    // 単独の '|', '&' は未対応
+                                    self.add_token();
+                                    if next_char.is_some() {
+                                        self.back_char();
+                                    }
+                                    self.unknown_character(c, start);
                                 }
                         }
                     }
@@ -571,40 +1522,155 @@ impl Lexer
                             Some('=') =>
                                 {
                                     self.add_token();
-                                    self.tokens.push(Token::Operator(match c {
+                                    self.push_token(Token::Operator(match c {
                                         '>' => Operator::GreaterThanOrEqual,
                                         '<' => Operator::LessThanOrEqual,
                                         _ => unreachable!(),
-                                    }));
+                                    }), start);
                                 }
                             _ =>
                                 {
                                     self.add_token();
-                                    self.tokens.push(Token::Operator(match c {
+                                    if next_char.is_some() {
+                                        self.back_char();
+                                    }
+                                    self.push_token(Token::Operator(match c {
                                         '>' => Operator::GreaterThan,
                                         '<' => Operator::LessThan,
                                         _ => unreachable!(),
-                                    }));
-                                    self.back_char();
+                                    }), start);
                                 }
                         }
                     }
                 _ =>
                     {
-                        panic!("Unknown character: {:?}", c);
+                        self.add_token();
+                        self.unknown_character(c, start);
                     }
             }
         }
+
+        // 最後のトークンを追加
+        self.add_token();
+
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors.clone())
+        }
+    }
+
+    /// 文字列か文字のリテラルを読み込む. quote は読み込み済みの開きの引用符
+    fn literal(&mut self, quote: char, start: usize)
+    {
+        let mut value = String::new();
+        let mut malformed = false;
+        loop {
+            match self.next_char() {
+                Some(c) if c == quote => break,
+                Some('\\') => match self.escape_sequence() {
+                    Some(c) => value.push(c),
+                    None => malformed = true,
+                },
+                Some('\n') => {
+                    // 改行は通常の文字として読み直す
+                    self.back_char();
+                    self.errors.push(LexError::UnterminatedLiteral(self.span_of(start, start)));
+                    return;
+                }
+                None => {
+                    self.errors.push(LexError::UnterminatedLiteral(self.span_of(start, start)));
+                    return;
+                }
+                Some(c) => value.push(c),
+            }
+        }
+
+        let span = self.span_of(start, self.position - 1);
+        let mut chars = value.chars();
+        match (quote, chars.next(), chars.next()) {
+            ('"', _, _) if !malformed => {
+                self.tokens.push(Token::StringLiteral(value));
+                self.spans.push(span);
+            }
+            ('\'', Some(c), None) if !malformed && c.is_ascii() => {
+                self.tokens.push(Token::CharLiteral(c));
+                self.spans.push(span);
+            }
+            _ => {
+                let literal = self.sentence.chars().skip(start).take(self.position - start).collect();
+                self.errors.push(LexError::MalformedLiteral(literal, span));
+            }
+        }
+    }
+
+    /// '\\' の後のエスケープシーケンスを読み込む. 不正な場合は None を返す
+    fn escape_sequence(&mut self) -> Option<char>
+    {
+        // 改行は読み込まず, 閉じられていないリテラルとして扱う
+        let c = self.peek_char().filter(|c| *c != '\n')?;
+        self.next_char();
+
+        let value = match c {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            'a' => '\x07',
+            'b' => '\x08',
+            'f' => '\x0c',
+            'v' => '\x0b',
+            '\\' | '\'' | '"' | '?' => c,
+            // 8 進数は 3 桁まで
+            '0'..='7' => {
+                let mut digits = String::from(c);
+                while digits.len() < 3 {
+                    match self.peek_char() {
+                        Some(c @ '0'..='7') => {
+                            digits.push(c);
+                            self.next_char();
+                        }
+                        _ => break,
+                    }
+                }
+                char::from_u32(u32::from_str_radix(&digits, 8).ok()?)?
+            }
+            'x' => {
+                let mut digits = String::new();
+                while let Some(c) = self.peek_char().filter(|c| c.is_ascii_hexdigit()) {
+                    digits.push(c);
+                    self.next_char();
+                }
+                char::from_u32(u32::from_str_radix(&digits, 16).ok()?)?
+            }
+            _ => return None,
+        };
+
+        // char は符号付き 8 ビットのため ASCII の範囲に限る
+        value.is_ascii().then_some(value)
+    }
+
+    fn unknown_character(&mut self, c: char, start: usize)
+    {
+        let span = self.span_of(start, start);
+        self.errors.push(LexError::UnknownCharacter(c, span));
     }
 
     fn add_token(&mut self)
     {
         // トークンを追加
         if !self.token_str.is_empty() {
+            let end = self.token_start + self.token_str.chars().count() - 1;
+            let span = self.span_of(self.token_start, end);
+
             if let Some(token) = Token::from_keyword(&self.token_str) {
                 self.tokens.push(token);
+                self.spans.push(span);
+            } else if Token::is_numeric(&self.token_str) {
+                // 数字で始まるが数値として解釈できない
+                self.errors.push(LexError::MalformedNumber(self.token_str.clone(), span));
             } else {
                 self.tokens.push(Token::Identifier(self.token_str.clone()));
+                self.spans.push(span);
             }
 
             // トークン文字列をクリア
@@ -614,6 +1680,9 @@ impl Lexer
 
     fn add_char(&mut self, c: char)
     {
+        if self.token_str.is_empty() {
+            self.token_start = self.position - 1;
+        }
         self.token_str.push(c);
     }
 
@@ -656,6 +1725,144 @@ impl Lexer
     pub fn tokens(&self) -> &Vec<Token> {
         &self.tokens
     }
+
+    /// 各トークンのソース上の位置. tokens() と同じ順番で並ぶ
+    pub fn spans(&self) -> &Vec<Span> {
+        &self.spans
+    }
+}
+
+/// #if の条件の整数の定数式. マクロ展開後に残った識別子は 0 として扱う
+struct ConstantExpression<'a>
+{
+    tokens: &'a [PpToken],
+    position: usize,
+}
+
+impl ConstantExpression<'_>
+{
+    fn next_is(&self, text: &str) -> bool
+    {
+        self.tokens.get(self.position).is_some_and(|token| token.text == text)
+    }
+
+    /// 次のトークンが operators のいずれかなら読み進めて返す
+    fn next_operator(&mut self, operators: &[&'static str]) -> Option<&'static str>
+    {
+        let operator = operators.iter().find(|operator| self.next_is(operator))?;
+        self.position += 1;
+        Some(operator)
+    }
+
+    fn logical_or(&mut self) -> Result<i64, ()>
+    {
+        let mut value = self.logical_and()?;
+        while self.next_operator(&["||"]).is_some() {
+            let rhs = self.logical_and()?;
+            value = (value != 0 || rhs != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn logical_and(&mut self) -> Result<i64, ()>
+    {
+        let mut value = self.equality()?;
+        while self.next_operator(&["&&"]).is_some() {
+            let rhs = self.equality()?;
+            value = (value != 0 && rhs != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn equality(&mut self) -> Result<i64, ()>
+    {
+        let mut value = self.relational()?;
+        while let Some(operator) = self.next_operator(&["==", "!="]) {
+            let rhs = self.relational()?;
+            value = match operator {
+                "==" => (value == rhs) as i64,
+                _ => (value != rhs) as i64,
+            };
+        }
+        Ok(value)
+    }
+
+    fn relational(&mut self) -> Result<i64, ()>
+    {
+        let mut value = self.additive()?;
+        while let Some(operator) = self.next_operator(&["<=", ">=", "<", ">"]) {
+            let rhs = self.additive()?;
+            value = match operator {
+                "<=" => (value <= rhs) as i64,
+                ">=" => (value >= rhs) as i64,
+                "<" => (value < rhs) as i64,
+                _ => (value > rhs) as i64,
+            };
+        }
+        Ok(value)
+    }
+
+    fn additive(&mut self) -> Result<i64, ()>
+    {
+        let mut value = self.multiplicative()?;
+        while let Some(operator) = self.next_operator(&["+", "-"]) {
+            let rhs = self.multiplicative()?;
+            value = match operator {
+                "+" => value.wrapping_add(rhs),
+                _ => value.wrapping_sub(rhs),
+            };
+        }
+        Ok(value)
+    }
+
+    fn multiplicative(&mut self) -> Result<i64, ()>
+    {
+        let mut value = self.unary()?;
+        while let Some(operator) = self.next_operator(&["*", "/", "%"]) {
+            let rhs = self.unary()?;
+            value = match operator {
+                "*" => value.wrapping_mul(rhs),
+                // 0 による除算はエラー
+                "/" => value.checked_div(rhs).ok_or(())?,
+                _ => value.checked_rem(rhs).ok_or(())?,
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, ()>
+    {
+        match self.next_operator(&["!", "-", "+"]) {
+            Some("!") => Ok((self.unary()? == 0) as i64),
+            Some("-") => Ok(self.unary()?.wrapping_neg()),
+            Some(_) => self.unary(),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, ()>
+    {
+        if self.next_operator(&["("]).is_some() {
+            let value = self.logical_or()?;
+            return match self.next_operator(&[")"]) {
+                Some(_) => Ok(value),
+                None => Err(()),
+            };
+        }
+
+        let token = self.tokens.get(self.position).ok_or(())?;
+        self.position += 1;
+        if token.is_identifier() {
+            return Ok(0);
+        }
+
+        // 10 進数と 16 進数の整数. 末尾の u, l は無視する
+        let text = token.text.trim_end_matches(['u', 'U', 'l', 'L']);
+        match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            Some(hex) => i64::from_str_radix(hex, 16).map_err(|_| ()),
+            None => text.parse::<i64>().map_err(|_| ()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -717,7 +1924,7 @@ int main() {
 ".to_string();
 
         let mut lexer = Lexer::new(sentence);
-        lexer.tokenize();
+        lexer.tokenize().unwrap();
         let tokens = lexer.tokens();
 
         let result = vec![
@@ -967,4 +2174,444 @@ int main() {
             assert_eq!(token, &result[i]);
         }
     }
+
+    #[test]
+    fn test_token_spans() {
+        let sentence = "#define N 10
+/* コメント
+ */ int x = N;
+int main() { // コメント
+    return x >= 1;
+}
+".to_string();
+
+        let mut lexer = Lexer::with_file_name(sentence, "test.c");
+        lexer.tokenize().unwrap();
+        let tokens = lexer.tokens();
+        let spans = lexer.spans();
+        assert_eq!(tokens.len(), spans.len());
+
+        // (トークン, 行, 列, 終了列)
+        let result = vec![
+            (Token::Type(ValueType::Int), 3, 5, 7),
+            (Token::Identifier("x".to_string()), 3, 9, 9),
+            (Token::Assign, 3, 11, 11),
+            // マクロから展開されたトークンはマクロ名の位置を指す
+            (Token::Constant(Constant::Integer(10)), 3, 13, 13),
+            (Token::Semicolon, 3, 14, 14),
+            (Token::Type(ValueType::Int), 4, 1, 3),
+            (Token::Identifier("main".to_string()), 4, 5, 8),
+            (Token::LeftParen, 4, 9, 9),
+            (Token::RightParen, 4, 10, 10),
+            (Token::LeftBrace, 4, 12, 12),
+            (Token::Return, 5, 5, 10),
+            (Token::Identifier("x".to_string()), 5, 12, 12),
+            (Token::Operator(Operator::GreaterThanOrEqual), 5, 14, 15),
+            (Token::Constant(Constant::Integer(1)), 5, 17, 17),
+            (Token::Semicolon, 5, 18, 18),
+            (Token::RightBrace, 6, 1, 1),
+        ];
+
+        assert_eq!(tokens.len(), result.len());
+        for (i, (token, line, column, end_column)) in result.iter().enumerate() {
+            assert_eq!(&tokens[i], token);
+            assert_eq!(spans[i].file(), "test.c");
+            assert_eq!(spans[i].line(), *line, "{:?}", token);
+            assert_eq!(spans[i].column(), *column, "{:?}", token);
+            assert_eq!(spans[i].end_line(), *line, "{:?}", token);
+            assert_eq!(spans[i].end_column(), *end_column, "{:?}", token);
+        }
+        assert_eq!(spans[0].to_string(), "test.c:3:5");
+    }
+
+    #[test]
+    fn test_whitespace_and_crlf() {
+        let sentence = "int\tx = 1;\r\nint y = x;\r\n".to_string();
+
+        let mut lexer = Lexer::new(sentence);
+        lexer.tokenize().unwrap();
+
+        let result = vec![
+            Token::Type(ValueType::Int),
+            Token::Identifier("x".to_string()),
+            Token::Assign,
+            Token::Constant(Constant::Integer(1)),
+            Token::Semicolon,
+            Token::Type(ValueType::Int),
+            Token::Identifier("y".to_string()),
+            Token::Assign,
+            Token::Identifier("x".to_string()),
+            Token::Semicolon,
+        ];
+        assert_eq!(lexer.tokens(), &result);
+        assert_eq!(lexer.spans()[5].line(), 2);
+    }
+
+    #[test]
+    fn test_lex_errors() {
+        let sentence = "#include <stdio.h>
+int a = 1 & 2;
+int b = 12abc;
+int c = 99999999999;
+int d = a | b;
+int e = 1 $ 2;
+/* 閉じていないコメント
+".to_string();
+
+        let mut lexer = Lexer::new(sentence);
+        let errors = lexer.tokenize().unwrap_err();
+
+        // すべてのエラーが報告される
+        assert_eq!(errors.len(), 7);
+        assert!(matches!(&errors[0], LexError::UnterminatedBlockComment(span) if span.line() == 7));
+        assert!(matches!(&errors[1], LexError::IncludeNotFound(f, span) if f == "stdio.h" && span.line() == 1));
+        assert!(matches!(&errors[2], LexError::UnknownCharacter('&', span) if span.line() == 2 && span.column() == 11));
+        assert!(matches!(&errors[3], LexError::MalformedNumber(n, span) if n == "12abc" && span.line() == 3));
+        assert!(matches!(&errors[4], LexError::MalformedNumber(n, _) if n == "99999999999"));
+        assert!(matches!(&errors[5], LexError::UnknownCharacter('|', span) if span.line() == 5));
+        assert!(matches!(&errors[6], LexError::UnknownCharacter('$', span) if span.line() == 6));
+
+        // エラーの後も字句解析を続ける
+        assert!(lexer.tokens().contains(&Token::Identifier("e".to_string())));
+    }
+
+    #[test]
+    fn test_define_without_value() {
+        let sentence = "#define EMPTY
+#define N 3
+int x = N;".to_string();
+
+        let mut lexer = Lexer::new(sentence);
+        lexer.tokenize().unwrap();
+
+        let result = vec![
+            Token::Type(ValueType::Int),
+            Token::Identifier("x".to_string()),
+            Token::Assign,
+            Token::Constant(Constant::Integer(3)),
+            Token::Semicolon,
+        ];
+        assert_eq!(lexer.tokens(), &result);
+    }
+
+    /// トークンを識別子と定数の文字列に変換する
+    fn token_texts(lexer: &Lexer) -> Vec<String> {
+        lexer.tokens().iter().map(|token| match token {
+            Token::Identifier(name) => name.clone(),
+            Token::Constant(Constant::Integer(value)) => value.to_string(),
+            token => format!("{:?}", token),
+        }).collect()
+    }
+
+    #[test]
+    fn test_macro_identifier_boundary() {
+        let sentence = "#define N 10
+int INDEX = N;
+int count_N = N1 + N;".to_string();
+
+        let mut lexer = Lexer::new(sentence);
+        lexer.tokenize().unwrap();
+
+        let texts = token_texts(&lexer);
+        assert!(texts.contains(&"INDEX".to_string()));
+        assert!(texts.contains(&"count_N".to_string()));
+        assert!(texts.contains(&"N1".to_string()));
+        assert_eq!(texts.iter().filter(|text| *text == "10").count(), 2);
+    }
+
+    #[test]
+    fn test_macro_rescan() {
+        let sentence = "#define WIDTH 3
+#define HEIGHT 4
+#define AREA WIDTH * HEIGHT
+#define SELF SELF + 1
+#define A B
+#define B A
+int x = AREA + WIDTH;
+int y = SELF;
+int z = A;".to_string();
+
+        let mut lexer = Lexer::new(sentence);
+        lexer.tokenize().unwrap();
+
+        assert_eq!(token_texts(&lexer), vec![
+            "Type(Int)", "x", "Assign", "3", "Operator(Multiply)", "4", "Operator(Plus)", "3", "Semicolon",
+            // 自分自身を含むマクロは再展開しない
+            "Type(Int)", "y", "Assign", "SELF", "Operator(Plus)", "1", "Semicolon",
+            "Type(Int)", "z", "Assign", "A", "Semicolon",
+        ]);
+    }
+
+    #[test]
+    fn test_macro_undef() {
+        let sentence = "int a = N;
+#define N 1
+int b = N;
+#undef N
+int c = N;
+#define N 2
+int d = N;".to_string();
+
+        let mut lexer = Lexer::new(sentence);
+        lexer.tokenize().unwrap();
+
+        let texts = token_texts(&lexer);
+        let values: Vec<&String> = texts.iter().filter(|text| *text == "N" || *text == "1" || *text == "2").collect();
+        assert_eq!(values, vec!["N", "1", "N", "2"]);
+
+        // 有効範囲は #define の行から #undef か再定義の行まで
+        let ranges: Vec<(usize, usize)> = lexer.macros.iter().map(|m| (m.start_line, m.end_line)).collect();
+        assert_eq!(ranges, vec![(2, 4), (6, 8)]);
+    }
+
+    /// プリプロセッサだけを通し, 空白を 1 つにそろえた結果を返す
+    fn preprocessed(sentence: &str) -> String {
+        let mut lexer = Lexer::new(sentence.to_string());
+        lexer.remove_comments();
+        lexer.preprocess();
+        assert!(lexer.errors.is_empty(), "{:?}", lexer.errors);
+        lexer.sentence.split_whitespace().collect::<Vec<&str>>().join(" ")
+    }
+
+    #[test]
+    fn test_function_like_macro() {
+        let sentence = "#define N 3
+#define SQUARE(x) ((x) * (x))
+#define ADD(a, b) (a + b)
+#define ZERO() 0
+int ADD = SQUARE(y + 1);
+int z = ADD(N, ADD(1,
+                   SQUARE(2))) + ZERO();";
+
+        assert_eq!(preprocessed(sentence),
+                   "int ADD = ( ( y + 1 ) * ( y + 1 ) ); int z = ( 3 + ( 1 + ( ( 2 ) * ( 2 ) ) ) ) + 0;");
+    }
+
+    #[test]
+    fn test_macro_stringize_and_paste() {
+        let sentence = r#"#define N 3
+#define STR(x) #x
+#define XSTR(x) STR(x)
+#define CAT(a, b) a ## b
+#define VALUE value ## N
+STR(N + 1) XSTR(N) STR("a\n")
+CAT(value, 1) CAT(, x) CAT(N, 2) CAT(N,) VALUE"#;
+
+        assert_eq!(preprocessed(sentence), r#""N + 1" "3" "\"a\\n\"" value1 x N2 3 valueN"#);
+    }
+
+    #[test]
+    fn test_macro_call_errors() {
+        let sentence = "#define ADD(a, b) (a + b)
+#define BAD(a, ) a
+int x = ADD(1);
+int y = ADD(1, 2";
+
+        let mut lexer = Lexer::new(sentence.to_string());
+        let errors = lexer.tokenize().unwrap_err();
+
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(&errors[0], LexError::BadDirective(d, span) if d == "#define BAD(" && span.line() == 2));
+        assert!(matches!(&errors[1], LexError::BadMacroCall(_, span) if span.line() == 3 && span.column() == 9));
+        assert!(matches!(&errors[2], LexError::BadMacroCall(_, span) if span.line() == 4));
+    }
+
+    #[test]
+    fn test_conditional_compilation() {
+        let sentence = "#define A 1
+#ifdef A
+int a = 1;
+#ifndef A
+int b = 2;
+#else
+#define B 2
+int c = B;
+#endif
+#else
+#define C 3
+int d = 4 $;
+#endif
+#if defined(C) || defined B && A + B * 2 == 5
+int e = 5;
+#elif 1
+int f = 6;
+#else
+int g = 7;
+#endif
+#if UNDEFINED
+int h = 8;
+#elif (10 - 4) / 3 >= 2 && !defined C
+int i = 9;
+#endif";
+
+        assert_eq!(preprocessed(sentence), "int a = 1; int c = 2; int e = 5; int i = 9;");
+    }
+
+    #[test]
+    fn test_predefined_macros() {
+        let sentence = "#ifdef DEBUG
+int debug = LEVEL;
+#endif
+#if LEVEL > 1
+int verbose = SQUARE(LEVEL);
+#endif";
+
+        let mut lexer = Lexer::new(sentence.to_string());
+        lexer.define("DEBUG").unwrap();
+        lexer.define("LEVEL=2").unwrap();
+        lexer.define("SQUARE(x)=x * x").unwrap();
+        lexer.remove_comments();
+        lexer.preprocess();
+        assert!(lexer.errors.is_empty(), "{:?}", lexer.errors);
+        assert_eq!(lexer.sentence.split_whitespace().collect::<Vec<&str>>().join(" "),
+                   "int debug = 2; int verbose = 2 * 2;");
+
+        // 不正な定義はエラーを返す
+        let error = lexer.define("1X=2").unwrap_err();
+        assert!(matches!(&error, LexError::BadDirective(_, span) if span.file() == "<command line>"));
+    }
+
+    #[test]
+    fn test_conditional_errors() {
+        let sentence = "#endif
+#if 1 +
+#else
+#else
+#endif
+#if 1 / 0
+#endif
+#ifdef
+#endif
+#if 1
+int x = 1;";
+
+        let mut lexer = Lexer::new(sentence.to_string());
+        let errors = lexer.tokenize().unwrap_err();
+
+        assert_eq!(errors.len(), 6);
+        assert!(matches!(&errors[0], LexError::BadDirective(d, span) if d == "#endif" && span.line() == 1));
+        assert!(matches!(&errors[1], LexError::BadDirective(d, span) if d == "#if 1 +" && span.line() == 2));
+        assert!(matches!(&errors[2], LexError::BadDirective(d, span) if d == "#else" && span.line() == 4));
+        assert!(matches!(&errors[3], LexError::BadDirective(d, _) if d == "#if 1 / 0"));
+        assert!(matches!(&errors[4], LexError::BadDirective(d, span) if d == "#ifdef " && span.line() == 8));
+        assert!(matches!(&errors[5], LexError::UnterminatedConditional(span) if span.line() == 10));
+    }
+
+    fn samples_directory() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("samples")
+    }
+
+    #[test]
+    fn test_include() {
+        let path = samples_directory().join("include.txt");
+        let sentence = fs::read_to_string(&path).unwrap();
+
+        let mut lexer = Lexer::with_file_name(sentence, &path.display().to_string());
+        lexer.tokenize().unwrap();
+
+        // インクルードガードと #pragma once で定義は 1 回だけになる
+        let texts = token_texts(&lexer);
+        assert_eq!(texts.iter().filter(|text| *text == "square").count(), 2);
+        assert_eq!(texts.iter().filter(|text| *text == "once_value").count(), 2);
+
+        // ヘッダのトークンはヘッダ上の位置を指す
+        let index = texts.iter().position(|text| text == "square").unwrap();
+        let span = &lexer.spans()[index];
+        assert!(span.file().ends_with("util.h"), "{}", span.file());
+        assert_eq!((span.line(), span.column()), (7, 5));
+
+        // ヘッダのマクロを展開したトークンは展開した位置を指す
+        let index = texts.iter().position(|text| text == "3").unwrap();
+        let span = &lexer.spans()[index];
+        assert!(span.file().ends_with("include.txt"), "{}", span.file());
+        assert_eq!(span.line(), 11);
+    }
+
+    #[test]
+    fn test_include_directories() {
+        let sentence = "#define HEADER <extra.h>
+#include HEADER
+int x = EXTRA;
+#include \"extra.h\"".to_string();
+
+        let mut lexer = Lexer::with_file_name(sentence, "test.c");
+        lexer.add_include_directory(samples_directory().join("include").join("search"));
+        lexer.tokenize().unwrap();
+        assert!(token_texts(&lexer).contains(&"7".to_string()));
+
+        // <file> はインクルードするファイルのディレクトリを探さない
+        let path = samples_directory().join("test.c");
+        let sentence = "#include <include.txt>".to_string();
+        let mut lexer = Lexer::with_file_name(sentence, &path.display().to_string());
+        let errors = lexer.tokenize().unwrap_err();
+        assert!(matches!(&errors[0], LexError::IncludeNotFound(f, span) if f == "include.txt" && span.line() == 1));
+    }
+
+    #[test]
+    fn test_recursive_include() {
+        let path = samples_directory().join("test.c");
+        let sentence = "#include \"include/cycle_a.h\"
+#include include.txt".to_string();
+
+        let mut lexer = Lexer::with_file_name(sentence, &path.display().to_string());
+        let errors = lexer.tokenize().unwrap_err();
+
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(&errors[0], LexError::RecursiveInclude(f, span)
+            if f == "cycle_a.h" && span.file().ends_with("cycle_b.h") && span.line() == 1));
+        assert!(matches!(&errors[1], LexError::BadDirective(d, _) if d == "#include include.txt"));
+    }
+
+    #[test]
+    fn test_string_and_char_literals() {
+        let sentence = r#"#define GREETING "N // not a comment"
+char s[] = GREETING; // comment
+char c = 'a' - '\n';
+char e[] = "\t\"\\\0\101\x42?";"#.to_string();
+
+        let mut lexer = Lexer::new(sentence);
+        lexer.tokenize().unwrap();
+
+        let tokens = lexer.tokens();
+        assert_eq!(tokens[0], Token::Type(ValueType::Char));
+        assert_eq!(tokens[5], Token::StringLiteral("N // not a comment".to_string()));
+        assert_eq!(&tokens[7..14], &[
+            Token::Type(ValueType::Char),
+            Token::Identifier("c".to_string()),
+            Token::Assign,
+            Token::CharLiteral('a'),
+            Token::Operator(Operator::Minus),
+            Token::CharLiteral('\n'),
+            Token::Semicolon,
+        ]);
+        assert_eq!(tokens[19], Token::StringLiteral("\t\"\\\0AB?".to_string()));
+
+        // 文字列の位置は引用符を含む
+        let span = &lexer.spans()[19];
+        assert_eq!((span.line(), span.column(), span.end_column()), (4, 12, 30));
+    }
+
+    #[test]
+    fn test_literal_errors() {
+        let sentence = r#"char a = 'ab';
+char b = '\q';
+char c[] = "\x80";
+char d[] = "open
+char e = '';
+char f = 'x"#.to_string();
+
+        let mut lexer = Lexer::new(sentence);
+        let errors = lexer.tokenize().unwrap_err();
+
+        assert_eq!(errors.len(), 6);
+        assert!(matches!(&errors[0], LexError::MalformedLiteral(l, span) if l == "'ab'" && span.line() == 1));
+        assert!(matches!(&errors[1], LexError::MalformedLiteral(l, _) if l == r"'\q'"));
+        assert!(matches!(&errors[2], LexError::MalformedLiteral(l, _) if l == r#""\x80""#));
+        assert!(matches!(&errors[3], LexError::UnterminatedLiteral(span) if span.line() == 4 && span.column() == 12));
+        assert!(matches!(&errors[4], LexError::MalformedLiteral(l, _) if l == "''"));
+        assert!(matches!(&errors[5], LexError::UnterminatedLiteral(span) if span.line() == 6));
+
+        // 閉じられていない文字列の次の行から字句解析を続ける
+        assert!(lexer.tokens().contains(&Token::Identifier("e".to_string())));
+    }
 }
@@ -1,9 +1,17 @@
 pub mod lexical;
 pub mod parser;
 pub mod interpreter;
+pub mod builtin;
+pub mod resolver;
+pub mod bytecode;
+pub mod vm;
 pub mod tree_viewer;
+#[cfg(feature = "llvm")]
 pub mod llvm_ir;
 
+#[cfg(all(test, feature = "llvm"))]
+mod differential;
+
 #[cfg(test)]
 mod test
 {
@@ -28,14 +36,14 @@ int main() {
 ");
 
         let mut lexer = Lexer::new(input);
-        lexer.tokenize();
+        lexer.tokenize().unwrap();
 
         let tokens = lexer.tokens().clone();
         let mut parser = parser::Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut interpreter = interpreter::Interpreter::new(parser.roots());
-        interpreter.run();
+        interpreter.run().unwrap();
 
         let variables = interpreter.global_variables();
 
@@ -1,32 +1,111 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
+use inkwell::basic_block::BasicBlock;
 use inkwell::builder::{Builder, BuilderError};
 use inkwell::context::Context;
 use inkwell::module::Module;
-use inkwell::values::{CallSiteValue, FunctionValue, InstructionValue, PointerValue};
-use inkwell::OptimizationLevel;
+use inkwell::module::Linkage;
+use inkwell::values::{ArrayValue, BasicMetadataValueEnum, BasicValueEnum, CallSiteValue, FloatValue, FunctionValue, InstructionValue, IntValue, PointerValue};
+use inkwell::{AddressSpace, FloatPredicate, IntPredicate, OptimizationLevel};
 use std::error::Error;
 use std::path::Path;
 use std::rc::Rc;
+use inkwell::passes::PassBuilderOptions;
 use inkwell::execution_engine::ExecutionEngine;
-use inkwell::types::{AnyType, AnyTypeEnum, BasicType, BasicTypeEnum};
-use crate::parser::{Leaf, Node};
+use inkwell::targets::{CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetData, TargetMachine};
+use inkwell::types::{BasicMetadataTypeEnum, BasicType, BasicTypeEnum, StructType};
+use crate::parser::{FunctionCall, FunctionDefinition, Leaf, Node};
 use inkwell::values::GlobalValue;
-use crate::lexical::{Constant, ValueType};
+use crate::lexical::{Constant, Operator, Span, UnaryOperator, ValueType};
+use crate::interpreter::{Array, Interpreter, RuntimeErrorKind, Struct, Variable, VariableType};
 
 
 #[derive(Debug, Clone)]
 pub enum VariableValue
 {
     Int(i32),
-    Float(f32),
+    Float(f64),
+}
+
+/// コード生成時のエラー
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    message: String,
+    span: Span,
+}
+
+impl CompileError {
+    pub fn new(message: String, span: Span) -> Self {
+        CompileError { message, span }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+impl std::fmt::Display for CompileError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+impl Error for CompileError {}
+
+impl From<BuilderError> for CompileError {
+    fn from(error: BuilderError) -> Self {
+        CompileError::new(format!("命令の生成に失敗しました : {}", error), Span::default())
+    }
+}
+
+/// 最適化レベルと IR の出力の設定
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompileOptions {
+    // 0 から 3 まで. -O0 から -O3 に対応する
+    level: u32,
+
+    // 最適化の前後で LLVM IR を標準エラー出力に表示する
+    print_ir: bool,
+}
+
+impl CompileOptions {
+    pub fn new(level: u32, print_ir: bool) -> Result<Self, CompileError> {
+        if level > 3 {
+            return Err(CompileError::new(format!("最適化レベルは 0 から 3 までです : {}", level), Span::default()));
+        }
+        Ok(CompileOptions { level, print_ir })
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn print_ir(&self) -> bool {
+        self.print_ir
+    }
+
+    /// コード生成と JIT に渡す最適化レベル
+    fn optimization_level(&self) -> OptimizationLevel {
+        match self.level {
+            0 => OptimizationLevel::None,
+            1 => OptimizationLevel::Less,
+            2 => OptimizationLevel::Default,
+            _ => OptimizationLevel::Aggressive,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 struct GlobalVariable<'ctx>
 {
     name: String,
-    value: VariableValue,
+    value_type: ValueType,
     global_variable: GlobalValue<'ctx>,
 }
 
@@ -34,15 +113,16 @@ struct GlobalVariable<'ctx>
 struct LocalVariable<'ctx>
 {
     name: String,
+    value_type: ValueType,
     pointer: PointerValue<'ctx>,
 }
 
 impl<'ctx> GlobalVariable<'ctx>
 {
-    pub fn new(name: String, value: VariableValue, global_variable: GlobalValue<'ctx>) -> Self {
+    pub fn new(name: String, value_type: ValueType, global_variable: GlobalValue<'ctx>) -> Self {
         GlobalVariable {
             name,
-            value,
+            value_type,
             global_variable,
         }
     }
@@ -53,16 +133,46 @@ struct CodeGen<'ctx> {
     context: &'ctx Context,
     module: Module<'ctx>,
     builder: Builder<'ctx>,
-    execution_engine: ExecutionEngine<'ctx>,
 
     // グローバル変数
     global_vars: HashMap<String, GlobalVariable<'ctx>>,
 
-    // ローカル変数
-    local_vars: HashMap<String, PointerValue<'ctx>>,
+    // 初期値が定数のグローバル変数. 後の初期化子から参照できる
+    global_constants: HashMap<String, VariableValue>,
+
+    // ローカル変数. 内側のスコープほど後ろに並ぶ
+    local_vars: Vec<HashMap<String, LocalVariable<'ctx>>>,
 
     // 関数一覧
     functions: HashMap<String, FunctionValue<'ctx>>,
+
+    // 関数の戻り値の型
+    function_types: HashMap<String, ValueType>,
+
+    // 関数の引数の型
+    function_arguments: HashMap<String, Vec<ValueType>>,
+
+    // 構造体の LLVM 型とメンバの名前と型
+    struct_types: HashMap<String, (StructType<'ctx>, Vec<(String, ValueType)>)>,
+
+    // コンパイル中の関数
+    current_function: Option<FunctionValue<'ctx>>,
+    current_return_type: ValueType,
+
+    // ループの continue 先と break 先. 内側のループほど後ろに並ぶ
+    loops: Vec<(BasicBlock<'ctx>, BasicBlock<'ctx>)>,
+}
+
+/// ホストのターゲットを初期化する
+fn initialize_native_target() -> Result<(), CompileError> {
+    Target::initialize_native(&InitializationConfig::default())
+        .map_err(|message| CompileError::new(
+            format!("ターゲットの初期化に失敗しました : {}", message), Span::default()))
+}
+
+/// ノードの位置を付けたエラーを作成する
+fn error<T>(node: &Rc<RefCell<Node>>, message: String) -> Result<T, CompileError> {
+    Err(CompileError::new(message, node.borrow().span().clone()))
 }
 
 impl<'ctx> CodeGen<'ctx>
@@ -71,139 +181,428 @@ impl<'ctx> CodeGen<'ctx>
         context: &'ctx Context,
         module: Module<'ctx>,
         builder: Builder<'ctx>,
-        execution_engine: ExecutionEngine<'ctx>,
     ) -> Self {
         let codegen = CodeGen {
             context,
             module,
             builder,
-            execution_engine,
             global_vars: HashMap::new(),
-            local_vars: HashMap::new(),
+            global_constants: HashMap::new(),
+            local_vars: Vec::new(),
             functions: HashMap::new(),
+            function_types: HashMap::new(),
+            function_arguments: HashMap::new(),
+            struct_types: HashMap::new(),
+            current_function: None,
+            current_return_type: ValueType::Void,
+            loops: Vec::new(),
         };
 
         codegen
     }
 
     // root を読み込んで、LLVM IR を生成する
-    pub fn generate(&mut self, root: &Rc<RefCell<Node>>) -> Result<(), Box<dyn Error>> {
-        println!("## generate");
-        if let Some(val) = root.borrow().val() {
-            match val
-            {
-                Leaf::Declaration(_) =>
-                    {
-                        self.declare_global_variable(root);
-                    }
-                Leaf::FunctionDefinition(_) =>
-                    {
-                        self.function_definition(root);
-                    }
-                _ =>
-                    {
-                        return Err("対応していないノードです".into());
-                    }
+    pub fn generate(&mut self, root: &Rc<RefCell<Node>>) -> Result<(), CompileError> {
+        let val = root.borrow().val().cloned();
+        match val
+        {
+            Some(Leaf::Declaration(_)) =>
+                {
+                    self.declare_global_variable(root)
+                }
+            Some(Leaf::FunctionDefinition(function_definition)) =>
+                {
+                    self.function_definition(root, &function_definition)
+                }
+            Some(Leaf::StructDefinition(name, members)) =>
+                {
+                    self.struct_definition(root, &name, &members)
+                }
+            Some(val) =>
+                {
+                    error(root, format!("対応していないノードです : {}", val))
+                }
+            None =>
+                {
+                    error(root, "ノードが見つかりません".to_string())
+                }
+        }
+    }
+
+    /// 定義より前にある呼び出しを解決するため, すべての関数を先に宣言する.
+    /// 引数に構造体を使えるように, 構造体はその前に定義する
+    pub fn declare_functions(&mut self, roots: &[Rc<RefCell<Node>>]) -> Result<(), CompileError> {
+        for root in roots {
+            if let Some(Leaf::StructDefinition(name, members)) = root.borrow().val() {
+                self.struct_definition(root, name, members)?;
             }
+        }
 
-            Ok(())
-        } else {
-            Err("ノードが見つかりません".into())
+        for root in roots {
+            if let Some(Leaf::FunctionDefinition(function_definition)) = root.borrow().val() {
+                self.define_function(root, function_definition)?;
+            }
         }
+        Ok(())
     }
 
-    fn function_definition(&mut self, node: &Rc<RefCell<Node>>) {
-        println!("## function_definition");
-        let function_name = self.get_function_name(node);
-        let function_type = self.get_function_type(node);
-        let function_body = self.get_function_body(node);
+    /// 構造体のメンバから名前付きの LLVM 構造体型を作る. 定義済みの場合は何もしない
+    fn struct_definition(&mut self, node: &Rc<RefCell<Node>>, name: &str, members: &[Rc<RefCell<Node>>])
+                         -> Result<(), CompileError>
+    {
+        if self.struct_types.contains_key(name) {
+            return Ok(());
+        }
+
+        let mut member_types = Vec::new();
+        let mut fields = Vec::new();
+        for member in members {
+            let value_type = self.get_variable_type(member)?;
+            let identifier = match member.borrow().lhs() {
+                Some(lhs) => self.get_identifier(lhs)?,
+                None => return error(member, "メンバ名が取得できませんでした".to_string()),
+            };
+            if let ValueType::Struct(member_struct) = &value_type {
+                if member_struct == name {
+                    return error(member, format!("構造体 {} は自身をメンバに持てません", name));
+                }
+            }
+
+            fields.push(self.get_value_type(member, &value_type)?);
+            member_types.push((identifier, value_type));
+        }
+
+        if member_types.is_empty() {
+            return error(node, format!("構造体 {} にメンバがありません", name));
+        }
+
+        let struct_type = self.context.opaque_struct_type(name);
+        struct_type.set_body(&fields, false);
+        self.struct_types.insert(name.to_string(), (struct_type, member_types));
+        Ok(())
+    }
+
+    /// 構造体のメンバの位置と型を返す
+    fn struct_member(&self, node: &Rc<RefCell<Node>>, name: &str, member: &str)
+                     -> Result<(StructType<'ctx>, u32, ValueType), CompileError>
+    {
+        let (struct_type, members) = match self.struct_types.get(name) {
+            Some(struct_type) => struct_type,
+            None => return error(node, format!("未定義の構造体です : {}", name)),
+        };
+
+        match members.iter().position(|(identifier, _)| identifier == member) {
+            Some(index) => Ok((*struct_type, index as u32, members[index].1.clone())),
+            None => error(node, format!("構造体 {} にメンバ {} はありません", name, member)),
+        }
+    }
+
+    fn function_definition(&mut self, node: &Rc<RefCell<Node>>, function_definition: &FunctionDefinition)
+                           -> Result<(), CompileError>
+    {
+        let function_type = function_definition.type_specifier().clone();
 
         // 関数を定義
-        let function = self.define_function(&function_name, &function_type);
+        let function = self.define_function(node, function_definition)?;
+        let basic_block = self.context.append_basic_block(function, "entry");
+        self.builder.position_at_end(basic_block);
+        self.current_function = Some(function);
+        self.current_return_type = function_type.clone();
+
+        // 引数をローカル変数にコピーする
+        self.local_vars.push(HashMap::new());
+        let result = self.function_body(node, function, function_definition);
+        self.local_vars.pop();
+        self.loops.clear();
+        self.current_function = None;
+        result
+    }
+
+    fn function_body(&mut self,
+                     node: &Rc<RefCell<Node>>,
+                     function: FunctionValue<'ctx>,
+                     function_definition: &FunctionDefinition) -> Result<(), CompileError>
+    {
+        for (i, argument) in function_definition.arguments().iter().enumerate() {
+            let value = function.get_nth_param(i as u32).unwrap();
+            value.set_name(argument.identify());
+
+            // 配列は参照渡しなので, 渡されたポインタをそのまま変数の格納先にする
+            if let ValueType::Array(_, _) = argument.type_specifier() {
+                self.insert_local_variable(argument.type_specifier().clone(), argument.identify(),
+                                           value.into_pointer_value());
+                continue;
+            }
+            self.define_local_variable(node, argument.type_specifier().clone(), argument.identify(), Some(value))?;
+        }
 
         // 関数の本体をコンパイル
-        if function_body.len() == 0 {
-            // 関数の本体がない場合は、適当な値を返す
-            match function_type {
+        for node in function_definition.body() {
+            self.compound_statement(node)?;
+        }
+
+        // return がない場合は、適当な値を返す
+        if !self.is_terminated() {
+            match function_definition.type_specifier() {
                 ValueType::Void => {
                     // None なので型を指定する必要がないが, 型推論を解決できないため明示的に指定
-                    self.add_ret::<inkwell::values::IntValue<'ctx>>(None);
+                    self.add_ret::<IntValue<'ctx>>(None)?;
                 }
                 ValueType::Int => {
-                    self.add_ret(Some(self.context.i32_type().const_int(0, false)));
+                    self.add_ret(Some(self.context.i32_type().const_int(0, false)))?;
                 }
                 ValueType::Float => {
-                    self.add_ret(Some(self.context.f32_type().const_float(0.0)));
+                    self.add_ret(Some(self.context.f64_type().const_float(0.0)))?;
                 }
-                _ => panic!("未対応の関数型です"),
-            }
-        } else {
-            for node in function_body {
-                self.compound_statement(&node);
+                ValueType::Char => {
+                    self.add_ret(Some(self.context.i8_type().const_int(0, false)))?;
+                }
+                value_type => return error(node, format!("未対応の関数型です : {:?}", value_type)),
             }
         }
+
+        Ok(())
+    }
+
+    /// 現在の基本ブロックが終端命令で終わっているか
+    fn is_terminated(&self) -> bool {
+        self.builder.get_insert_block()
+            .and_then(|block| block.get_terminator())
+            .is_some()
+    }
+
+    /// 新しい基本ブロックを現在の関数の末尾に追加する
+    fn append_block(&self, name: &str) -> BasicBlock<'ctx> {
+        let function = self.current_function.expect("関数の外では基本ブロックを作成できません");
+        self.context.append_basic_block(function, name)
+    }
+
+    /// 現在の基本ブロックが終端していなければ target へ分岐する
+    fn branch_to(&self, target: BasicBlock<'ctx>) -> Result<(), CompileError> {
+        if !self.is_terminated() {
+            self.builder.build_unconditional_branch(target)?;
+        }
+        Ok(())
     }
 
-    fn compound_statement(&mut self, node: &Rc<RefCell<Node>>) {
-        println!("## compound_statement");
+    fn compound_statement(&mut self, node: &Rc<RefCell<Node>>) -> Result<(), CompileError> {
+        // return や break の後の文は到達しないブロックに置く
+        if self.is_terminated() {
+            let unreachable = self.append_block("unreachable");
+            self.builder.position_at_end(unreachable);
+        }
+
         // compound は関す内部でしか呼ばれないため、ローカル変数のみを扱う
-        if let Some(val) = node.borrow().val() {
+        let val = node.borrow().val().cloned();
+        if let Some(val) = val {
             match val {
                 Leaf::Declaration(_) => {
-                    self.declare_local_variable(node);
+                    self.declare_local_variable(node)?;
                 }
                 Leaf::Return => {
-                    self.return_statement(node);
+                    self.return_statement(node)?;
+                }
+                Leaf::Assignment => {
+                    self.assignment(node)?;
+                }
+                Leaf::ArrayAssignment(index) => {
+                    self.array_assignment(node, &index)?;
+                }
+                Leaf::FunctionCall(function_call) => {
+                    // 文として呼び出した場合は void でもよい
+                    self.function_call(node, &function_call)?;
+                }
+                Leaf::IfStatement(condition) => {
+                    self.if_statement(node, &condition)?;
+                }
+                Leaf::WhileStatement => {
+                    self.while_statement(node)?;
+                }
+                Leaf::ForStatement(for_statement) => {
+                    self.for_statement(for_statement.initializer(), for_statement.condition(),
+                                       for_statement.update(), for_statement.statement())?;
+                }
+                Leaf::Break | Leaf::Continue => {
+                    let (continue_block, break_block) = match self.loops.last() {
+                        Some(targets) => *targets,
+                        None => return error(node, format!("ループの外で {} は使用できません", val)),
+                    };
+                    let target = if let Leaf::Break = val { break_block } else { continue_block };
+                    self.builder.build_unconditional_branch(target)?;
+                }
+                Leaf::StructDefinition(name, members) => {
+                    self.struct_definition(node, &name, &members)?;
+                }
+                Leaf::BlockItem(nodes) => {
+                    self.local_vars.push(HashMap::new());
+                    let result = nodes.iter().try_for_each(|node| self.compound_statement(node));
+                    self.local_vars.pop();
+                    result?;
+                }
+
+                // 式文は評価して値を捨てる
+                Leaf::Operator(_) | Leaf::UnaryExpression(_) | Leaf::ParenthesizedExpression |
+                Leaf::Identifier(_) | Leaf::Constant(_) | Leaf::ArrayAccess | Leaf::StructMemberAccess => {
+                    self.expression(node)?;
                 }
                 _ => {
-                    panic!("未対応のノードです");
+                    return error(node, format!("未対応のノードです : {}", val));
                 }
             }
         }
+
+        Ok(())
     }
 
 
-    fn compile_node(&mut self, node: Rc<RefCell<Node>>) {
-        println!("## compile_node");
+    /// 条件式を評価して i1 にする
+    fn condition(&mut self, node: &Rc<RefCell<Node>>) -> Result<IntValue<'ctx>, CompileError> {
+        let value = self.expression(node)?;
+        self.is_true(node, value)
+    }
 
-        if let Some(val) = node.borrow().val() {
-            match val {
-                Leaf::Return => {
-                    self.return_statement(&node);
-                }
-                Leaf::Declaration(_) => {
-                    self.declare_global_variable(&node);
-                }
-                _ => {
-                    panic!("未対応のノードです");
-                }
-            }
+    fn if_statement(&mut self, node: &Rc<RefCell<Node>>, condition: &Rc<RefCell<Node>>)
+                    -> Result<(), CompileError>
+    {
+        let then_node = node.borrow().lhs().cloned();
+        let else_node = node.borrow().rhs().cloned();
+
+        let condition = self.condition(condition)?;
+        let then_block = self.append_block("then");
+        let else_block = self.append_block("else");
+        let merge_block = self.append_block("endif");
+        self.builder.build_conditional_branch(condition, then_block, else_block)?;
+
+        self.builder.position_at_end(then_block);
+        if let Some(then_node) = then_node {
+            self.compound_statement(&then_node)?;
+        }
+        self.branch_to(merge_block)?;
+
+        // else がない場合も else ブロックから合流先へ分岐する
+        self.builder.position_at_end(else_block);
+        if let Some(else_node) = else_node {
+            self.compound_statement(&else_node)?;
         }
+        self.branch_to(merge_block)?;
+
+        self.builder.position_at_end(merge_block);
+        Ok(())
     }
 
-    fn declare_local_variable(&mut self, node: &Rc<RefCell<Node>>)
+    fn while_statement(&mut self, node: &Rc<RefCell<Node>>) -> Result<(), CompileError> {
+        let condition = match node.borrow().lhs() {
+            Some(condition) => condition.clone(),
+            None => return error(node, "while 文の条件式がありません".to_string()),
+        };
+        let body = node.borrow().rhs().cloned();
+
+        let condition_block = self.append_block("while.cond");
+        let body_block = self.append_block("while.body");
+        let end_block = self.append_block("while.end");
+        self.builder.build_unconditional_branch(condition_block)?;
+
+        self.builder.position_at_end(condition_block);
+        let condition = self.condition(&condition)?;
+        self.builder.build_conditional_branch(condition, body_block, end_block)?;
+
+        // continue は条件式へ戻る
+        self.builder.position_at_end(body_block);
+        self.loops.push((condition_block, end_block));
+        if let Some(body) = body {
+            self.compound_statement(&body)?;
+        }
+        self.loops.pop();
+        self.branch_to(condition_block)?;
+
+        self.builder.position_at_end(end_block);
+        Ok(())
+    }
+
+    fn for_statement(&mut self,
+                     initializer: &Rc<RefCell<Node>>,
+                     condition: &Rc<RefCell<Node>>,
+                     update: &Rc<RefCell<Node>>,
+                     body: &Rc<RefCell<Node>>) -> Result<(), CompileError>
+    {
+        self.compound_statement(initializer)?;
+
+        let condition_block = self.append_block("for.cond");
+        let body_block = self.append_block("for.body");
+        let update_block = self.append_block("for.update");
+        let end_block = self.append_block("for.end");
+        self.builder.build_unconditional_branch(condition_block)?;
+
+        self.builder.position_at_end(condition_block);
+        let condition = self.condition(condition)?;
+        self.builder.build_conditional_branch(condition, body_block, end_block)?;
+
+        // continue は更新式へ進む
+        self.builder.position_at_end(body_block);
+        self.loops.push((update_block, end_block));
+        self.compound_statement(body)?;
+        self.loops.pop();
+        self.branch_to(update_block)?;
+
+        self.builder.position_at_end(update_block);
+        self.compound_statement(update)?;
+        self.branch_to(condition_block)?;
+
+        self.builder.position_at_end(end_block);
+        Ok(())
+    }
+
+    fn declare_local_variable(&mut self, node: &Rc<RefCell<Node>>) -> Result<(), CompileError>
     {
-        
+        let value_type = self.get_variable_type(node)?;
+
+        // 左辺値から識別子を取得
+        let identifier = match node.borrow().lhs() {
+            Some(lhs) => self.get_identifier(lhs)?,
+            None => return error(node, "識別子が取得できませんでした".to_string()),
+        };
+
+        // 初期化子は変数を宣言する前に評価する. 配列の右辺は要素数なので初期化子ではない
+        let rhs = self.get_initializer(node);
+        let value = match rhs {
+            Some(rhs) => Some(self.expression(&rhs)?),
+            None => None,
+        };
+
+        // 文字列で初期化する char の配列は, 0 で初期化した後に文字列を書き込む
+        let string = match (&value_type, self.get_string_initializer(node)) {
+            (ValueType::Array(_, size), Some(string)) => Some(self.const_string(&string, *size)),
+            _ => None,
+        };
+
+        self.define_local_variable(node, value_type, &identifier, value)?;
+        if let Some(string) = string {
+            let (pointer, _) = self.find_variable(node, &identifier)?;
+            self.builder.build_store(pointer, string)?;
+        }
+        Ok(())
     }
 
     /// return 文を処理
-    /// TODO : 戻り値を定数以外取り扱えるようにする
-    fn return_statement(&self, node: &Rc<RefCell<Node>>)
+    fn return_statement(&mut self, node: &Rc<RefCell<Node>>) -> Result<(), CompileError>
     {
-        if let Some(constant_value) = self.get_constant_value(node.borrow().lhs().unwrap()) {
-            match constant_value {
-                VariableValue::Int(value) => {
-                    self.add_ret(Some(self.context.i32_type().const_int(value as u64, false)));
-                }
-                VariableValue::Float(value) => {
-                    self.add_ret(Some(self.context.f32_type().const_float(value as f64)));
+        let lhs = node.borrow().lhs().cloned();
+        match lhs {
+            Some(lhs) => {
+                if let ValueType::Void = self.current_return_type {
+                    return error(node, "void 型の関数は値を返せません".to_string());
                 }
+                let value = self.expression(&lhs)?;
+                let value = self.check_value(&lhs, value, &self.current_return_type.clone())?;
+                self.add_ret(Some(value))?;
+            }
+            None => {
+                // 戻り値がない場合
+                self.add_ret::<IntValue<'ctx>>(None)?;
             }
-        } else {
-            // 戻り値がない場合
-            self.add_ret::<inkwell::values::IntValue<'ctx>>(None)
-                .expect("return 文の追加に失敗しました");
         }
+
+        Ok(())
     }
 
     fn add_ret<T: inkwell::values::BasicValue<'ctx>>(&self, value: Option<T>)
@@ -215,218 +614,725 @@ impl<'ctx> CodeGen<'ctx>
         }
     }
 
-    fn get_function_body(&self, node: &Rc<RefCell<Node>>) -> Vec<Rc<RefCell<Node>>> {
-        if let Some(val) = node.borrow().val() {
-            if let Leaf::FunctionDefinition(func) = val {
-                return func.body().clone();
-            }
+    /// 関数を宣言する. 宣言済みの場合はそれを返す
+    fn define_function(&mut self, node: &Rc<RefCell<Node>>, function_definition: &FunctionDefinition)
+                       -> Result<FunctionValue<'ctx>, CompileError>
+    {
+        let function_name = function_definition.name();
+        if let Some(function) = self.functions.get(function_name) {
+            return Ok(*function);
+        }
+
+        let mut parameters: Vec<BasicMetadataTypeEnum<'ctx>> = Vec::new();
+        for argument in function_definition.arguments().iter() {
+            parameters.push(self.get_parameter_type(node, argument.type_specifier())?.into());
         }
 
-        panic!("関数の本体が取得できませんでした");
+        let function_type = match function_definition.type_specifier() {
+            ValueType::Void => self.context.void_type().fn_type(&parameters, false),
+            ValueType::Int => self.context.i32_type().fn_type(&parameters, false),
+            ValueType::Float => self.context.f64_type().fn_type(&parameters, false),
+            ValueType::Char => self.context.i8_type().fn_type(&parameters, false),
+            value_type => return error(node, format!("未対応の関数型です : {:?}", value_type)),
+        };
+        let function = self.module.add_function(function_name, function_type, None);
+
+        self.functions.insert(function_name.clone(), function);
+        self.function_types.insert(function_name.clone(), function_definition.type_specifier().clone());
+        self.function_arguments.insert(function_name.clone(), function_definition.arguments().iter()
+            .map(|argument| argument.type_specifier().clone())
+            .collect());
+        Ok(function)
     }
 
-    fn define_function(&self, function_name: &str, function_type: &ValueType) -> FunctionValue {
-        match function_type {
-            ValueType::Void => self.define_function_void(function_name),
-            ValueType::Int => self.define_function_int(function_name),
-            _ => panic!("未対応の関数型です"),
+    /// 変数に使う LLVM 型を返す
+    fn get_value_type(&self, node: &Rc<RefCell<Node>>, value_type: &ValueType)
+                      -> Result<BasicTypeEnum<'ctx>, CompileError>
+    {
+        match value_type {
+            ValueType::Int => Ok(self.context.i32_type().as_basic_type_enum()),
+            ValueType::Float => Ok(self.context.f64_type().as_basic_type_enum()),
+            ValueType::Char => Ok(self.context.i8_type().as_basic_type_enum()),
+            ValueType::Array(element, size) => {
+                let element = self.get_value_type(node, element)?;
+                Ok(element.array_type(*size as u32).as_basic_type_enum())
+            }
+            ValueType::Struct(name) => match self.struct_types.get(name) {
+                Some((struct_type, _)) => Ok(struct_type.as_basic_type_enum()),
+                None => error(node, format!("未定義の構造体です : {}", name)),
+            },
+            ValueType::Void => error(node, "void 型の変数は定義できません".to_string()),
         }
     }
-    fn define_function_void(&self, function_name: &str) -> FunctionValue
+
+    /// 引数に使う LLVM 型を返す. 配列は参照渡しなのでポインタになる
+    fn get_parameter_type(&self, node: &Rc<RefCell<Node>>, value_type: &ValueType)
+                          -> Result<BasicTypeEnum<'ctx>, CompileError>
     {
-        let void_type = self.context.void_type();
-        let function_type = void_type.fn_type(&[], false);
-        let function = self.module.add_function(function_name, function_type, None);
+        match value_type {
+            ValueType::Array(_, _) => Ok(self.context.ptr_type(AddressSpace::default()).as_basic_type_enum()),
+            value_type => self.get_value_type(node, value_type),
+        }
+    }
 
+    /// 関数の先頭に alloca を置く. mem2reg でレジスタに昇格できるようにするため
+    fn create_entry_block_alloca(&self, value_type: BasicTypeEnum<'ctx>, name: &str)
+                                 -> Result<PointerValue<'ctx>, CompileError>
+    {
+        let function = self.current_function.expect("関数の外でローカル変数は定義できません");
+        let entry = function.get_first_basic_block().unwrap();
 
-        // 関数が main の場合は、エントリーポイントを設定
-        if function_name == "main" {
-            let basic_block = self.context.append_basic_block(function, "entry");
-            self.builder.position_at_end(basic_block);
+        let builder = self.context.create_builder();
+        match entry.get_first_instruction() {
+            Some(instruction) => builder.position_before(&instruction),
+            None => builder.position_at_end(entry),
         }
 
-        function
+        Ok(builder.build_alloca(value_type, name)?)
     }
 
-    fn define_function_int(&self, function_name: &str) -> FunctionValue
+    fn define_local_variable(&mut self,
+                             node: &Rc<RefCell<Node>>,
+                             value_type: ValueType,
+                             identifier: &str,
+                             value: Option<BasicValueEnum<'ctx>>) -> Result<(), CompileError>
     {
-        let int_type = self.context.i32_type();
-        let function_type = int_type.fn_type(&[], false);
-        let function = self.module.add_function(function_name, function_type, None);
+        let llvm_type = self.get_value_type(node, &value_type)?;
+        let pointer = self.create_entry_block_alloca(llvm_type, identifier)?;
 
-        // 関数が main の場合は、エントリーポイントを設定
-        if function_name == "main" {
-            let basic_block = self.context.append_basic_block(function, "entry");
-            self.builder.position_at_end(basic_block);
-        }
+        // 初期値がない場合は 0 で初期化
+        let value = match value {
+            Some(value) => self.check_value(node, value, &value_type)?,
+            None => llvm_type.const_zero(),
+        };
+        self.builder.build_store(pointer, value)?;
 
-        function
+        self.insert_local_variable(value_type, identifier, pointer);
+        Ok(())
     }
 
+    fn insert_local_variable(&mut self, value_type: ValueType, identifier: &str, pointer: PointerValue<'ctx>) {
+        let local_variable = LocalVariable {
+            name: identifier.to_string(),
+            value_type,
+            pointer,
+        };
+        self.local_vars.last_mut()
+            .expect("関数の外でローカル変数は定義できません")
+            .insert(identifier.to_string(), local_variable);
+    }
 
-    fn get_function_name(&self, node: &Rc<RefCell<Node>>) -> String
+    /// 変数の格納先と型を探す. ローカル変数は内側のスコープから検索し, なければグローバル変数を検索する
+    fn find_variable(&self, node: &Rc<RefCell<Node>>, identifier: &str)
+                     -> Result<(PointerValue<'ctx>, ValueType), CompileError>
     {
-        if let Some(val) = node.borrow().val() {
-            if let Leaf::FunctionDefinition(func) = val {
-                return func.name().clone();
+        for scope in self.local_vars.iter().rev() {
+            if let Some(variable) = scope.get(identifier) {
+                return Ok((variable.pointer, variable.value_type.clone()));
             }
         }
 
-        panic!("関数名が取得できませんでした");
+        match self.global_vars.get(identifier) {
+            Some(variable) => Ok((variable.global_variable.as_pointer_value(), variable.value_type.clone())),
+            None => error(node, format!("未定義の変数です : {}", identifier)),
+        }
     }
 
-    fn get_function_type(&self, node: &Rc<RefCell<Node>>) -> ValueType
+    /// 変数, 配列の要素, 構造体のメンバの格納先と型を返す
+    fn variable_pointer(&mut self, node: &Rc<RefCell<Node>>)
+                        -> Result<(PointerValue<'ctx>, ValueType), CompileError>
     {
-        if let Some(val) = node.borrow().val() {
-            if let Leaf::FunctionDefinition(func) = val {
-                return func.type_specifier().clone();
+        let val = node.borrow().val().cloned();
+        let lhs = node.borrow().lhs().cloned();
+        let rhs = node.borrow().rhs().cloned();
+
+        match (val, lhs, rhs) {
+            (Some(Leaf::Identifier(identifier)), _, _) => self.find_variable(node, &identifier),
+            (Some(Leaf::ParenthesizedExpression), Some(lhs), _) => self.variable_pointer(&lhs),
+            (Some(Leaf::ArrayAccess), Some(array), Some(index)) => self.element_pointer(&array, &index),
+            (Some(Leaf::StructMemberAccess), Some(lhs), Some(member)) => {
+                let (pointer, value_type) = self.variable_pointer(&lhs)?;
+                let name = match value_type {
+                    ValueType::Struct(name) => name,
+                    value_type => return error(&lhs, format!("構造体ではありません : {:?}", value_type)),
+                };
+
+                let member = self.get_identifier(&member)?;
+                let (struct_type, index, member_type) = self.struct_member(node, &name, &member)?;
+                let pointer = self.builder.build_struct_gep(struct_type, pointer, index, &member)?;
+                Ok((pointer, member_type))
             }
+            (val, _, _) => error(node, format!("代入できない式です : {:?}", val)),
         }
+    }
+
+    /// 配列の要素の格納先と要素の型を返す
+    fn element_pointer(&mut self, array: &Rc<RefCell<Node>>, index: &Rc<RefCell<Node>>)
+                       -> Result<(PointerValue<'ctx>, ValueType), CompileError>
+    {
+        let (pointer, value_type) = self.variable_pointer(array)?;
+        let array_type = self.get_value_type(array, &value_type)?;
+        let element = match value_type {
+            ValueType::Array(element, _) => *element,
+            value_type => return error(array, format!("配列ではありません : {:?}", value_type)),
+        };
+
+        let value = self.expression(index)?;
+        let index = match self.promote(value)? {
+            BasicValueEnum::IntValue(index) => index,
+            value => return error(index, format!("配列の添字は int である必要があります : {:?}", value)),
+        };
+        let zero = self.context.i32_type().const_zero();
 
-        panic!("関数の型が取得できませませんでした");
+        // SAFETY: 添字の範囲は検査しない. C と同様に範囲外のアクセスは未定義
+        let pointer = unsafe {
+            self.builder.build_in_bounds_gep(array_type, pointer, &[zero, index], "element")?
+        };
+        Ok((pointer, element))
     }
 
-    /// ValueType に応じた LLVM 型を返す
-    fn get_basic_type(&self, value_type: &ValueType) -> AnyTypeEnum<'ctx> {
-        match value_type {
-            ValueType::Void => self.context.void_type().as_any_type_enum(),
-            ValueType::Int => self.context.i32_type().as_any_type_enum(),
-            ValueType::Float => self.context.f32_type().as_any_type_enum(),
-            ValueType::Struct(_) => {
-                panic!("LLVM IR では構造体は未対応です");
+    /// 値を変数の型に合わせる. int, float, char は変換し, 構造体は同じ型のみ受け付ける
+    fn check_value(&self, node: &Rc<RefCell<Node>>, value: BasicValueEnum<'ctx>, value_type: &ValueType)
+                   -> Result<BasicValueEnum<'ctx>, CompileError>
+    {
+        match (value_type, value) {
+            (ValueType::Int | ValueType::Float | ValueType::Char,
+                BasicValueEnum::IntValue(_) | BasicValueEnum::FloatValue(_)) => {
+                self.convert(value, value_type)
+            }
+            (ValueType::Struct(_), BasicValueEnum::StructValue(struct_value))
+            if struct_value.get_type().as_basic_type_enum() == self.get_value_type(node, value_type)? => {
+                Ok(value)
+            }
+            (ValueType::Array(_, _), _) => error(node, "配列には代入できません".to_string()),
+            (value_type, value) => error(node, format!("{:?} 型に {:?} は代入できません", value_type, value)),
+        }
+    }
+
+    fn assignment(&mut self, node: &Rc<RefCell<Node>>) -> Result<(), CompileError> {
+        let (lhs, rhs) = match node.borrow().get_lhs_and_rhs() {
+            Some((lhs, rhs)) => (lhs.clone(), rhs.clone()),
+            None => return error(node, "代入式が不正です".to_string()),
+        };
+
+        let value = self.expression(&rhs)?;
+        let (pointer, value_type) = self.variable_pointer(&lhs)?;
+        let value = self.check_value(node, value, &value_type)?;
+        self.builder.build_store(pointer, value)?;
+        Ok(())
+    }
+
+    /// 配列の要素への代入. 添字を値より先に評価する
+    fn array_assignment(&mut self, node: &Rc<RefCell<Node>>, index: &Rc<RefCell<Node>>) -> Result<(), CompileError> {
+        let (lhs, rhs) = match node.borrow().get_lhs_and_rhs() {
+            Some((lhs, rhs)) => (lhs.clone(), rhs.clone()),
+            None => return error(node, "代入式が不正です".to_string()),
+        };
+
+        let (pointer, element) = self.element_pointer(&lhs, index)?;
+        let value = self.expression(&rhs)?;
+        let value = self.check_value(node, value, &element)?;
+        self.builder.build_store(pointer, value)?;
+        Ok(())
+    }
+
+    /// 式を評価して値を返す
+    fn expression(&mut self, node: &Rc<RefCell<Node>>) -> Result<BasicValueEnum<'ctx>, CompileError> {
+        let val = node.borrow().val().cloned();
+        let lhs = node.borrow().lhs().cloned();
+        let rhs = node.borrow().rhs().cloned();
+
+        match (val, lhs, rhs) {
+            (Some(Leaf::Constant(constant)), _, _) => {
+                match constant {
+                    Constant::Integer(value) => {
+                        Ok(self.context.i32_type().const_int(value as u64, true).into())
+                    }
+                    Constant::Float(value) => {
+                        Ok(self.context.f64_type().const_float(value as f64).into())
+                    }
+                    Constant::Char(value) => {
+                        Ok(self.context.i8_type().const_int(value as u64, true).into())
+                    }
+                }
+            }
+            (Some(Leaf::StringLiteral(_)), _, _) => {
+                error(node, "文字列は char の配列の初期化子か, 関数の引数にのみ使用できます".to_string())
+            }
+            (Some(Leaf::Identifier(_) | Leaf::ArrayAccess | Leaf::StructMemberAccess), _, _) => {
+                let (pointer, value_type) = self.variable_pointer(node)?;
+                if let ValueType::Array(_, _) = value_type {
+                    return error(node, "配列は値として使用できません".to_string());
+                }
+                let llvm_type = self.get_value_type(node, &value_type)?;
+                Ok(self.builder.build_load(llvm_type, pointer, "load")?)
+            }
+            (Some(Leaf::Operator(op @ (Operator::LogicalAnd | Operator::LogicalOr))), Some(lhs), Some(rhs)) => {
+                self.logical_operator(op, &lhs, &rhs)
+            }
+            (Some(Leaf::Operator(op)), Some(lhs), Some(rhs)) => {
+                let lhs = self.expression(&lhs)?;
+                let rhs = self.expression(&rhs)?;
+                self.binary_operator(node, op, lhs, rhs)
+            }
+            (Some(Leaf::UnaryExpression(op)), Some(lhs), _) => {
+                let value = self.expression(&lhs)?;
+                self.unary_operator(node, op, value)
             }
-            ValueType::Array(_, _) => {
-                panic!("LLVM IR では配列は未対応です");
+            (Some(Leaf::ParenthesizedExpression), Some(lhs), _) => {
+                self.expression(&lhs)
             }
+            (Some(Leaf::FunctionCall(function_call)), _, _) => {
+                match self.function_call(node, &function_call)? {
+                    Some(value) => Ok(value),
+                    None => error(node, format!("void 型の関数 {} の戻り値は使用できません", function_call.name())),
+                }
+            }
+            (val, _, _) => error(node, format!("未対応の式です : {:?}", val)),
         }
     }
 
-    fn assign_value(&mut self, identifier: String, value: VariableValue)
+    /// 関数を呼び出す. 戻り値が void の場合は None
+    fn function_call(&mut self, node: &Rc<RefCell<Node>>, function_call: &FunctionCall)
+                     -> Result<Option<BasicValueEnum<'ctx>>, CompileError>
     {
-        let variable = self.local_vars.get(&identifier).unwrap();
+        let name = function_call.name();
+        let function = match self.functions.get(name) {
+            Some(function) => *function,
+            None => return error(node, format!("未定義の関数です : {}", name)),
+        };
+
+        let parameters = self.function_arguments.get(name).cloned().unwrap_or_default();
+        if parameters.len() != function_call.arguments().len() {
+            return error(node, format!("関数 {} の引数の数が一致しません : 期待 {} 個, 実際 {} 個",
+                                       name, parameters.len(), function_call.arguments().len()));
+        }
+
+        // 引数を仮引数の型に変換して渡す. 配列は先頭のポインタを渡す
+        let mut arguments: Vec<BasicMetadataValueEnum<'ctx>> = Vec::new();
+        for (argument, parameter) in function_call.arguments().iter().zip(parameters) {
+            let string = match argument.borrow().val() {
+                Some(Leaf::StringLiteral(string)) => Some(string.clone()),
+                _ => None,
+            };
+            let value = match (&parameter, string) {
+                // 文字列は '\0' で終わる char の配列として渡す
+                (ValueType::Array(element, _), Some(string)) if **element == ValueType::Char => {
+                    self.string_pointer(&string).into()
+                }
+                (ValueType::Array(element, _), _) => {
+                    let (pointer, value_type) = self.variable_pointer(argument)?;
+                    match value_type {
+                        ValueType::Array(argument_element, _) if argument_element == *element => pointer.into(),
+                        value_type => return error(argument, format!(
+                            "関数 {} の引数に {:?} は渡せません : 期待 {:?}", name, value_type, parameter)),
+                    }
+                }
+                _ => {
+                    let value = self.expression(argument)?;
+                    self.check_value(argument, value, &parameter)?
+                }
+            };
+            arguments.push(value.into());
+        }
+
+        Ok(self.builder.build_call(function, &arguments, "call")?
+            .try_as_basic_value()
+            .left())
+    }
+
+    /// 値を int, float, char に変換する
+    fn convert(&self, value: BasicValueEnum<'ctx>, value_type: &ValueType)
+               -> Result<BasicValueEnum<'ctx>, CompileError>
+    {
+        let int_type = match value_type {
+            ValueType::Char => self.context.i8_type(),
+            _ => self.context.i32_type(),
+        };
+
+        match (value, value_type) {
+            (BasicValueEnum::IntValue(value), ValueType::Float) => {
+                Ok(self.builder.build_signed_int_to_float(value, self.context.f64_type(), "int_to_float")?.into())
+            }
+            (BasicValueEnum::FloatValue(value), ValueType::Int | ValueType::Char) => {
+                Ok(self.builder.build_float_to_signed_int(value, int_type, "float_to_int")?.into())
+            }
+            (BasicValueEnum::IntValue(value), ValueType::Int | ValueType::Char) => {
+                Ok(self.builder.build_int_cast_sign_flag(value, int_type, true, "int_cast")?.into())
+            }
+            (value, _) => Ok(value),
+        }
+    }
 
+    /// 計算の前に char を int に符号拡張する
+    fn promote(&self, value: BasicValueEnum<'ctx>) -> Result<BasicValueEnum<'ctx>, CompileError> {
         match value {
-            VariableValue::Int(value) => {
-                self.builder.build_store(*variable, self.context.i32_type().const_int(value as u64, false));
+            BasicValueEnum::IntValue(int_value) if int_value.get_type().get_bit_width() < 32 => {
+                self.convert(value, &ValueType::Int)
             }
-            VariableValue::Float(value) => {
-                self.builder.build_store(*variable, self.context.f32_type().const_float(value as f64));
+            value => Ok(value),
+        }
+    }
+
+    /// 真偽値 (i1) を int (0 または 1) にする
+    fn bool_to_int(&self, value: IntValue<'ctx>) -> Result<BasicValueEnum<'ctx>, CompileError> {
+        Ok(self.builder.build_int_z_extend(value, self.context.i32_type(), "bool_to_int")?.into())
+    }
+
+    /// 値が 0 でないかを i1 で返す
+    fn is_true(&self, node: &Rc<RefCell<Node>>, value: BasicValueEnum<'ctx>) -> Result<IntValue<'ctx>, CompileError> {
+        match value {
+            BasicValueEnum::FloatValue(value) => {
+                let zero = self.context.f64_type().const_zero();
+                Ok(self.builder.build_float_compare(FloatPredicate::ONE, value, zero, "is_true")?)
+            }
+            BasicValueEnum::IntValue(value) => {
+                let zero = value.get_type().const_zero();
+                Ok(self.builder.build_int_compare(IntPredicate::NE, value, zero, "is_true")?)
             }
+            value => error(node, format!("条件式に使用できない値です : {:?}", value)),
         }
     }
 
-    fn define_local_variable(&mut self,
-                             value_type: ValueType,
-                             identifier: &str,
-                             value: VariableValue)
+    /// '&&' と '||'. 左辺で結果が決まる場合は右辺を評価せず, phi で結果を合流させる
+    fn logical_operator(&mut self, op: Operator, lhs: &Rc<RefCell<Node>>, rhs: &Rc<RefCell<Node>>)
+                        -> Result<BasicValueEnum<'ctx>, CompileError>
     {
-        println!("## define_local_variable");
+        let lhs = self.condition(lhs)?;
+        let lhs_block = self.builder.get_insert_block().unwrap();
+        let rhs_block = self.append_block("logical.rhs");
+        let merge_block = self.append_block("logical.end");
+
+        // && は左辺が偽なら, || は左辺が真なら右辺を飛ばす
+        let short_circuit = if let Operator::LogicalAnd = op {
+            self.builder.build_conditional_branch(lhs, rhs_block, merge_block)?;
+            self.context.bool_type().const_zero()
+        } else {
+            self.builder.build_conditional_branch(lhs, merge_block, rhs_block)?;
+            self.context.bool_type().const_all_ones()
+        };
+
+        self.builder.position_at_end(rhs_block);
+        let rhs = self.condition(rhs)?;
+        let rhs_end_block = self.builder.get_insert_block().unwrap();
+        self.builder.build_unconditional_branch(merge_block)?;
+
+        self.builder.position_at_end(merge_block);
+        let phi = self.builder.build_phi(self.context.bool_type(), "logical")?;
+        phi.add_incoming(&[(&short_circuit, lhs_block), (&rhs, rhs_end_block)]);
+        self.bool_to_int(phi.as_basic_value().into_int_value())
+    }
 
-        unimplemented!("define_local_variable");
+    fn binary_operator(&self, node: &Rc<RefCell<Node>>, op: Operator,
+                       lhs: BasicValueEnum<'ctx>, rhs: BasicValueEnum<'ctx>)
+                       -> Result<BasicValueEnum<'ctx>, CompileError>
+    {
+        // どちらかが float の場合は float で計算する
+        let lhs = self.promote(lhs)?;
+        let rhs = self.promote(rhs)?;
+        match (lhs, rhs) {
+            (BasicValueEnum::IntValue(lhs), BasicValueEnum::IntValue(rhs)) => {
+                self.int_operator(op, lhs, rhs)
+            }
+            (BasicValueEnum::IntValue(_) | BasicValueEnum::FloatValue(_),
+                BasicValueEnum::IntValue(_) | BasicValueEnum::FloatValue(_)) if op == Operator::Modulo => {
+                self.remainder(lhs, rhs)
+            }
+            (BasicValueEnum::IntValue(_) | BasicValueEnum::FloatValue(_),
+                BasicValueEnum::IntValue(_) | BasicValueEnum::FloatValue(_)) => {
+                let lhs = self.convert(lhs, &ValueType::Float)?.into_float_value();
+                let rhs = self.convert(rhs, &ValueType::Float)?.into_float_value();
+                self.float_operator(op, lhs, rhs)
+            }
+            (lhs, rhs) => error(node, format!("未対応の演算です : {:?} {} {:?}", lhs, op.as_str(), rhs)),
+        }
     }
 
-    fn declare_global_variable(&mut self, node: &Rc<RefCell<Node>>) {
-        println!("## declare_global_variable");
-        let value_type = self.get_variable_type(node);
+    fn int_operator(&self, op: Operator, lhs: IntValue<'ctx>, rhs: IntValue<'ctx>)
+                    -> Result<BasicValueEnum<'ctx>, CompileError>
+    {
+        let builder = &self.builder;
+        let predicate = match op {
+            Operator::Plus => return Ok(builder.build_int_add(lhs, rhs, "add")?.into()),
+            Operator::Minus => return Ok(builder.build_int_sub(lhs, rhs, "sub")?.into()),
+            Operator::Multiply => return Ok(builder.build_int_mul(lhs, rhs, "mul")?.into()),
+            Operator::Divide => return Ok(builder.build_int_signed_div(lhs, rhs, "div")?.into()),
+            Operator::Modulo => return Ok(builder.build_int_signed_rem(lhs, rhs, "rem")?.into()),
+            Operator::LessThan => IntPredicate::SLT,
+            Operator::GreaterThan => IntPredicate::SGT,
+            Operator::LessThanOrEqual => IntPredicate::SLE,
+            Operator::GreaterThanOrEqual => IntPredicate::SGE,
+            Operator::Equal => IntPredicate::EQ,
+            Operator::NotEqual => IntPredicate::NE,
+            Operator::LogicalAnd | Operator::LogicalOr => unreachable!(),
+        };
 
-        // 左辺値から識別子を取得
-        let mut identifier = String::new();
-        if let Some(lhs) = node.borrow().lhs() {
-            identifier = self.get_identifier(lhs);
+        let value = builder.build_int_compare(predicate, lhs, rhs, "cmp")?;
+        self.bool_to_int(value)
+    }
+
+    /// float を含む '%'. インタプリタと同じく結果は int にする.
+    /// 左辺が int の場合は右辺を切り捨ててから余りを求め, float の場合は float の余りを切り捨てる
+    fn remainder(&self, lhs: BasicValueEnum<'ctx>, rhs: BasicValueEnum<'ctx>)
+                 -> Result<BasicValueEnum<'ctx>, CompileError>
+    {
+        if let BasicValueEnum::IntValue(lhs) = lhs {
+            let rhs = self.convert(rhs, &ValueType::Int)?.into_int_value();
+            return Ok(self.builder.build_int_signed_rem(lhs, rhs, "rem")?.into());
         }
 
-        // 右辺値がある場合は、その値を取得
-        // TODO : 初期値設定時に演算を行えるようにする
-        let value = if let Some(rhs) = node.borrow().rhs() {
-            self.get_constant_value(rhs).unwrap()
-        } else {
-            // 見つからない場合は、初期値を設定
-            match value_type {
-                ValueType::Int => VariableValue::Int(0),
-                ValueType::Float => VariableValue::Float(0.0),
-                _ => panic!("未対応の型です"),
+        let lhs = lhs.into_float_value();
+        let rhs = self.convert(rhs, &ValueType::Float)?.into_float_value();
+        let value = self.builder.build_float_rem(lhs, rhs, "rem")?;
+        self.convert(value.into(), &ValueType::Int)
+    }
+
+    fn float_operator(&self, op: Operator, lhs: FloatValue<'ctx>, rhs: FloatValue<'ctx>)
+                      -> Result<BasicValueEnum<'ctx>, CompileError>
+    {
+        let builder = &self.builder;
+        let predicate = match op {
+            Operator::Plus => return Ok(builder.build_float_add(lhs, rhs, "add")?.into()),
+            Operator::Minus => return Ok(builder.build_float_sub(lhs, rhs, "sub")?.into()),
+            Operator::Multiply => return Ok(builder.build_float_mul(lhs, rhs, "mul")?.into()),
+            Operator::Divide => return Ok(builder.build_float_div(lhs, rhs, "div")?.into()),
+            Operator::LessThan => FloatPredicate::OLT,
+            Operator::GreaterThan => FloatPredicate::OGT,
+            Operator::LessThanOrEqual => FloatPredicate::OLE,
+            Operator::GreaterThanOrEqual => FloatPredicate::OGE,
+            Operator::Equal => FloatPredicate::OEQ,
+            Operator::NotEqual => FloatPredicate::UNE,
+            Operator::Modulo | Operator::LogicalAnd | Operator::LogicalOr => unreachable!(),
+        };
+
+        let value = builder.build_float_compare(predicate, lhs, rhs, "cmp")?;
+        self.bool_to_int(value)
+    }
+
+    fn unary_operator(&self, node: &Rc<RefCell<Node>>, op: UnaryOperator, value: BasicValueEnum<'ctx>)
+                      -> Result<BasicValueEnum<'ctx>, CompileError>
+    {
+        match (op, self.promote(value)?) {
+            (UnaryOperator::Minus, BasicValueEnum::IntValue(value)) => {
+                Ok(self.builder.build_int_neg(value, "neg")?.into())
+            }
+            (UnaryOperator::Minus, BasicValueEnum::FloatValue(value)) => {
+                Ok(self.builder.build_float_neg(value, "neg")?.into())
             }
+            (UnaryOperator::LogicalNot, value) => {
+                let value = self.is_true(node, value)?;
+                let value = self.builder.build_not(value, "not")?;
+                self.bool_to_int(value)
+            }
+            (op, value) => error(node, format!("未対応の単項演算です : {:?} {:?}", op, value)),
+        }
+    }
+
+    fn declare_global_variable(&mut self, node: &Rc<RefCell<Node>>) -> Result<(), CompileError> {
+        let value_type = self.get_variable_type(node)?;
+
+        // 左辺値から識別子を取得
+        let identifier = match node.borrow().lhs() {
+            Some(lhs) => self.get_identifier(lhs)?,
+            None => return error(node, "識別子が取得できませんでした".to_string()),
+        };
+
+        // 右辺値がある場合は、定数式として評価する
+        let value = match self.get_initializer(node) {
+            Some(rhs) => Some(self.get_constant_value(&rhs)?),
+            None => None,
         };
 
         // LLVM IR でグローバル変数を定義
-        self.define_global_variable(value_type, &identifier, value);
+        self.define_global_variable(node, value_type, &identifier, value)
     }
 
     fn define_global_variable(&mut self,
+                              node: &Rc<RefCell<Node>>,
                               value_type: ValueType,
                               identifier: &str,
-                              value: VariableValue) {
-        match value_type {
-            ValueType::Int => {
-                match value {
-                    VariableValue::Int(value) => self.add_global_int(identifier, value),
-                    VariableValue::Float(value) => self.add_global_int(identifier, value as i32),
+                              value: Option<VariableValue>) -> Result<(), CompileError> {
+        // 初期値がない場合は 0 で初期化
+        match (&value_type, value) {
+            (ValueType::Int, None) => self.add_global_int(identifier, 0),
+            (ValueType::Int, Some(VariableValue::Int(value))) => self.add_global_int(identifier, value),
+            (ValueType::Int, Some(VariableValue::Float(value))) => self.add_global_int(identifier, value as i32),
+            (ValueType::Float, None) => self.add_global_float(identifier, 0.0),
+            (ValueType::Float, Some(VariableValue::Int(value))) => self.add_global_float(identifier, value as f64),
+            (ValueType::Float, Some(VariableValue::Float(value))) => self.add_global_float(identifier, value),
+            (ValueType::Char, None) => self.add_global_char(identifier, 0),
+            (ValueType::Char, Some(VariableValue::Int(value))) => self.add_global_char(identifier, value as i8),
+            (ValueType::Char, Some(VariableValue::Float(value))) => self.add_global_char(identifier, value as i8),
+            (ValueType::Array(_, _) | ValueType::Struct(_), None) => {
+                let llvm_type = self.get_value_type(node, &value_type)?;
+                let global = self.module.add_global(llvm_type, None, identifier);
+
+                // char の配列は文字列で初期化できる
+                match (&value_type, self.get_string_initializer(node)) {
+                    (ValueType::Array(_, size), Some(string)) => global.set_initializer(&self.const_string(&string, *size)),
+                    _ => global.set_initializer(&llvm_type.const_zero()),
                 }
+
+                let global_var = GlobalVariable::new(identifier.to_string(), value_type, global);
+                self.global_vars.insert(identifier.to_string(), global_var);
             }
-            ValueType::Float => {
-                match value {
-                    VariableValue::Int(value) => self.add_global_float(identifier, value as f32),
-                    VariableValue::Float(value) => self.add_global_float(identifier, value),
-                }
+            (ValueType::Array(_, _) | ValueType::Struct(_), Some(_)) => {
+                return error(node, format!("{:?} 型の初期化子は未対応です", value_type));
             }
-            _ => panic!("未対応の型です"),
+            (ValueType::Void, _) => return error(node, "void 型の変数は定義できません".to_string()),
         }
+
+        Ok(())
     }
 
-    fn get_identifier(&self, node: &Rc<RefCell<Node>>) -> String {
-        if let Some(val) = node.borrow().val() {
-            if let Leaf::Identifier(identifier) = val {
-                return identifier.clone();
-            }
+    fn get_identifier(&self, node: &Rc<RefCell<Node>>) -> Result<String, CompileError> {
+        match node.borrow().val() {
+            Some(Leaf::Identifier(identifier)) => Ok(identifier.clone()),
+            _ => error(node, "識別子が取得できませんでした".to_string()),
         }
-
-        panic!("識別子が取得できませんでした");
     }
 
     fn add_global_int(&mut self, name: &str, value: i32) {
+        self.global_constants.insert(name.to_string(), VariableValue::Int(value));
+
         let int_type = self.context.i32_type();
         let global = self.module.add_global(int_type, None, name);
         let const_value = int_type.const_int(value as u64, false);
         global.set_initializer(&const_value);
 
         let global_var =
-            GlobalVariable::new(name.to_string(), VariableValue::Int(value), global);
+            GlobalVariable::new(name.to_string(), ValueType::Int, global);
         self.global_vars.insert(name.to_string(), global_var);
     }
 
-    fn add_global_float(&mut self, name: &str, value: f32) {
-        let float_type = self.context.f32_type();
+    fn add_global_float(&mut self, name: &str, value: f64) {
+        self.global_constants.insert(name.to_string(), VariableValue::Float(value));
+
+        let float_type = self.context.f64_type();
         let global = self.module.add_global(float_type, None, name);
-        let const_value = float_type.const_float(value as f64);
+        let const_value = float_type.const_float(value);
+        global.set_initializer(&const_value);
+
+        let global_var =
+            GlobalVariable::new(name.to_string(), ValueType::Float, global);
+        self.global_vars.insert(name.to_string(), global_var);
+    }
+
+    fn add_global_char(&mut self, name: &str, value: i8) {
+        // 初期化子からは int として参照する
+        self.global_constants.insert(name.to_string(), VariableValue::Int(value as i32));
+
+        let char_type = self.context.i8_type();
+        let global = self.module.add_global(char_type, None, name);
+        let const_value = char_type.const_int(value as u64, true);
         global.set_initializer(&const_value);
 
         let global_var =
-            GlobalVariable::new(name.to_string(), VariableValue::Float(value), global);
+            GlobalVariable::new(name.to_string(), ValueType::Char, global);
         self.global_vars.insert(name.to_string(), global_var);
     }
 
-    fn get_constant_value(&self, node: &Rc<RefCell<Node>>) -> Option<VariableValue>
+    /// 文字列を size 個の char の定数配列にする. 足りない分は '\0' で埋める
+    fn const_string(&self, string: &str, size: usize) -> ArrayValue<'ctx> {
+        let char_type = self.context.i8_type();
+        let mut values: Vec<IntValue<'ctx>> = string.bytes().map(|c| char_type.const_int(c as u64, false)).collect();
+        values.resize(size, char_type.const_zero());
+        char_type.const_array(&values)
+    }
+
+    /// 文字列を '\0' で終わる char の配列としてモジュールに置き, その先頭を返す
+    fn string_pointer(&self, string: &str) -> PointerValue<'ctx> {
+        let value = self.const_string(string, string.len() + 1);
+        let global = self.module.add_global(value.get_type(), None, "string");
+        global.set_linkage(Linkage::Private);
+        global.set_initializer(&value);
+        global.as_pointer_value()
+    }
+
+    /// 初期化子をコンパイル時に評価する. 演算はインタプリタと同じ規則で行う
+    fn get_constant_value(&self, node: &Rc<RefCell<Node>>) -> Result<VariableValue, CompileError>
     {
-        if let Some(val) = node.borrow().val() {
-            if let Leaf::Constant(constant) = val {
-                match constant {
-                    Constant::Integer(value) => return Some(VariableValue::Int(*value)),
-                    Constant::Float(value) => return Some(VariableValue::Float(*value)),
-                }
+        match self.constant_expression(node)? {
+            VariableType::Int(value) => Ok(VariableValue::Int(value)),
+            VariableType::Float(value) => Ok(VariableValue::Float(value)),
+            VariableType::Char(value) => Ok(VariableValue::Int(value as i32)),
+            value => error(node, format!("定数式の値が不正です : {}", value)),
+        }
+    }
+
+    fn constant_expression(&self, node: &Rc<RefCell<Node>>) -> Result<VariableType, CompileError>
+    {
+        let val = node.borrow().val().cloned();
+        let lhs = node.borrow().lhs().cloned();
+        let rhs = node.borrow().rhs().cloned();
+        let kind_error = |kind: RuntimeErrorKind| CompileError::new(kind.to_string(), node.borrow().span().clone());
+
+        match (val, lhs, rhs) {
+            (Some(Leaf::Constant(Constant::Integer(value))), _, _) => Ok(VariableType::Int(value)),
+            (Some(Leaf::Constant(Constant::Float(value))), _, _) => Ok(VariableType::Float(value as f64)),
+            (Some(Leaf::Constant(Constant::Char(value))), _, _) => Ok(VariableType::Char(value)),
+
+            // 先に定義された定数のグローバル変数は参照できる
+            (Some(Leaf::Identifier(identifier)), _, _) => match self.global_constants.get(&identifier) {
+                Some(VariableValue::Int(value)) => Ok(VariableType::Int(*value)),
+                Some(VariableValue::Float(value)) => Ok(VariableType::Float(*value)),
+                None => error(node, format!("{} は定数ではありません", identifier)),
+            },
+            (Some(Leaf::ParenthesizedExpression), Some(lhs), _) => self.constant_expression(&lhs),
+            (Some(Leaf::UnaryExpression(op)), Some(lhs), _) => {
+                let value = self.constant_expression(&lhs)?;
+                Interpreter::unary_operator(&op, value).map_err(kind_error)
             }
+            (Some(Leaf::Operator(op @ (Operator::LogicalAnd | Operator::LogicalOr))), Some(lhs), Some(rhs)) => {
+                // 左辺で結果が決まる場合は右辺を評価しない
+                let lhs = Interpreter::truth_value(self.constant_expression(&lhs)?).map_err(kind_error)?;
+                let value = match (op, lhs) {
+                    (Operator::LogicalOr, true) => true,
+                    (Operator::LogicalAnd, false) => false,
+                    _ => Interpreter::truth_value(self.constant_expression(&rhs)?).map_err(kind_error)?,
+                };
+                Ok(VariableType::Int(value as i32))
+            }
+            (Some(Leaf::Operator(op)), Some(lhs), Some(rhs)) => {
+                let lhs = self.constant_expression(&lhs)?;
+                let rhs = self.constant_expression(&rhs)?;
+                Interpreter::binary_operator(&op, lhs, rhs).map_err(kind_error)
+            }
+            (Some(val), _, _) => error(node, format!("グローバル変数の初期値は定数式である必要があります : {}", val)),
+            (None, _, _) => error(node, "定数式が取得できませんでした".to_string()),
         }
+    }
 
-        None
+    /// 宣言の型を返す. 右辺が要素数の場合は配列型にする
+    fn get_variable_type(&self, node: &Rc<RefCell<Node>>) -> Result<ValueType, CompileError> {
+        let decl_type = match node.borrow().val() {
+            Some(Leaf::Declaration(decl_type)) => decl_type.clone(),
+            _ => return error(node, "変数の型が取得できませんでした".to_string()),
+        };
+
+        match node.borrow().rhs().map(|rhs| rhs.borrow().val().cloned()) {
+            Some(Some(Leaf::Array(size))) => Ok(ValueType::Array(Box::new(decl_type), size)),
+            _ => Ok(decl_type),
+        }
     }
 
-    fn get_variable_type(&self, node: &Rc<RefCell<Node>>) -> ValueType {
-        if let Some(val) = node.borrow().val() {
-            if let Leaf::Declaration(decl_type) = val {
-                return decl_type.clone();
-            }
+    /// 宣言の初期化子を返す. 配列の要素数は初期化子ではない
+    fn get_initializer(&self, node: &Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>> {
+        let rhs = node.borrow().rhs().cloned()?;
+        if let Some(Leaf::Array(_)) = rhs.borrow().val() {
+            return None;
         }
+        Some(rhs)
+    }
 
-        panic!("変数の型が取得できませんでした");
+    /// char の配列の初期化子の文字列を返す
+    fn get_string_initializer(&self, node: &Rc<RefCell<Node>>) -> Option<String> {
+        let rhs = node.borrow().rhs().cloned()?;
+        let string = rhs.borrow().lhs()?.borrow().val().cloned();
+        match string {
+            Some(Leaf::StringLiteral(string)) => Some(string),
+            _ => None,
+        }
     }
 
     // ビットコードをファイルに書き出す
@@ -434,6 +1340,170 @@ impl<'ctx> CodeGen<'ctx>
         self.module.write_bitcode_to_path(Path::new(path));
     }
 
+    /// すべての関数を宣言してから構文木を LLVM IR にし, 検証する
+    pub fn generate_all(&mut self, roots: &[Rc<RefCell<Node>>]) -> Result<(), CompileError> {
+        self.declare_functions(roots)?;
+        for root in roots {
+            self.generate(root)?;
+        }
+
+        self.module.verify().map_err(|message| CompileError::new(
+            format!("LLVM IR の検証に失敗しました : {}", message.to_string()), Span::default()))
+    }
+
+    /// ホスト向けの TargetMachine を作成し, モジュールに triple とデータレイアウトを設定する
+    fn create_target_machine(&self, optimization_level: OptimizationLevel) -> Result<TargetMachine, CompileError> {
+        initialize_native_target()?;
+
+        let triple = TargetMachine::get_default_triple();
+        let target = Target::from_triple(&triple)
+            .map_err(|message| CompileError::new(
+                format!("ターゲットが見つかりません : {}", message.to_string()), Span::default()))?;
+        let cpu = TargetMachine::get_host_cpu_name();
+        let features = TargetMachine::get_host_cpu_features();
+        let target_machine = target.create_target_machine(
+            &triple,
+            &cpu.to_string(),
+            &features.to_string(),
+            optimization_level,
+            RelocMode::PIC,
+            CodeModel::Default,
+        ).ok_or_else(|| CompileError::new(
+            format!("TargetMachine を作成できません : {}", triple), Span::default()))?;
+
+        self.module.set_triple(&triple);
+        self.module.set_data_layout(&target_machine.get_target_data().get_data_layout());
+        Ok(target_machine)
+    }
+
+    /// 新しいパスマネージャの default<On> パイプラインでモジュールを最適化する
+    pub fn optimize(&self, target_machine: &TargetMachine, options: &CompileOptions) -> Result<(), CompileError> {
+        if options.print_ir() {
+            eprintln!("## 最適化前の LLVM IR");
+            self.module.print_to_stderr();
+        }
+
+        let passes = format!("default<O{}>", options.level());
+        self.module.run_passes(&passes, target_machine, PassBuilderOptions::create())
+            .map_err(|message| CompileError::new(
+                format!("最適化に失敗しました : {}", message.to_string()), Span::default()))?;
+
+        if options.print_ir() {
+            eprintln!("## 最適化後の LLVM IR");
+            self.module.print_to_stderr();
+        }
+
+        Ok(())
+    }
+
+    /// ホスト向けのオブジェクトファイルを書き出す
+    pub fn write_object_file(&self, path: &Path, options: &CompileOptions) -> Result<(), CompileError> {
+        let target_machine = self.create_target_machine(options.optimization_level())?;
+        self.optimize(&target_machine, options)?;
+
+        target_machine.write_to_file(&self.module, FileType::Object, path)
+            .map_err(|message| CompileError::new(
+                format!("{} に書き出せません : {}", path.display(), message.to_string()), Span::default()))
+    }
+
+    /// main 関数を JIT コンパイルして実行し, 戻り値と実行後のグローバル変数を返す
+    pub fn run_main(&self, options: &CompileOptions) -> Result<(i32, HashMap<String, Variable>), CompileError> {
+        let main_type = match self.function_types.get("main") {
+            Some(main_type) => main_type.clone(),
+            None => return Err(CompileError::new("main 関数が定義されていません".to_string(), Span::default())),
+        };
+
+        let target_machine = self.create_target_machine(options.optimization_level())?;
+        self.optimize(&target_machine, options)?;
+
+        let execution_engine = self.module.create_jit_execution_engine(options.optimization_level())
+            .map_err(|message| CompileError::new(
+                format!("JIT の作成に失敗しました : {}", message.to_string()), Span::default()))?;
+
+        // SAFETY: main は引数を取らず, 宣言した型で呼び出す
+        let return_value = unsafe {
+            match main_type {
+                ValueType::Int => {
+                    let main = execution_engine.get_function::<unsafe extern "C" fn() -> i32>("main")
+                        .map_err(|error| CompileError::new(format!("main 関数が取得できません : {}", error), Span::default()))?;
+                    main.call()
+                }
+                ValueType::Void => {
+                    let main = execution_engine.get_function::<unsafe extern "C" fn()>("main")
+                        .map_err(|error| CompileError::new(format!("main 関数が取得できません : {}", error), Span::default()))?;
+                    main.call();
+                    0
+                }
+                _ => return Err(CompileError::new("main 関数の戻り値は int である必要があります".to_string(), Span::default())),
+            }
+        };
+
+        Ok((return_value, self.read_globals(&execution_engine)?))
+    }
+
+    /// 実行後のグローバル変数をインタプリタと同じ形で読み出す
+    fn read_globals(&self, execution_engine: &ExecutionEngine<'ctx>) -> Result<HashMap<String, Variable>, CompileError> {
+        let mut globals = HashMap::new();
+        for (name, global) in self.global_vars.iter() {
+            // コード生成済みのモジュールでは, 関数以外のシンボルもこのアドレス検索で引ける
+            let address = execution_engine.get_function_address(name)
+                .map_err(|error| CompileError::new(
+                    format!("グローバル変数 {} が取得できません : {}", name, error), Span::default()))?;
+
+            // SAFETY: JIT が確保したグローバル変数の領域を, 宣言した型のレイアウトで読む
+            let value = unsafe { self.read_value(execution_engine.get_target_data(), name, address, &global.value_type)? };
+            let variable = match value {
+                VariableType::Array(array) => Variable::Array(array),
+                VariableType::Struct(s) => Variable::Struct(s),
+                value => Variable::Value(value),
+            };
+            globals.insert(name.clone(), variable);
+        }
+
+        Ok(globals)
+    }
+
+    unsafe fn read_value(&self, target_data: &TargetData, name: &str, address: usize, value_type: &ValueType)
+                         -> Result<VariableType, CompileError>
+    {
+        let node = Rc::new(RefCell::new(Node::new()));
+        match value_type {
+            ValueType::Int => Ok(VariableType::Int(std::ptr::read(address as *const i32))),
+            ValueType::Float => Ok(VariableType::Float(std::ptr::read(address as *const f64))),
+            ValueType::Char => Ok(VariableType::Char(std::ptr::read(address as *const i8))),
+            ValueType::Array(element, size) => {
+                let stride = target_data.get_abi_size(&self.get_value_type(&node, element)?) as usize;
+                let mut values = Vec::new();
+                for i in 0..*size {
+                    values.push(self.read_value(target_data, name, address + i * stride, element)?);
+                }
+
+                // インタプリタと同様に要素の初期値を配列の型とする
+                let template = match **element {
+                    ValueType::Float => VariableType::Float(0.0),
+                    ValueType::Char => VariableType::Char(0),
+                    _ => VariableType::Int(0),
+                };
+                let array = Array::new(name.to_string(), template, values);
+                Ok(VariableType::Array(Rc::new(RefCell::new(array))))
+            }
+            ValueType::Struct(struct_name) => {
+                let (struct_type, members) = match self.struct_types.get(struct_name) {
+                    Some(struct_type) => struct_type,
+                    None => return error(&node, format!("未定義の構造体です : {}", struct_name)),
+                };
+
+                let mut values = HashMap::new();
+                for (i, (member, member_type)) in members.iter().enumerate() {
+                    let offset = target_data.offset_of_element(struct_type, i as u32).unwrap_or_default() as usize;
+                    values.insert(member.clone(), self.read_value(target_data, member, address + offset, member_type)?);
+                }
+                Ok(VariableType::Struct(Struct::new(struct_name.clone(), values)))
+            }
+            ValueType::Void => error(&node, "void 型の変数は読み出せません".to_string()),
+        }
+    }
+
     // ----------------------------------------------------------------------------------------
     // デバッグ用関数群
 
@@ -462,26 +1532,214 @@ impl<'ctx> CodeGen<'ctx>
 }
 
 
-pub fn compile(roots: &Vec<Rc<RefCell<Node>>>) -> Result<(), Box<dyn Error>> {
+/// 構文木をコンパイルし, ホスト向けのオブジェクトファイルを output に書き出す
+pub fn compile(roots: &[Rc<RefCell<Node>>], output: &Path, options: &CompileOptions) -> Result<(), CompileError> {
     let context = Context::create();
     let module = context.create_module("main");
     let builder = context.create_builder();
-    let execution_engine = module.create_jit_execution_engine(OptimizationLevel::None)?;
 
-    let mut codegen = CodeGen::new(&context, module, builder, execution_engine);
-    codegen.define_printf();
+    let mut codegen = CodeGen::new(&context, module, builder);
 
-    println!("## コンパイル開始");
+    codegen.generate_all(roots)?;
+    codegen.write_object_file(output, options)
+}
+
+/// 構文木を JIT コンパイルし, main の戻り値を返す
+pub fn jit_run(roots: &[Rc<RefCell<Node>>], options: &CompileOptions) -> Result<i32, CompileError> {
+    Ok(jit_run_with_globals(roots, options)?.0)
+}
 
-    for root in roots {
-        codegen.generate(root)?;
+/// 構文木を JIT コンパイルし, main の戻り値と実行後のグローバル変数を返す
+pub fn jit_run_with_globals(roots: &[Rc<RefCell<Node>>], options: &CompileOptions)
+                            -> Result<(i32, HashMap<String, Variable>), CompileError>
+{
+    let context = Context::create();
+    let module = context.create_module("main");
+    let builder = context.create_builder();
+
+    let mut codegen = CodeGen::new(&context, module, builder);
+    codegen.generate_all(roots)?;
+    codegen.run_main(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{Interpreter, VariableType};
+    use crate::lexical::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(program: &str) -> Vec<Rc<RefCell<Node>>> {
+        let mut lexer = Lexer::with_file_name(program.to_string(), "test.c");
+        lexer.tokenize().unwrap();
+        let mut parser = Parser::with_spans(lexer.tokens().clone(), lexer.spans().clone());
+        parser.parse().unwrap();
+        parser.roots().clone()
     }
 
+    /// JIT で main を実行し, 最適化後の LLVM IR と戻り値を返す
+    fn run(program: &str, options: &CompileOptions) -> (String, i32) {
+        let context = Context::create();
+        let mut codegen = CodeGen::new(&context, context.create_module("main"), context.create_builder());
+        codegen.generate_all(&parse(program)).unwrap();
+        let value = codegen.run_main(options).unwrap().0;
+        (codegen.module.print_to_string().to_string(), value)
+    }
 
-    // 中間コードを標準出力に出力
-    codegen.module.print_to_stderr();
+    /// インタプリタで実行した main の戻り値
+    fn interpret(program: &str) -> VariableType {
+        Interpreter::new(&parse(program)).run().unwrap()
+    }
 
-    codegen.write_bitcode("output.bc");
+    #[test]
+    fn test_locals_and_parameters() {
+        let program = "
+            int add(int a, int b) {
+                int sum = a + b;
+                return sum;
+            }
 
-    Ok(())
+            float half(float x) {
+                float y = x / 2.0;
+                return y;
+            }
+
+            int main() {
+                int a = 4;
+                float f = half(5.0);
+                f = f * a;
+                a = add(a, 6) * 2;
+                return a * 10 + (f > 9.5);
+            }
+        ";
+
+        // 引数とローカル変数は関数の先頭の alloca に置かれる
+        let (ir, value) = run(program, &CompileOptions::default());
+        assert!(ir.contains("define i32 @add(i32 %a, i32 %b)"), "{}", ir);
+        assert!(ir.contains("define double @half(double %x)"), "{}", ir);
+        assert!(ir.contains("%sum = alloca i32"), "{}", ir);
+        assert!(ir.contains("%y = alloca double"), "{}", ir);
+
+        assert_eq!(value, 201);
+        assert_eq!(VariableType::Int(value), interpret(program));
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let program = "
+            int calls = 0;
+
+            int touch() {
+                calls = calls + 1;
+                return 1;
+            }
+
+            int main() {
+                int x = 0;
+                int total = 0;
+                int i;
+                if (x != 0 && 10 / x > 1) {
+                    total = 100;
+                }
+                if (x == 0 || touch()) {
+                    total = total + 1;
+                }
+                for (i = 0; i < 10; i = i + 1) {
+                    if (i % 2 == 1) {
+                        continue;
+                    }
+                    if (i > 6) {
+                        break;
+                    }
+                    total = total + i;
+                }
+                while (1) {
+                    x = x + 1;
+                    if (x >= 3) {
+                        break;
+                    }
+                }
+                return total * 10 + x + calls;
+            }
+        ";
+
+        // && と || は右辺を別のブロックに置き, phi で合流させる
+        let (ir, value) = run(program, &CompileOptions::default());
+        assert!(ir.contains("logical.rhs"), "{}", ir);
+        assert!(ir.contains("phi i1"), "{}", ir);
+        assert!(ir.contains("for.update"), "{}", ir);
+        assert!(ir.contains("while.end"), "{}", ir);
+
+        // 右辺を飛ばすので 0 で割らず, touch も呼ばれない
+        assert_eq!(value, 133);
+        assert_eq!(VariableType::Int(value), interpret(program));
+
+        let error = jit_run(&parse("int main() { break; return 0; }"), &CompileOptions::default()).unwrap_err();
+        assert!(error.message().starts_with("ループの外で"), "{}", error);
+    }
+
+    #[test]
+    fn test_optimization_levels() {
+        let program = "
+            int square(int n) {
+                int result = n * n;
+                return result;
+            }
+
+            int main() {
+                int total = 0;
+                int i;
+                for (i = 1; i <= 4; i = i + 1) {
+                    total = total + square(i);
+                }
+                return total;
+            }
+        ";
+
+        for level in 0..=3 {
+            // -O1 以上では mem2reg でローカル変数がレジスタに昇格する
+            let (ir, value) = run(program, &CompileOptions::new(level, false).unwrap());
+            assert_eq!(ir.contains("alloca"), level == 0, "-O{}\n{}", level, ir);
+
+            assert_eq!(value, 30, "-O{}", level);
+            assert_eq!(VariableType::Int(value), interpret(program), "-O{}", level);
+        }
+
+        let error = CompileOptions::new(4, false).unwrap_err();
+        assert_eq!(error.message(), "最適化レベルは 0 から 3 までです : 4");
+    }
+
+    #[test]
+    fn test_constant_global_initializers() {
+        let program = "
+            int base = 2 + 3 * 4;
+            int twice = base * 2;
+            float ratio = twice / 8.0;
+            int flag = base > 10 && twice < 0;
+
+            int main() {
+                return twice + flag + (ratio == 3.5);
+            }
+        ";
+
+        // 初期化子はコンパイル時に評価され, 定数としてモジュールに置かれる
+        let (ir, value) = run(program, &CompileOptions::default());
+        assert!(ir.contains("@base = global i32 14"), "{}", ir);
+        assert!(ir.contains("@twice = global i32 28"), "{}", ir);
+        assert!(ir.contains("@ratio = global double 3.500000e+00"), "{}", ir);
+        assert!(ir.contains("@flag = global i32 0"), "{}", ir);
+
+        assert_eq!(value, 29);
+        assert_eq!(VariableType::Int(value), interpret(program));
+
+        // 0 で割る初期化子はその位置のコンパイルエラーになる
+        let error = jit_run(&parse("int broken = 1 / (2 - 2);\nint main() { return 0; }"),
+                            &CompileOptions::default()).unwrap_err();
+        assert_eq!(error.message(), RuntimeErrorKind::DivisionByZero.to_string());
+        assert_eq!((error.span().line(), error.span().column()), (1, 14));
+
+        let error = jit_run(&parse("int count = 1;\nint copy = count + main();\nint main() { return 0; }"),
+                            &CompileOptions::default()).unwrap_err();
+        assert!(error.message().starts_with("グローバル変数の初期値は定数式である必要があります"), "{}", error);
+    }
 }
@@ -1,7 +1,8 @@
 use crate::lexical::Operator;
 use std::cell::{Ref, RefCell};
 use std::rc::{Rc, Weak};
-use crate::lexical::{Constant, Token, ValueType, UnaryOperator};
+use crate::lexical::{Constant, Span, Token, ValueType, UnaryOperator};
+use crate::resolver::Binding;
 
 #[derive(Debug, Clone)]
 pub struct FunctionCall {
@@ -216,6 +217,7 @@ pub enum Leaf
 
     // 定数
     Constant(Constant),
+    StringLiteral(String),
 }
 
 // Leaf の format 出力
@@ -241,6 +243,7 @@ impl std::fmt::Display for Leaf
             Leaf::Identifier(identifier) => write!(f, "Identifier [{:?}]", identifier),
             Leaf::Operator(operator) => write!(f, "Operator [{:?}]", operator),
             Leaf::Constant(constant) => write!(f, "Constant [{:?}]", constant),
+            Leaf::StringLiteral(string) => write!(f, "StringLiteral [{:?}]", string),
             Leaf::WhileStatement => write!(f, "WhileStatement"),
             Leaf::ForStatement(_) => write!(f, "ForStatement"),
             Leaf::Array(size) => write!(f, "Array [{:?}]", size),
@@ -258,6 +261,10 @@ pub struct Node {
     rhs: Option<Rc<RefCell<Node>>>,
     val: Option<Leaf>,
     parent: Weak<RefCell<Node>>,
+    span: Span,
+
+    // 識別子の参照先. Resolver が設定する
+    binding: Option<Binding>,
 }
 
 impl Node {
@@ -267,6 +274,8 @@ impl Node {
             rhs: None,
             val: None,
             parent: Weak::new(),
+            span: Span::default(),
+            binding: None,
         }
     }
 
@@ -274,6 +283,16 @@ impl Node {
         self.val.as_ref()
     }
 
+    /// このノードが表すソース上の範囲
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// 識別子の参照先. 解決前は None
+    pub fn binding(&self) -> Option<Binding> {
+        self.binding
+    }
+
     pub fn lhs(&self) -> Option<&Rc<RefCell<Node>>> {
         self.lhs.as_ref()
     }
@@ -328,6 +347,9 @@ impl Node {
                 Leaf::Constant(constant) => {
                     println!("Constant [{:?}]", constant);
                 }
+                Leaf::StringLiteral(string) => {
+                    println!("StringLiteral [{:?}]", string);
+                }
                 Leaf::Return => {
                     println!("Return");
                 }
@@ -387,22 +409,84 @@ impl Node {
     pub fn set_val(&mut self, leaf: Leaf) {
         self.val = Some(leaf);
     }
+
+    pub fn set_span(&mut self, span: Span) {
+        self.span = span;
+    }
+
+    pub fn set_binding(&mut self, binding: Binding) {
+        self.binding = Some(binding);
+    }
+}
+
+/// 構文解析のエラー
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    message: String,
+    // None の場合は入力の終端
+    found: Option<Token>,
+    span: Span,
 }
 
+impl ParseError {
+    pub fn new(message: String, found: Option<Token>, span: Span) -> Self {
+        ParseError {
+            message,
+            found,
+            span,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn found(&self) -> Option<&Token> {
+        self.found.as_ref()
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+impl std::fmt::Display for ParseError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        match &self.found {
+            Some(token) => write!(f, "{}: {} : {:?}", self.span, self.message, token),
+            None => write!(f, "{}: {} : EOF", self.span, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 #[derive(Debug, Clone)]
 pub struct Parser {
     tokens: Vec<Token>,
+    spans: Vec<Span>,
     roots: Vec<Rc<RefCell<Node>>>,
     token_index: usize,
+    errors: Vec<ParseError>,
 }
 
 impl Parser
 {
     pub fn new(tokens: Vec<Token>) -> Self {
+        let spans = vec![Span::default(); tokens.len()];
+        Parser::with_spans(tokens, spans)
+    }
+
+    /// Lexer::spans() で得た位置情報とともに Parser を作成する
+    pub fn with_spans(tokens: Vec<Token>, spans: Vec<Span>) -> Self {
         Parser {
             tokens,
+            spans,
             roots: Vec::new(),
             token_index: 0,
+            errors: Vec::new(),
         }
     }
 
@@ -414,18 +498,6 @@ impl Parser
         self.roots.first().unwrap()
     }
 
-    fn get_next_token(&mut self) -> Option<Token>
-    {
-        if self.token_index < self.tokens.len() {
-            println!("token_index: {}, Token : {:?}", self.token_index, self.tokens[self.token_index]);
-            let result = Some(self.tokens[self.token_index].clone());
-            self.token_index += 1;
-            result
-        } else {
-            None
-        }
-    }
-
     fn get_next_token_without_increment(&self) -> Option<Token>
     {
         if self.token_index < self.tokens.len() {
@@ -443,9 +515,115 @@ impl Parser
         self.token_index += 1;
     }
 
-    pub fn parse(&mut self)
+    /// index 番目のトークンの位置. 範囲外の場合は最後のトークンの位置を返す
+    fn token_span(&self, index: usize) -> Span
+    {
+        self.spans.get(index)
+            .or(self.spans.last())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// start 番目のトークンから直前に読んだトークンまでの範囲
+    fn span_from(&self, start: usize) -> Span
+    {
+        if self.token_index <= start {
+            return self.token_span(start);
+        }
+
+        self.token_span(start).to(&self.token_span(self.token_index - 1))
+    }
+
+    /// ノードに start 番目のトークンから直前に読んだトークンまでの範囲を設定する
+    fn set_span(&self, node: &Rc<RefCell<Node>>, start: usize)
     {
+        node.borrow_mut().set_span(self.span_from(start));
+    }
+
+    /// 現在のトークンを指すエラーを作成する
+    fn error(&self, message: &str) -> ParseError
+    {
+        ParseError::new(
+            message.to_string(),
+            self.tokens.get(self.token_index).cloned(),
+            self.token_span(self.token_index),
+        )
+    }
+
+    /// 次のトークンが expected であることを確認して読み進める.
+    /// 一致しない場合はトークンを読み進めずにエラーを返す
+    fn expect(&mut self, expected: Token, message: &str) -> Result<(), ParseError>
+    {
+        if self.get_next_token_without_increment() == Some(expected) {
+            self.token_index_increment();
+            Ok(())
+        } else {
+            Err(self.error(message))
+        }
+    }
+
+    /// 次のトークンが識別子であることを確認して読み進める
+    fn expect_identifier(&mut self, message: &str) -> Result<String, ParseError>
+    {
+        if let Some(Token::Identifier(identifier)) = self.get_next_token_without_increment() {
+            self.token_index_increment();
+            Ok(identifier)
+        } else {
+            Err(self.error(message))
+        }
+    }
+
+    /// エラーの後, 次の文または宣言の先頭までトークンを読み飛ばす.
+    /// ';' と '{' ... '}' のブロックは読み飛ばし, 対応のない '}' は呼び出し元で処理するため読み飛ばさない
+    fn synchronize(&mut self)
+    {
+        while let Some(token) = self.get_next_token_without_increment() {
+            match token {
+                Token::Semicolon => {
+                    self.token_index_increment();
+                    return;
+                }
+                Token::RightBrace => {
+                    return;
+                }
+                Token::LeftBrace => {
+                    // ブロックは対応する '}' まで読み飛ばす
+                    let mut depth = 0;
+                    while let Some(token) = self.get_next_token_without_increment() {
+                        self.token_index_increment();
+                        match token {
+                            Token::LeftBrace => depth += 1,
+                            Token::RightBrace => depth -= 1,
+                            _ => {}
+                        }
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    return;
+                }
+                _ => {
+                    self.token_index_increment();
+                }
+            }
+        }
+    }
+
+    /// 構文解析を行い, 構文木のルートを返す.
+    /// エラーがあっても文や宣言の区切りから解析を再開し, 見つかったエラーをすべて返す
+    pub fn parse(&mut self) -> Result<Vec<Rc<RefCell<Node>>>, Vec<ParseError>>
+    {
+        self.token_index = 0;
+        self.roots.clear();
+        self.errors.clear();
+
         self.translation_unit();
+
+        if self.errors.is_empty() {
+            Ok(self.roots.clone())
+        } else {
+            Err(self.errors.clone())
+        }
     }
 
     fn translation_unit(&mut self)
@@ -453,137 +631,135 @@ impl Parser
         while self.token_index < self.tokens.len() {
             println!("TranslationUnit token_index: {}", self.token_index);
             // トークンがなくなるまで繰り返す
-            self.external_declaration();
+            if let Err(error) = self.external_declaration() {
+                self.errors.push(error);
+                self.synchronize();
+
+                // トップレベルで余った '}' は読み飛ばす
+                if let Some(Token::RightBrace) = self.get_next_token_without_increment() {
+                    self.token_index_increment();
+                }
+            }
         }
     }
 
     /// 関数定義かグローバル変数定義かを判定する
-    fn external_declaration(&mut self)
+    fn external_declaration(&mut self) -> Result<(), ParseError>
     {
         // 関数の場合は type_specifier, identify, ( となり '(' が続く場合は関数として処理する
 
         let token = self.tokens[self.token_index].clone();
         match token {
             Token::Type(_) => {
-                let next_token = self.tokens[self.token_index + 2].clone();
+                let next_token = self.tokens.get(self.token_index + 2);
 
-                if next_token == Token::LeftParen {
+                if next_token == Some(&Token::LeftParen) {
                     println!("function_definition");
-                    self.function_definition();
+                    self.function_definition()?;
                 } else {
                     println!("declaration : {}", self.token_index);
-                    let root = self.declaration();
+                    let root = self.declaration()?;
                     self.roots.push(root);
                 }
             }
             _ => {
-                let root = self.statement();
+                let root = self.statement()?;
                 self.roots.push(root);
             }
         }
+
+        Ok(())
     }
 
-    fn function_definition(&mut self)
+    fn function_definition(&mut self) -> Result<(), ParseError>
     {
+        let start = self.token_index;
         let mut function_definition = FunctionDefinition::new();
 
         // 関数定義の型を取得
-        if let Some(Token::Type(type_specifier)) = self.get_next_token() {
+        if let Some(Token::Type(type_specifier)) = self.get_next_token_without_increment() {
+            self.token_index_increment();
             function_definition.set_type_specifier(type_specifier);
         } else {
-            panic!("型が見つかりませんでした : {:?}", self.tokens[self.token_index]);
+            return Err(self.error("型が見つかりませんでした"));
         }
 
         // 関数定義の識別子を取得
-        if let Some(Token::Identifier(identifier)) = self.get_next_token() {
-            function_definition.set_identify(identifier);
-        } else {
-            panic!("識別子が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        let identifier = self.expect_identifier("識別子が見つかりませんでした")?;
+        function_definition.set_identify(identifier);
 
         // 関数定義の引数リストを取得
-        if let Some(Token::LeftParen) = self.get_next_token() {
-            // 引数がない場合は ')' が来る
-            if let Some(Token::RightParen) = self.get_next_token_without_increment() {
-                self.token_index_increment();
-            } else {
-                self.parameter_list(&mut function_definition);
-                // ')' が来ることを確認
-                if let Some(Token::RightParen) = self.get_next_token() {
-                    // 何もしない
-                } else {
-                    panic!("')' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-                }
-            }
+        self.expect(Token::LeftParen, "'(' が見つかりませんでした")?;
+
+        // 引数がない場合は ')' が来る
+        if let Some(Token::RightParen) = self.get_next_token_without_increment() {
+            self.token_index_increment();
+        } else {
+            self.parameter_list(&mut function_definition)?;
+            // ')' が来ることを確認
+            self.expect(Token::RightParen, "')' が見つかりませんでした")?;
         }
 
         // 関数定義の本体を取得. '{', '}' の処理は compound_statement 内部で行う
-        let roots = self.compound_statement();
+        let roots = self.compound_statement()?;
         function_definition.body = roots;
 
         let root = Rc::new(RefCell::new(Node::new()));
         root.borrow_mut().set_val(Leaf::FunctionDefinition(function_definition));
+        self.set_span(&root, start);
 
         self.roots.push(root);
+
+        Ok(())
     }
 
-    fn compound_statement(&mut self) -> Vec<Rc<RefCell<Node>>>
+    fn compound_statement(&mut self) -> Result<Vec<Rc<RefCell<Node>>>, ParseError>
     {
         let mut roots: Vec<Rc<RefCell<Node>>> = Vec::new();
         println!("compound_statement");
         // '{' が来ることを確認
-        if let Some(Token::LeftBrace) = self.get_next_token() {
-            // 何もしない
-        } else {
-            panic!("'{{' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        self.expect(Token::LeftBrace, "'{' が見つかりませんでした")?;
 
         // '}' が来るまで繰り返す
         loop {
-            if let Some(Token::RightBrace) = self.get_next_token_without_increment() {
-                break;
+            match self.get_next_token_without_increment() {
+                Some(Token::RightBrace) | None => break,
+                _ => {}
             }
 
-            let root = self.block_item();
-            roots.push(root);
+            // エラーがあった場合は記録して次の文から解析を再開する
+            match self.block_item() {
+                Ok(root) => roots.push(root),
+                Err(error) => {
+                    self.errors.push(error);
+                    self.synchronize();
+                }
+            }
         }
 
         // '}' が来ることを確認
-        if let Some(Token::RightBrace) = self.get_next_token() {
-            // 何もしない
-        } else {
-            panic!("'}}' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        self.expect(Token::RightBrace, "'}' が見つかりませんでした")?;
 
-        roots
+        Ok(roots)
     }
 
-    fn block_item(&mut self) -> Rc<RefCell<Node>>
+    fn block_item(&mut self) -> Result<Rc<RefCell<Node>>, ParseError>
     {
-        let mut root = Rc::new(RefCell::new(Node::new()));
-
-        if let Some(next_token) = self.get_next_token_without_increment()
+        match self.get_next_token_without_increment()
         {
-            match next_token
-            {
-                // 変数定義の場合
-                Token::Type(type_specifier) => {
-                    root = self.declaration();
-                }
-                Token::Struct => {
-                    root = self.declaration();
-                }
-                _ => {
-                    root = self.statement();
-                }
+            // 変数定義の場合
+            Some(Token::Type(_)) | Some(Token::Struct) => {
+                self.declaration()
+            }
+            _ => {
+                self.statement()
             }
         }
-
-        root
     }
 
-    fn statement(&mut self) -> Rc<RefCell<Node>>
+    fn statement(&mut self) -> Result<Rc<RefCell<Node>>, ParseError>
     {
+        let start = self.token_index;
         let mut root = Rc::new(RefCell::new(Node::new()));
 
         if let Some(next_token) = self.get_next_token_without_increment()
@@ -592,206 +768,148 @@ impl Parser
             {
                 Token::LeftBrace => {
                     // compound_statement の場合
-                    let roots = self.compound_statement();
+                    let roots = self.compound_statement()?;
                     root.borrow_mut().set_val(Leaf::BlockItem(roots));
+                    self.set_span(&root, start);
+                }
+                Token::Semicolon => {
+                    // 空の文は空のブロックとして扱う
+                    self.token_index_increment();
+                    root.borrow_mut().set_val(Leaf::BlockItem(Vec::new()));
+                    self.set_span(&root, start);
                 }
                 Token::If => {
                     // if_statement の場合
-                    root = self.selection_statement();
+                    root = self.selection_statement()?;
                 }
-                Token::While => {
-                    // while_statement の場合
-                    root = self.iteration_statement();
+                Token::While | Token::For => {
+                    // iteration_statement の場合
+                    root = self.iteration_statement()?;
                 }
-                Token::Return => {
+                Token::Return | Token::Break | Token::Continue => {
                     // jump_statement の場合
-                    root = self.jump_statement();
+                    root = self.jump_statement()?;
                 }
                 Token::Struct => {
-                    root = self.declaration();
+                    root = self.declaration()?;
                 }
-                Token::Identifier(identifier) => {
+                _ => {
                     // expression_statement の場合
-                    root = self.expression_statement();
+                    root = self.expression_statement()?;
 
                     // ';' が来ることを確認
-                    self.semicolon();
-                }
-                Token::Break => {
-                    // jump_statement の場合
-                    root = self.jump_statement();
-                }
-                Token::For => {
-                    // iteration_statement の場合
-                    root = self.iteration_statement();
-                }
-                _ => {
-                    // expression_statement の場合
-                    unimplemented!("expression_statement");
+                    self.semicolon()?;
                 }
             }
+        } else {
+            return Err(self.error("文が見つかりませんでした"));
         }
 
-        root
+        Ok(root)
     }
 
-    fn iteration_statement(&mut self) -> Rc<RefCell<Node>>
+    fn iteration_statement(&mut self) -> Result<Rc<RefCell<Node>>, ParseError>
     {
-        let mut root = Rc::new(RefCell::new(Node::new()));
-
         // 最初の トークンを取得して while_statement かどうかを判定
-        if let Some(token) = self.get_next_token_without_increment()
+        match self.get_next_token_without_increment()
         {
-            match token
-            {
-                Token::While => {
-                    root = self.while_statement();
-                }
-                Token::For => {
-                    root = self.for_statement();
-                }
-                _ => {
-                    panic!("while_statement が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-                }
+            Some(Token::While) => {
+                self.while_statement()
+            }
+            Some(Token::For) => {
+                self.for_statement()
+            }
+            _ => {
+                Err(self.error("while_statement が見つかりませんでした"))
             }
         }
-
-        root
     }
 
-    fn for_statement(&mut self) -> Rc<RefCell<Node>>
+    fn for_statement(&mut self) -> Result<Rc<RefCell<Node>>, ParseError>
     {
-        let mut root = Rc::new(RefCell::new(Node::new()));
+        let start = self.token_index;
+        let root = Rc::new(RefCell::new(Node::new()));
 
         // 最初の for トークンを取得
-        if let Some(Token::For) = self.get_next_token()
-        {
-            // 何もしない
-        } else {
-            panic!("'for' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        self.expect(Token::For, "'for' が見つかりませんでした")?;
 
         // 次のトークンが '(' かどうか
-        if let Some(Token::LeftParen) = self.get_next_token()
-        {
-            // 何もしない
-        } else {
-            panic!("'(' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        self.expect(Token::LeftParen, "'(' が見つかりませんでした")?;
 
         // 初期化式を取得.
-        let initializer = self.expression_statement();
-        self.semicolon();
+        let initializer = self.expression_statement()?;
+        self.semicolon()?;
 
 
         // 条件式を取得
-        let condition = self.logical_or_expression(&root).unwrap();
-        self.semicolon();
+        let condition = self.logical_or_expression(&root)?;
+        self.semicolon()?;
 
 
         // 更新式を取得
-        let update = self.expression_statement();
+        let update = self.expression_statement()?;
 
         // 次のトークンが ')' かどうか
-        if let Some(Token::RightParen) = self.get_next_token()
-        {
-            // 何もしない
-        } else {
-            panic!("')' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        self.expect(Token::RightParen, "')' が見つかりませんでした")?;
 
         // for の中身を取得
-        let statement = self.statement();
+        let statement = self.statement()?;
 
         // ForStatement を作成
         let for_statement = ForStatement::new(initializer, condition, update, statement);
         root.borrow_mut().set_val(Leaf::ForStatement(for_statement));
 
-        root
+        self.set_span(&root, start);
+        Ok(root)
     }
 
-    fn while_statement(&mut self) -> Rc<RefCell<Node>>
+    fn while_statement(&mut self) -> Result<Rc<RefCell<Node>>, ParseError>
     {
-        let mut root = Rc::new(RefCell::new(Node::new()));
+        let start = self.token_index;
+        let root = Rc::new(RefCell::new(Node::new()));
         root.borrow_mut().set_val(Leaf::WhileStatement);
 
         // 最初の while トークンを取得
-        if let Some(Token::While) = self.get_next_token()
-        {
-            // 何もしない
-        } else {
-            panic!("'while' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        self.expect(Token::While, "'while' が見つかりませんでした")?;
 
         // 次のトークンが '(' かどうか
-        if let Some(Token::LeftParen) = self.get_next_token()
-        {
-            // 何もしない
-        } else {
-            panic!("'(' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        self.expect(Token::LeftParen, "'(' が見つかりませんでした")?;
 
         // 条件式を取得
-        if let Some(condition) = self.logical_or_expression(&root)
-        {
-            root.borrow_mut().set_lhs(condition);
-        } else {
-            panic!("条件式が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        let condition = self.logical_or_expression(&root)?;
+        root.borrow_mut().set_lhs(condition);
 
         // 次のトークンが ')' かどうか
-        if let Some(Token::RightParen) = self.get_next_token()
-        {
-            // 何もしない
-        } else {
-            panic!("')' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        self.expect(Token::RightParen, "')' が見つかりませんでした")?;
 
         // while の中身を取得
-        let statement = self.statement();
+        let statement = self.statement()?;
         root.borrow_mut().set_rhs(statement);
 
-        root
+        self.set_span(&root, start);
+        Ok(root)
     }
 
-    fn selection_statement(&mut self) -> Rc<RefCell<Node>>
+    fn selection_statement(&mut self) -> Result<Rc<RefCell<Node>>, ParseError>
     {
-        let mut root = Rc::new(RefCell::new(Node::new()));
+        let start = self.token_index;
+        let root = Rc::new(RefCell::new(Node::new()));
 
         // 最初の if トークンを取得
-        if let Some(Token::If) = self.get_next_token()
-        {
-            // 何もしない
-        } else {
-            panic!("'if' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        self.expect(Token::If, "'if' が見つかりませんでした")?;
 
         // 次のトークンが '(' かどうか
-        if let Some(Token::LeftParen) = self.get_next_token()
-        {
-            // 何もしない
-        } else {
-            panic!("'(' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        self.expect(Token::LeftParen, "'(' が見つかりませんでした")?;
 
         // 条件式を取得
-        if let Some(condition) = self.logical_or_expression(&root)
-        {
-            root.borrow_mut().set_val(Leaf::IfStatement(condition));
-        } else {
-            panic!("条件式が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        let condition = self.logical_or_expression(&root)?;
+        root.borrow_mut().set_val(Leaf::IfStatement(condition));
 
         // 次のトークンが ')' かどうか
-        if let Some(Token::RightParen) = self.get_next_token()
-        {
-            // 何もしない
-        } else {
-            panic!("')' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        self.expect(Token::RightParen, "')' が見つかりませんでした")?;
 
         // if の中身を取得
-        let true_statement = self.statement();
+        let true_statement = self.statement()?;
         root.borrow_mut().set_lhs(true_statement);
 
         // else がある場合
@@ -800,231 +918,204 @@ impl Parser
             self.token_index_increment();
 
             // else の中身を取得
-            let false_statement = self.statement();
+            let false_statement = self.statement()?;
             root.borrow_mut().set_rhs(false_statement);
         }
 
-        root
+        self.set_span(&root, start);
+        Ok(root)
     }
 
-    fn semicolon(&mut self) {
-        if let Some(Token::Semicolon) = self.get_next_token() {
-            // 何もしない
-        } else {
-            panic!("';' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+    fn semicolon(&mut self) -> Result<(), ParseError> {
+        self.expect(Token::Semicolon, "';' が見つかりませんでした")
     }
-    fn expression_statement(&mut self) -> Rc<RefCell<Node>>
+
+    fn expression_statement(&mut self) -> Result<Rc<RefCell<Node>>, ParseError>
     {
-        let mut root = Rc::new(RefCell::new(Node::new()));
+        let root = Rc::new(RefCell::new(Node::new()));
 
-        let identifier = self.tokens[self.token_index].clone();
-        let next_token = self.tokens[self.token_index + 1].clone();
+        let identifier = self.tokens.get(self.token_index).cloned();
+        let next_token = self.tokens.get(self.token_index + 1).cloned();
 
-        // next_token が '=' なら assignment として処理する
-        match next_token
+        // 識別子の次のトークンが '=' なら assignment として処理する
+        match (identifier, next_token)
         {
-            Token::Assign => {
-                root = self.assignment();
+            (Some(Token::Identifier(_)), Some(Token::Assign)) => {
+                self.assignment()
             }
-            Token::LeftBracket => {
-                root = self.array_assignment();
+            (Some(Token::Identifier(_)), Some(Token::LeftBracket)) => {
+                self.array_assignment()
             }
-            Token::Dot => {
+            (Some(Token::Identifier(_)), Some(Token::Dot)) => {
                 // 構造体メンバへの代入
-                root = self.assignment();
+                self.assignment()
             }
             _ => {
                 // それ以外は expression として処理する
-                root = self.logical_or_expression(&root).unwrap();
+                self.logical_or_expression(&root)
             }
         }
-
-        root
     }
 
-    fn assignment(&mut self) -> Rc<RefCell<Node>>
+    fn assignment(&mut self) -> Result<Rc<RefCell<Node>>, ParseError>
     {
-        let mut root = Rc::new(RefCell::new(Node::new()));
+        let start = self.token_index;
+        let root = Rc::new(RefCell::new(Node::new()));
         root.borrow_mut().set_val(Leaf::Assignment);
 
         // 左辺をパースする. 構造体メンバアクセスの可能性があるため postfix_expression を使用
-        if let Some(lhs) = self.postfix_expression(&root)
-        {
-            root.borrow_mut().set_lhs(lhs);
-        } else {
-            panic!("左辺が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        let lhs = self.postfix_expression(&root)?;
+        root.borrow_mut().set_lhs(lhs);
 
-        // 次のトークンが '=' かどうか
-        if let Some(next_token) = self.get_next_token()
-        {
-            match next_token
-            {
-                Token::Assign => {
-                    // '=' の場合は initializer をパースする
-                    if let Some(initializer) = self.logical_or_expression(&root) {
-                        root.borrow_mut().set_rhs(initializer);
-                    }
-                }
-                _ => {
-                    panic!("初期化子が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-                }
-            }
-        } else {
-            panic!("トークンがありません");
-        }
+        // 次のトークンが '=' かどうか. '=' の場合は initializer をパースする
+        self.expect(Token::Assign, "'=' が見つかりませんでした")?;
+        let initializer = self.logical_or_expression(&root)?;
+        root.borrow_mut().set_rhs(initializer);
 
-        root
+        self.set_span(&root, start);
+        Ok(root)
     }
 
-    fn array_assignment(&mut self) -> Rc<RefCell<Node>>
+    fn array_assignment(&mut self) -> Result<Rc<RefCell<Node>>, ParseError>
     {
-        let mut root = Rc::new(RefCell::new(Node::new()));
+        let start = self.token_index;
+        let root = Rc::new(RefCell::new(Node::new()));
 
         // identifier を取得
-        if let Some(Token::Identifier(identifier)) = self.get_next_token()
-        {
-            let left_node = Rc::new(RefCell::new(Node::new()));
-            left_node.borrow_mut().set_val(Leaf::Identifier(identifier));
-            root.borrow_mut().set_lhs(left_node);
-        } else {
-            panic!("識別子が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        let identifier = self.expect_identifier("識別子が見つかりませんでした")?;
+        let left_node = Rc::new(RefCell::new(Node::new()));
+        left_node.borrow_mut().set_val(Leaf::Identifier(identifier));
+        left_node.borrow_mut().set_span(self.token_span(start));
+        root.borrow_mut().set_lhs(left_node);
 
         // '[' が来ることを確認
-        if let Some(Token::LeftBracket) = self.get_next_token()
-        {
-            // 何もしない
-        } else {
-            panic!("'[' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        self.expect(Token::LeftBracket, "'[' が見つかりませんでした")?;
 
         // アクセスするindex (添字) を取得
-        if let Some(index) = self.logical_or_expression(&root)
-        {
-            root.borrow_mut().set_val(Leaf::ArrayAssignment(index));
-        } else {
-            panic!("添字が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        let index = self.logical_or_expression(&root)?;
+        root.borrow_mut().set_val(Leaf::ArrayAssignment(index));
 
         // ']' が来ることを確認
-        if let Some(Token::RightBracket) = self.get_next_token()
-        {
-            // 何もしない
-        } else {
-            panic!("']' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        self.expect(Token::RightBracket, "']' が見つかりませんでした")?;
 
-        // 次のトークンが '=' かどうか
-        if let Some(Token::Assign) = self.get_next_token()
-        {
-            // '=' の場合は initializer をパースする
-            if let Some(initializer) = self.logical_or_expression(&root) {
-                root.borrow_mut().set_rhs(initializer);
-            }
-        } else {
-            panic!("'=' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        // 次のトークンが '=' かどうか. '=' の場合は initializer をパースする
+        self.expect(Token::Assign, "'=' が見つかりませんでした")?;
+        let initializer = self.logical_or_expression(&root)?;
+        root.borrow_mut().set_rhs(initializer);
 
-        root
+        self.set_span(&root, start);
+        Ok(root)
     }
 
-    fn jump_statement(&mut self) -> Rc<RefCell<Node>>
+    fn jump_statement(&mut self) -> Result<Rc<RefCell<Node>>, ParseError>
     {
-        let mut root = Rc::new(RefCell::new(Node::new()));
+        let start = self.token_index;
+        let root = Rc::new(RefCell::new(Node::new()));
 
         // 次のトークンを取得
-        if let Some(jump_token) = self.get_next_token()
+        match self.get_next_token_without_increment()
         {
-            match jump_token
-            {
-                Token::Return => {
-                    println!("return");
-                    // valにReturnを設定
-                    root.borrow_mut().set_val(Leaf::Return);
-
-                    // return の場合は expression が続く
-                    if let Some(expression) = self.logical_or_expression(&root) {
-                        root.borrow_mut().set_lhs(expression);
-                    } else {
-                        panic!("return の後に式がありませんでした : {:?}", self.tokens[self.token_index]);
-                    }
-                }
-                Token::Break => {
-                    println!("break");
-                    root.borrow_mut().set_val(Leaf::Break);
-                }
-                Token::Continue => {
-                    unimplemented!("continue");
-                }
-                _ => {
-                    panic!("ジャンプステートメントが見つかりませんでした : {:?}", self.tokens[self.token_index]);
+            Some(Token::Return) => {
+                println!("return");
+                self.token_index_increment();
+                // valにReturnを設定
+                root.borrow_mut().set_val(Leaf::Return);
+
+                // return の場合は ';' でなければ expression が続く
+                if let Some(Token::Semicolon) = self.get_next_token_without_increment() {
+                    // 戻り値なし
+                } else {
+                    let expression = self.logical_or_expression(&root)?;
+                    root.borrow_mut().set_lhs(expression);
                 }
             }
+            Some(Token::Break) => {
+                println!("break");
+                self.token_index_increment();
+                root.borrow_mut().set_val(Leaf::Break);
+            }
+            Some(Token::Continue) => {
+                println!("continue");
+                self.token_index_increment();
+                root.borrow_mut().set_val(Leaf::Continue);
+            }
+            _ => {
+                return Err(self.error("ジャンプステートメントが見つかりませんでした"));
+            }
         }
 
         // ';' が来ることを確認
-        self.semicolon();
+        self.semicolon()?;
+
+        self.set_span(&root, start);
+        Ok(root)
+    }
 
-        root
+    /// 型指定子を取得する (int, float, struct など)
+    fn type_specifier(&mut self, message: &str) -> Result<ValueType, ParseError>
+    {
+        match self.get_next_token_without_increment() {
+            Some(Token::Type(t)) => {
+                self.token_index_increment();
+                Ok(t)
+            }
+            Some(Token::Struct) => {
+                self.token_index_increment();
+                let struct_name = self.expect_identifier("構造体名が見つかりませんでした")?;
+                Ok(ValueType::Struct(struct_name))
+            }
+            _ => Err(self.error(message)),
+        }
+    }
+
+    /// 配列のサイズを取得する
+    fn array_size(&mut self) -> Result<usize, ParseError>
+    {
+        if let Some(Token::Constant(Constant::Integer(size))) = self.get_next_token_without_increment() {
+            if size >= 0 {
+                self.token_index_increment();
+                return Ok(size as usize);
+            }
+        }
+
+        Err(self.error("配列のサイズが見つかりませんでした"))
     }
 
     /// 関数の引数リストを取得する. ')' が来るまで繰り返す
-    fn parameter_list(&mut self, function_definition: &mut FunctionDefinition)
+    fn parameter_list(&mut self, function_definition: &mut FunctionDefinition) -> Result<(), ParseError>
     {
         // ')' が来る場合は何もしない
         if let Some(Token::RightParen) = self.get_next_token_without_increment() {
-            return;
+            return Ok(());
         }
 
         loop {
             // 型指定子を取得 (int, float, struct など)
-            let type_specifier = match self.get_next_token() {
-                Some(Token::Type(t)) => t,
-                Some(Token::Struct) => {
-                    if let Some(Token::Identifier(struct_name)) = self.get_next_token() {
-                        ValueType::Struct(struct_name)
-                    } else {
-                        panic!("構造体名が見つかりませんでした");
-                    }
-                }
-                _ => panic!("引数の型が見つかりませんでした : {:?}", self.tokens[self.token_index]),
-            };
+            let type_specifier = self.type_specifier("引数の型が見つかりませんでした")?;
 
             if type_specifier == ValueType::Void && function_definition.arguments().is_empty() {
                 // (void) の形式
                 if let Some(Token::RightParen) = self.get_next_token_without_increment() {
-                    return;
+                    return Ok(());
                 }
             }
 
             // 型がある場合は識別子が続く
-            if let Some(Token::Identifier(identifier)) = self.get_next_token()
+            let identifier = self.expect_identifier("関数の引数の識別子が見つかりませんでした")?;
+
+            // 配列の場合は '[' が続く
+            if let Some(Token::LeftBracket) = self.get_next_token_without_increment()
             {
-                // 配列の場合は '[' が続く
-                if let Some(Token::LeftBracket) = self.get_next_token_without_increment()
-                {
-                    self.token_index_increment();
-                    // '[' が来た場合は配列
-                    if let Some(Token::Constant(Constant::Integer(size))) = self.get_next_token()
-                    {
-                        // ']' が来ることを確認
-                        if let Some(Token::RightBracket) = self.get_next_token()
-                        {
-                            let array_type = ValueType::Array(Box::new(type_specifier), size as usize);
-                            function_definition.add_argument(array_type, identifier);
-                        } else {
-                            panic!("']' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-                        }
-                    } else {
-                        panic!("配列のサイズが見つかりませんでした : {:?}", self.tokens[self.token_index]);
-                    }
-                } else {
-                    function_definition.add_argument(type_specifier, identifier);
-                }
+                self.token_index_increment();
+                // '[' が来た場合は配列
+                let size = self.array_size()?;
+
+                // ']' が来ることを確認
+                self.expect(Token::RightBracket, "']' が見つかりませんでした")?;
+                let array_type = ValueType::Array(Box::new(type_specifier), size);
+                function_definition.add_argument(array_type, identifier);
             } else {
-                panic!("関数の引数の識別子が見つかりませんでした : {:?}", self.tokens[self.token_index]);
+                function_definition.add_argument(type_specifier, identifier);
             }
 
             // 次のトークンが ',' か ')' かを調べて ',' なら次の引数を取得する
@@ -1037,336 +1128,345 @@ impl Parser
                     break;
                 }
                 _ => {
-                    panic!("次のトークンがありません : {:?}", self.tokens[self.token_index]);
+                    return Err(self.error("',' か ')' が見つかりませんでした"));
                 }
             }
         }
+
+        Ok(())
     }
 
-    fn declaration(&mut self) -> Rc<RefCell<Node>>
+    fn declaration(&mut self) -> Result<Rc<RefCell<Node>>, ParseError>
     {
+        let start = self.token_index;
         println!("declaration");
 
         // グローバル変数定義をパースする
-        let mut root = Rc::new(RefCell::new(Node::new()));
+        let root = Rc::new(RefCell::new(Node::new()));
 
         // type_specifier を取得
-        let type_specifier = match self.get_next_token() {
-            Some(Token::Type(t)) => t,
+        let type_specifier = match self.get_next_token_without_increment() {
+            Some(Token::Type(t)) => {
+                self.token_index_increment();
+                t
+            }
             Some(Token::Struct) => {
+                self.token_index_increment();
                 // struct identifier の形式
-                if let Some(Token::Identifier(struct_name)) = self.get_next_token() {
-                    // 次のトークンが '{' なら構造体定義
-                    if let Some(Token::LeftBrace) = self.get_next_token_without_increment() {
-                        self.token_index_increment();
-                        let mut members = Vec::new();
-                        loop {
-                            if let Some(Token::RightBrace) = self.get_next_token_without_increment() {
+                let struct_name = self.expect_identifier("構造体名が見つかりませんでした")?;
+
+                // 次のトークンが '{' なら構造体定義
+                if let Some(Token::LeftBrace) = self.get_next_token_without_increment() {
+                    self.token_index_increment();
+                    let mut members = Vec::new();
+                    loop {
+                        match self.get_next_token_without_increment() {
+                            Some(Token::RightBrace) => {
                                 self.token_index_increment();
                                 break;
                             }
-                            members.push(self.declaration());
-                        }
-                        // ';' が来ることを確認
-                        if let Some(Token::Semicolon) = self.get_next_token_without_increment() {
-                            self.token_index_increment();
+                            None => {
+                                return Err(self.error("'}' が見つかりませんでした"));
+                            }
+                            _ => {
+                                members.push(self.declaration()?);
+                            }
                         }
-                        root.borrow_mut().set_val(Leaf::StructDefinition(struct_name, members));
-                        return root;
-                    } else {
-                        // 構造体型の変数宣言
-                        ValueType::Struct(struct_name)
                     }
+                    // ';' が来ることを確認
+                    if let Some(Token::Semicolon) = self.get_next_token_without_increment() {
+                        self.token_index_increment();
+                    }
+                    root.borrow_mut().set_val(Leaf::StructDefinition(struct_name, members));
+                    self.set_span(&root, start);
+                    return Ok(root);
                 } else {
-                    panic!("構造体名が見つかりませんでした");
+                    // 構造体型の変数宣言
+                    ValueType::Struct(struct_name)
                 }
             }
-            _ => panic!("型が見つかりませんでした : {:?}", self.tokens[self.token_index]),
+            _ => return Err(self.error("型が見つかりませんでした")),
         };
 
+        let is_char_array = type_specifier == ValueType::Char;
         root.borrow_mut().set_val(Leaf::Declaration(type_specifier));
 
         // declaration の左辺として識別子が入る
-        if let Some(Token::Identifier(identifier)) = self.get_next_token() {
-            let left_node = Rc::new(RefCell::new(Node::new()));
-            left_node.borrow_mut().set_val(Leaf::Identifier(identifier));
-            root.borrow_mut().set_lhs(left_node);
-        } else {
-            panic!("識別子が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-        }
+        let identifier = self.expect_identifier("識別子が見つかりませんでした")?;
+        let left_node = Rc::new(RefCell::new(Node::new()));
+        left_node.borrow_mut().set_val(Leaf::Identifier(identifier));
+        left_node.borrow_mut().set_span(self.token_span(self.token_index - 1));
+        root.borrow_mut().set_lhs(left_node);
 
         // 次のトークンが '=' かどうか
-        if let Some(next_token) = self.get_next_token()
+        match self.get_next_token_without_increment()
         {
-            match next_token
-            {
-                Token::Assign => {
-                    // '=' の場合は initializer をパースする
-                    if let Some(initializer) = self.logical_or_expression(&root) {
-                        root.borrow_mut().set_rhs(initializer);
-                    }
+            Some(Token::Assign) => {
+                self.token_index_increment();
+                // '=' の場合は initializer をパースする
+                let initializer = self.logical_or_expression(&root)?;
+                root.borrow_mut().set_rhs(initializer);
 
-                    // ';' が来ることを確認
-                    if let Some(Token::Semicolon) = self.get_next_token() {
-                        // 何もしない
-                    } else {
-                        panic!("';' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-                    }
-                }
-                Token::Semicolon => {
-                    // 何もしない
-                }
-                Token::LeftBracket => {
-                    // 配列の場合
-                    if let Some(Token::Constant(Constant::Integer(size))) = self.get_next_token() {
-                        let right_node = Rc::new(RefCell::new(Node::new()));
-                        right_node.borrow_mut().set_val(Leaf::Array(size as usize));
-                        root.borrow_mut().set_rhs(right_node);
-                    } else {
-                        panic!("配列のサイズが見つかりませんでした : {:?}", self.tokens[self.token_index]);
+                // ';' が来ることを確認
+                self.semicolon()?;
+            }
+            Some(Token::Semicolon) => {
+                self.token_index_increment();
+            }
+            Some(Token::LeftBracket) => {
+                self.token_index_increment();
+                // 配列の場合. 文字列で初期化する場合はサイズを省略できる
+                let size_index = self.token_index;
+                let size = match self.get_next_token_without_increment() {
+                    Some(Token::RightBracket) => None,
+                    _ => Some(self.array_size()?),
+                };
+
+                // 正しく配列のサイズが取得できた場合
+                self.expect(Token::RightBracket, "']' が見つかりませんでした")?;
+
+                let right_node = Rc::new(RefCell::new(Node::new()));
+                right_node.borrow_mut().set_span(self.token_span(size_index));
+
+                // '=' が続く場合は char の配列を文字列で初期化する
+                let mut initializer = None;
+                if let Some(Token::Assign) = self.get_next_token_without_increment() {
+                    self.token_index_increment();
+                    if !is_char_array {
+                        return Err(self.error("文字列で初期化できるのは char の配列だけです"));
                     }
-
-                    // 正しく配列のサイズが取得できた場合
-                    if let Some(Token::RightBracket) = self.get_next_token() {
-                        // 何もしない
-                    } else {
-                        panic!("']' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
+                    let Some(Token::StringLiteral(string)) = self.get_next_token_without_increment() else {
+                        return Err(self.error("文字列リテラルが見つかりませんでした"));
+                    };
+                    if size.is_some_and(|size| string.len() > size) {
+                        return Err(self.error("文字列が配列のサイズより長いです"));
                     }
+                    self.token_index_increment();
 
-                    self.semicolon();
-                }
-                _ => {
-                    panic!("初期化子が見つかりませんでした : {:?}", self.tokens[self.token_index]);
+                    let string_node = Rc::new(RefCell::new(Node::new()));
+                    string_node.borrow_mut().set_val(Leaf::StringLiteral(string.clone()));
+                    string_node.borrow_mut().set_span(self.token_span(self.token_index - 1));
+                    right_node.borrow_mut().set_lhs(string_node);
+                    initializer = Some(string);
                 }
+
+                // 文字列のバイト数に終端の '\0' を加えたものが省略したサイズになる
+                let size = match (size, initializer) {
+                    (Some(size), _) => size,
+                    (None, Some(string)) => string.len() + 1,
+                    (None, None) => return Err(self.error("配列のサイズが見つかりませんでした")),
+                };
+                right_node.borrow_mut().set_val(Leaf::Array(size));
+                root.borrow_mut().set_rhs(right_node);
+
+                self.semicolon()?;
+            }
+            _ => {
+                return Err(self.error("初期化子が見つかりませんでした"));
             }
-        } else {
-            panic!("トークンがありません");
         }
-        root
+
+        self.set_span(&root, start);
+        Ok(root)
     }
 
 
-    fn logical_or_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>>
+    fn logical_or_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Result<Rc<RefCell<Node>>, ParseError>
     {
+        let start = self.token_index;
         println!("logical_or_expression");
-        let mut node = Rc::new(RefCell::new(Node::new()));
+        let node = Rc::new(RefCell::new(Node::new()));
         node.borrow_mut().set_parent(parent);
 
-        if let Some(left_node) = self.logical_and_expression(&parent) {
+        let left_node = self.logical_and_expression(parent)?;
 
-            // 次のトークンが '||' の場合は logical_or_expression をパースする
-            if let Some(Token::Operator(Operator::LogicalOr)) = self.get_next_token_without_increment() {
-                node.borrow_mut().set_val(Leaf::Operator(Operator::LogicalOr));
-                node.borrow_mut().set_lhs(left_node);
-                self.token_index_increment();
+        // 次のトークンが '||' の場合は logical_or_expression をパースする
+        if let Some(Token::Operator(Operator::LogicalOr)) = self.get_next_token_without_increment() {
+            node.borrow_mut().set_val(Leaf::Operator(Operator::LogicalOr));
+            node.borrow_mut().set_lhs(left_node);
+            self.token_index_increment();
 
-                if let Some(right_node) = self.logical_or_expression(&node) {
-                    node.borrow_mut().set_rhs(right_node);
-                } else {
-                    panic!("右のノードが見つかりませんでした : {:?}", self.tokens[self.token_index]);
-                }
+            let right_node = self.logical_or_expression(&node)?;
+            node.borrow_mut().set_rhs(right_node);
 
-                Some(node)
-            } else {
-                // 演算子がない場合は左のノードをそのまま返す
-                Some(left_node)
-            }
+            self.set_span(&node, start);
+            Ok(node)
         } else {
-            panic!("識別子が見つかりませんでした : {:?}", self.tokens[self.token_index]);
+            // 演算子がない場合は左のノードをそのまま返す
+            Ok(left_node)
         }
     }
 
-    fn logical_and_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>>
+    fn logical_and_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Result<Rc<RefCell<Node>>, ParseError>
     {
+        let start = self.token_index;
         println!("logical_and_expression");
-        let mut node = Rc::new(RefCell::new(Node::new()));
+        let node = Rc::new(RefCell::new(Node::new()));
         node.borrow_mut().set_parent(parent);
 
-        if let Some(left_node) = self.equality_expression(&node)
-        {
-            // 次のトークンが '&&' の場合は logical_and_expression をパースする
-            if let Some(Token::Operator(Operator::LogicalAnd)) = self.get_next_token_without_increment() {
-                node.borrow_mut().set_val(Leaf::Operator(Operator::LogicalAnd));
-                node.borrow_mut().set_lhs(left_node);
-                self.token_index_increment();
+        let left_node = self.equality_expression(&node)?;
 
-                // 再度 logical_and_expression を呼び出す
-                if let Some(right_node) = self.logical_and_expression(&mut node) {
-                    node.borrow_mut().set_rhs(right_node);
-                } else {
-                    panic!("右のノードが見つかりませんでした : {:?}", self.tokens[self.token_index]);
-                }
+        // 次のトークンが '&&' の場合は logical_and_expression をパースする
+        if let Some(Token::Operator(Operator::LogicalAnd)) = self.get_next_token_without_increment() {
+            node.borrow_mut().set_val(Leaf::Operator(Operator::LogicalAnd));
+            node.borrow_mut().set_lhs(left_node);
+            self.token_index_increment();
 
-                Some(node)
-            } else {
-                // 演算子がない場合は左のノードをそのまま返す
-                Some(left_node)
-            }
+            // 再度 logical_and_expression を呼び出す
+            let right_node = self.logical_and_expression(&node)?;
+            node.borrow_mut().set_rhs(right_node);
+
+            self.set_span(&node, start);
+            Ok(node)
         } else {
-            panic!("識別子が見つかりませんでした : {:?}", self.tokens[self.token_index]);
+            // 演算子がない場合は左のノードをそのまま返す
+            Ok(left_node)
         }
     }
 
-    fn equality_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>> {
+    fn equality_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Result<Rc<RefCell<Node>>, ParseError> {
+        let start = self.token_index;
         println!("equality_expression");
-        let mut node = Rc::new(RefCell::new(Node::new()));
+        let node = Rc::new(RefCell::new(Node::new()));
         node.borrow_mut().set_parent(parent);
 
-        if let Some(left_node) = self.relational_expression(&node) {
-            // '==' または '!=' の演算子を取得
-            if let Some(operator) = self.get_next_token_without_increment()
-                .and_then(|token| match token {
-                    Token::Operator(Operator::Equal) => Some(Operator::Equal),
-                    Token::Operator(Operator::NotEqual) => Some(Operator::NotEqual),
-                    _ => None,
-                })
-            {
-                node.borrow_mut().set_val(Leaf::Operator(operator));
-                node.borrow_mut().set_lhs(left_node);
-                self.token_index_increment();
+        let left_node = self.relational_expression(&node)?;
 
-                // 再帰的に equality_expression を呼び出す
-                if let Some(right_node) = self.equality_expression(&node) {
-                    node.borrow_mut().set_rhs(right_node);
-                } else {
-                    panic!(
-                        "右のノードが見つかりませんでした : {:?}",
-                        self.tokens[self.token_index]
-                    );
-                }
+        // '==' または '!=' の演算子を取得
+        if let Some(operator) = self.get_next_token_without_increment()
+            .and_then(|token| match token {
+                Token::Operator(Operator::Equal) => Some(Operator::Equal),
+                Token::Operator(Operator::NotEqual) => Some(Operator::NotEqual),
+                _ => None,
+            })
+        {
+            node.borrow_mut().set_val(Leaf::Operator(operator));
+            node.borrow_mut().set_lhs(left_node);
+            self.token_index_increment();
 
-                Some(node)
-            } else {
-                // 演算子がない場合は左のノードをそのまま返す
-                Some(left_node)
-            }
+            // 再帰的に equality_expression を呼び出す
+            let right_node = self.equality_expression(&node)?;
+            node.borrow_mut().set_rhs(right_node);
+
+            self.set_span(&node, start);
+            Ok(node)
         } else {
-            panic!(
-                "識別子が見つかりませんでした : {:?}",
-                self.tokens[self.token_index]
-            );
+            // 演算子がない場合は左のノードをそのまま返す
+            Ok(left_node)
         }
     }
 
 
-    fn relational_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>>
+    fn relational_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Result<Rc<RefCell<Node>>, ParseError>
     {
+        let start = self.token_index;
         println!("relational_expression");
-        let mut node = Rc::new(RefCell::new(Node::new()));
+        let node = Rc::new(RefCell::new(Node::new()));
         node.borrow_mut().set_parent(parent);
 
-        if let Some(left_node) = self.additive_expression(&mut node) {
-            // '<' または '>' の演算子を取得
-            if let Some(operator) = self.get_next_token_without_increment()
-                .and_then(|token| match token {
-                    Token::Operator(Operator::LessThan) => Some(Operator::LessThan),
-                    Token::Operator(Operator::LessThanOrEqual) => Some(Operator::LessThanOrEqual),
-                    Token::Operator(Operator::GreaterThan) => Some(Operator::GreaterThan),
-                    Token::Operator(Operator::GreaterThanOrEqual) => Some(Operator::GreaterThanOrEqual),
-                    _ => None,
-                })
-            {
-                println!("relational_expression");
-                node.borrow_mut().set_val(Leaf::Operator(operator));
-                node.borrow_mut().set_lhs(left_node);
-                self.token_index_increment();
+        let left_node = self.additive_expression(&node)?;
+
+        // '<' または '>' の演算子を取得
+        if let Some(operator) = self.get_next_token_without_increment()
+            .and_then(|token| match token {
+                Token::Operator(Operator::LessThan) => Some(Operator::LessThan),
+                Token::Operator(Operator::LessThanOrEqual) => Some(Operator::LessThanOrEqual),
+                Token::Operator(Operator::GreaterThan) => Some(Operator::GreaterThan),
+                Token::Operator(Operator::GreaterThanOrEqual) => Some(Operator::GreaterThanOrEqual),
+                _ => None,
+            })
+        {
+            println!("relational_expression");
+            node.borrow_mut().set_val(Leaf::Operator(operator));
+            node.borrow_mut().set_lhs(left_node);
+            self.token_index_increment();
 
-                // 再帰的に relational_expression を呼び出す
-                if let Some(right_node) = self.relational_expression(&mut node) {
-                    node.borrow_mut().set_rhs(right_node);
-                } else {
-                    panic!("右のノードが見つかりませんでした : {:?}", self.tokens[self.token_index]);
-                }
+            // 再帰的に relational_expression を呼び出す
+            let right_node = self.relational_expression(&node)?;
+            node.borrow_mut().set_rhs(right_node);
 
-                Some(node)
-            } else {
-                // 演算子がない場合は左のノードをそのまま返す
-                Some(left_node)
-            }
+            self.set_span(&node, start);
+            Ok(node)
         } else {
-            panic!("識別子が見つかりませんでした : {:?}", self.tokens[self.token_index]);
+            // 演算子がない場合は左のノードをそのまま返す
+            Ok(left_node)
         }
     }
 
 
-    fn additive_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>>
+    fn additive_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Result<Rc<RefCell<Node>>, ParseError>
     {
+        let start = self.token_index;
         println!("additive_expression");
-        let mut node = Rc::new(RefCell::new(Node::new()));
+        let node = Rc::new(RefCell::new(Node::new()));
         node.borrow_mut().set_parent(parent);
 
-        if let Some(left_node) = self.multiplicative_expression(&mut node) {
-            // '+' または '-' の演算子を取得
-            if let Some(operator) = self.get_next_token_without_increment()
-                .and_then(|token| match token {
-                    Token::Operator(Operator::Plus) => Some(Operator::Plus),
-                    Token::Operator(Operator::Minus) => Some(Operator::Minus),
-                    _ => None,
-                })
-            {
-                self.token_index_increment();
-                node.borrow_mut().set_val(Leaf::Operator(operator));
-                node.borrow_mut().set_lhs(left_node);
+        let left_node = self.multiplicative_expression(&node)?;
 
-                // 再帰的に additive_expression を呼び出す
-                if let Some(right_node) = self.additive_expression(&mut node) {
-                    node.borrow_mut().set_rhs(right_node);
-                } else {
-                    panic!("右のノードが見つかりませんでした : {:?}", self.tokens[self.token_index]);
-                }
+        // '+' または '-' の演算子を取得
+        if let Some(operator) = self.get_next_token_without_increment()
+            .and_then(|token| match token {
+                Token::Operator(Operator::Plus) => Some(Operator::Plus),
+                Token::Operator(Operator::Minus) => Some(Operator::Minus),
+                _ => None,
+            })
+        {
+            self.token_index_increment();
+            node.borrow_mut().set_val(Leaf::Operator(operator));
+            node.borrow_mut().set_lhs(left_node);
 
-                Some(node)
-            } else {
-                // 演算子がない場合は左のノードをそのまま返す
-                Some(left_node)
-            }
+            // 再帰的に additive_expression を呼び出す
+            let right_node = self.additive_expression(&node)?;
+            node.borrow_mut().set_rhs(right_node);
+
+            self.set_span(&node, start);
+            Ok(node)
         } else {
-            panic!("識別子が見つかりませんでした : {:?}", self.tokens[self.token_index]);
+            // 演算子がない場合は左のノードをそのまま返す
+            Ok(left_node)
         }
     }
 
-    fn multiplicative_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>>
+    fn multiplicative_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Result<Rc<RefCell<Node>>, ParseError>
     {
+        let start = self.token_index;
         println!("multiplicative_expression");
-        let mut node = Rc::new(RefCell::new(Node::new()));
+        let node = Rc::new(RefCell::new(Node::new()));
         node.borrow_mut().set_parent(parent);
 
-        if let Some(left_node) = self.unary_expression(&mut node) {
-            // '*', '/', '%' の演算子を取得
-            if let Some(operator) = self.get_next_token_without_increment()
-                .and_then(|token| match token {
-                    Token::Operator(Operator::Multiply) => Some(Operator::Multiply),
-                    Token::Operator(Operator::Divide) => Some(Operator::Divide),
-                    Token::Operator(Operator::Modulo) => Some(Operator::Modulo),
-                    _ => None,
-                })
-            {
-                self.token_index_increment();
-                node.borrow_mut().set_val(Leaf::Operator(operator));
-                node.borrow_mut().set_lhs(left_node);
+        let left_node = self.unary_expression(&node)?;
 
-                // 再帰的に multiplicative_expression を呼び出す
-                if let Some(right_node) = self.multiplicative_expression(&mut node) {
-                    node.borrow_mut().set_rhs(right_node);
-                } else {
-                    panic!("右のノードが見つかりませんでした : {:?}", self.tokens[self.token_index]);
-                }
+        // '*', '/', '%' の演算子を取得
+        if let Some(operator) = self.get_next_token_without_increment()
+            .and_then(|token| match token {
+                Token::Operator(Operator::Multiply) => Some(Operator::Multiply),
+                Token::Operator(Operator::Divide) => Some(Operator::Divide),
+                Token::Operator(Operator::Modulo) => Some(Operator::Modulo),
+                _ => None,
+            })
+        {
+            self.token_index_increment();
+            node.borrow_mut().set_val(Leaf::Operator(operator));
+            node.borrow_mut().set_lhs(left_node);
 
-                Some(node)
-            } else {
-                // 演算子がない場合は左のノードをそのまま返す
-                Some(left_node)
-            }
+            // 再帰的に multiplicative_expression を呼び出す
+            let right_node = self.multiplicative_expression(&node)?;
+            node.borrow_mut().set_rhs(right_node);
+
+            self.set_span(&node, start);
+            Ok(node)
         } else {
-            panic!("識別子が見つかりませんでした : {:?}", self.tokens[self.token_index]);
+            // 演算子がない場合は左のノードをそのまま返す
+            Ok(left_node)
         }
     }
 
     // 最終的にはpostfix_expression を呼び出すが関数呼び出しと配列は現状無視する.
-    fn unary_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>>
+    fn unary_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Result<Rc<RefCell<Node>>, ParseError>
     {
+        let start = self.token_index;
         println!("unary_expression");
-        let mut node = Rc::new(RefCell::new(Node::new()));
+        let node = Rc::new(RefCell::new(Node::new()));
         node.borrow_mut().set_parent(parent);
 
         // 次のトークンを取得
@@ -1376,234 +1476,205 @@ impl Parser
             // 単項演算子の場合
             node.borrow_mut().set_val(Leaf::UnaryExpression(operator));
             self.token_index_increment();
-            let left_node = self.postfix_expression(&mut node);
-            if let Some(left_node) = left_node {
-                node.borrow_mut().set_lhs(left_node);
-            }
+            let left_node = self.postfix_expression(&node)?;
+            node.borrow_mut().set_lhs(left_node);
+            self.set_span(&node, start);
+            Ok(node)
         } else {
             // 単項演算子でない場合は postfix_expression をパースする
-            let postfix_node = self.postfix_expression(&mut node);
-            if let Some(postfix_node) = postfix_node {
-                node = postfix_node;
-            }
+            self.postfix_expression(&node)
         }
-
-        Some(node)
     }
 
-    fn postfix_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>>
+    fn postfix_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Result<Rc<RefCell<Node>>, ParseError>
     {
+        let start = self.token_index;
         println!("postfix_expression");
         let mut node = Rc::new(RefCell::new(Node::new()));
         node.borrow_mut().set_parent(parent);
 
-        if let Some(next_token) = self.get_next_token_without_increment()
+        match self.get_next_token_without_increment()
         {
-            match next_token
-            {
-                Token::Identifier(identify) => {
-                    // index を進める
-                    self.token_index_increment();
+            Some(Token::Identifier(identify)) => {
+                // index を進める
+                self.token_index_increment();
 
-                    if let Some(next_identify) = self.get_next_token_without_increment()
-                    {
-                        match next_identify
-                        {
-                            Token::LeftParen => {
-                                self.token_index_increment();
+                match self.get_next_token_without_increment()
+                {
+                    Some(Token::LeftParen) => {
+                        self.token_index_increment();
 
-                                // 関数呼び出しの場合
-                                let mut function_call = FunctionCall::new(identify);
-
-                                // ')' が来るまで argument_expression_list を呼び出す
-                                loop {
-                                    let next_token = self.get_next_token_without_increment();
-
-                                    // 引数がない場合は ')' が来る
-                                    if let Some(Token::RightParen) = next_token {
-                                        self.token_index_increment();
-                                        break;
-                                    }
-
-                                    let logical_or_expression_node
-                                        = self.logical_or_expression(&node);
-
-                                    if let Some(arg) = logical_or_expression_node {
-                                        function_call.add_argument(arg);
-                                    }
-
-                                    // ',' が来ることを確認
-                                    match self.get_next_token_without_increment()
-                                    {
-                                        Some(Token::Comma) => {
-                                            self.token_index_increment();
-                                        }
-                                        None => {
-                                            panic!("次のトークンがありません");
-                                        }
-                                        _ => {
-                                            // 何もしない
-                                        }
-                                    }
-                                }
+                        // 関数呼び出しの場合
+                        let mut function_call = FunctionCall::new(identify);
 
-                                node.borrow_mut().set_val(Leaf::FunctionCall(function_call));
-                            }
-                            Token::LeftBracket => {
+                        // ')' が来るまで argument_expression_list を呼び出す
+                        loop {
+                            let next_token = self.get_next_token_without_increment();
+
+                            // 引数がない場合は ')' が来る
+                            if let Some(Token::RightParen) = next_token {
                                 self.token_index_increment();
-                                // 配列の場合
-                                node.borrow_mut().set_val(Leaf::ArrayAccess);
+                                break;
+                            }
 
-                                // 左側に識別子を設定
-                                let left_node = Rc::new(RefCell::new(Node::new()));
-                                left_node.borrow_mut().set_val(Leaf::Identifier(identify));
-                                node.borrow_mut().set_lhs(left_node);
+                            let arg = self.logical_or_expression(&node)?;
+                            function_call.add_argument(arg);
 
-                                // ']' のときはからの配列として扱う
-                                if let Some(Token::RightBracket) = self.get_next_token_without_increment() {
-                                    // 何もしない
+                            // ',' が来ることを確認
+                            match self.get_next_token_without_increment()
+                            {
+                                Some(Token::Comma) => {
                                     self.token_index_increment();
-                                } else {
-                                    // 配列の index を取得
-                                    let logical_or_expression_node = self.logical_or_expression(&node);
-                                    if let Some(index) = logical_or_expression_node {
-                                        node.borrow_mut().set_rhs(index);
-                                    }
-
-                                    // ']' が来ることを確認
-                                    if let Some(Token::RightBracket) = self.get_next_token() {
-                                        // 何もしない
-                                    } else {
-                                        panic!("']' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-                                    }
+                                }
+                                Some(Token::RightParen) => {
+                                    // 何もしない
+                                }
+                                _ => {
+                                    return Err(self.error("',' か ')' が見つかりませんでした"));
                                 }
                             }
-                            Token::Dot => {
-                                // 構造体メンバアクセスの場合
-                                node.borrow_mut().set_val(Leaf::StructMemberAccess);
+                        }
 
-                                // 左側に postfix_expression (今回は簡単のため識別子のみをサポートするが、本来は再帰的)
-                                let left_node = Rc::new(RefCell::new(Node::new()));
-                                left_node.borrow_mut().set_val(Leaf::Identifier(identify));
-                                node.borrow_mut().set_lhs(left_node);
+                        node.borrow_mut().set_val(Leaf::FunctionCall(function_call));
+                    }
+                    Some(Token::LeftBracket) => {
+                        self.token_index_increment();
+                        // 配列の場合
+                        node.borrow_mut().set_val(Leaf::ArrayAccess);
+
+                        // 左側に識別子を設定
+                        let left_node = Rc::new(RefCell::new(Node::new()));
+                        left_node.borrow_mut().set_val(Leaf::Identifier(identify));
+                        left_node.borrow_mut().set_span(self.token_span(start));
+                        node.borrow_mut().set_lhs(left_node);
+
+                        // ']' のときはからの配列として扱う
+                        if let Some(Token::RightBracket) = self.get_next_token_without_increment() {
+                            // 何もしない
+                            self.token_index_increment();
+                        } else {
+                            // 配列の index を取得
+                            let index = self.logical_or_expression(&node)?;
+                            node.borrow_mut().set_rhs(index);
 
-                                // '.' を進める
-                                self.token_index_increment();
+                            // ']' が来ることを確認
+                            self.expect(Token::RightBracket, "']' が見つかりませんでした")?;
+                        }
+                    }
+                    Some(Token::Dot) => {
+                        // 構造体メンバアクセスの場合
+                        node.borrow_mut().set_val(Leaf::StructMemberAccess);
 
-                                // メンバ名を取得
-                                if let Some(Token::Identifier(member_name)) = self.get_next_token() {
-                                    let right_node = Rc::new(RefCell::new(Node::new()));
-                                    right_node.borrow_mut().set_val(Leaf::Identifier(member_name));
-                                    node.borrow_mut().set_rhs(right_node);
-                                } else {
-                                    panic!("メンバ名が見つかりませんでした");
-                                }
+                        // 左側に postfix_expression (今回は簡単のため識別子のみをサポートするが、本来は再帰的)
+                        let left_node = Rc::new(RefCell::new(Node::new()));
+                        left_node.borrow_mut().set_val(Leaf::Identifier(identify));
+                        left_node.borrow_mut().set_span(self.token_span(start));
+                        node.borrow_mut().set_lhs(left_node);
 
-                                // さらに '.' が続く場合は再帰的に処理するために node を parent にして postfix_expression を呼び出す必要があるが、
-                                // 現状は一階層のみ。
-                                if let Some(Token::Dot) = self.get_next_token_without_increment() {
-                                    // 再帰的なメンバアクセスをサポートするためのループ
-                                    loop {
-                                        if let Some(Token::Dot) = self.get_next_token_without_increment() {
-                                            self.token_index_increment();
-                                            let next_node = Rc::new(RefCell::new(Node::new()));
-                                            next_node.borrow_mut().set_val(Leaf::StructMemberAccess);
-                                            next_node.borrow_mut().set_lhs(node.clone());
-
-                                            if let Some(Token::Identifier(m_name)) = self.get_next_token() {
-                                                let r_node = Rc::new(RefCell::new(Node::new()));
-                                                r_node.borrow_mut().set_val(Leaf::Identifier(m_name));
-                                                next_node.borrow_mut().set_rhs(r_node);
-                                                node = next_node;
-                                            } else {
-                                                panic!("メンバ名が見つかりませんでした");
-                                            }
-                                        } else {
-                                            break;
-                                        }
-                                    }
-                                }
-                            }
-                            _ => {
-                                // それ以外の場合は identifier として処理する
-                                node.borrow_mut().set_val(Leaf::Identifier(identify));
-                            }
-                        }
+                        // '.' を進める
+                        self.token_index_increment();
 
-                        return Some(node);
+                        // メンバ名を取得
+                        let member_name = self.expect_identifier("メンバ名が見つかりませんでした")?;
+                        let right_node = Rc::new(RefCell::new(Node::new()));
+                        right_node.borrow_mut().set_val(Leaf::Identifier(member_name));
+                        right_node.borrow_mut().set_span(self.token_span(self.token_index - 1));
+                        node.borrow_mut().set_rhs(right_node);
+
+                        // さらに '.' が続く場合は再帰的に処理するために node を parent にして postfix_expression を呼び出す必要があるが、
+                        // 現状は一階層のみ。
+                        self.set_span(&node, start);
+                        // 再帰的なメンバアクセスをサポートするためのループ
+                        while let Some(Token::Dot) = self.get_next_token_without_increment() {
+                            self.token_index_increment();
+                            let next_node = Rc::new(RefCell::new(Node::new()));
+                            next_node.borrow_mut().set_val(Leaf::StructMemberAccess);
+                            next_node.borrow_mut().set_lhs(node.clone());
+
+                            let m_name = self.expect_identifier("メンバ名が見つかりませんでした")?;
+                            let r_node = Rc::new(RefCell::new(Node::new()));
+                            r_node.borrow_mut().set_val(Leaf::Identifier(m_name));
+                            r_node.borrow_mut().set_span(self.token_span(self.token_index - 1));
+                            next_node.borrow_mut().set_rhs(r_node);
+                            node = next_node;
+                            self.set_span(&node, start);
+                        }
                     }
-                }
-                // それ以外の場合
-                _ => {
-                    // primary_expression を呼び出す
-                    let primary_node = self.primary_expression(&node);
-                    if let Some(primary_node) = primary_node {
-                        node = primary_node;
+                    _ => {
+                        // それ以外の場合は identifier として処理する
+                        node.borrow_mut().set_val(Leaf::Identifier(identify));
                     }
                 }
-            }
 
-            return Some(node);
+                self.set_span(&node, start);
+                Ok(node)
+            }
+            // それ以外の場合
+            Some(_) => {
+                // primary_expression を呼び出す
+                self.primary_expression(&node)
+            }
+            None => {
+                Err(self.error("式が見つかりませんでした"))
+            }
         }
-
-        None
     }
 
     fn argument_expression_list(&mut self, parent: &Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>>
     {
         println!("argument_expression_list");
-        let mut node = Rc::new(RefCell::new(Node::new()));
+        let node = Rc::new(RefCell::new(Node::new()));
         node.borrow_mut().set_parent(parent);
 
         Some(node)
     }
 
-    fn primary_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>>
+    fn primary_expression(&mut self, parent: &Rc<RefCell<Node>>) -> Result<Rc<RefCell<Node>>, ParseError>
     {
+        let start = self.token_index;
         println!("primary_expression");
-        let mut node = Rc::new(RefCell::new(Node::new()));
+        let node = Rc::new(RefCell::new(Node::new()));
         node.borrow_mut().set_parent(parent);
 
         // 次のトークンを取得
-        if let Some(next_token) = self.get_next_token()
+        match self.get_next_token_without_increment()
         {
-            match next_token
-            {
-                Token::Identifier(identifier) => {
-                    node.borrow_mut().set_val(Leaf::Identifier(identifier));
-                }
-                Token::Constant(constant) => {
-                    // 定数の場合
-                    node.borrow_mut().set_val(Leaf::Constant(constant));
-                }
-                Token::LeftParen => {
-                    node.borrow_mut().set_val(Leaf::ParenthesizedExpression);
-
-                    // '(' が来た場合は logical_or_expression を呼び出す
-                    let logical_or_expression_node = self.logical_or_expression(&node);
-                    if let Some(logical_or_expression_node) = logical_or_expression_node {
-                        node.borrow_mut().set_lhs(logical_or_expression_node);
-                    } else {
-                        panic!("空の括弧が見つかりました : {:?}", self.tokens[self.token_index]);
-                    }
+            Some(Token::Identifier(identifier)) => {
+                self.token_index_increment();
+                node.borrow_mut().set_val(Leaf::Identifier(identifier));
+            }
+            Some(Token::Constant(constant)) => {
+                // 定数の場合
+                self.token_index_increment();
+                node.borrow_mut().set_val(Leaf::Constant(constant));
+            }
+            Some(Token::CharLiteral(c)) => {
+                // 文字は char の定数として扱う
+                self.token_index_increment();
+                node.borrow_mut().set_val(Leaf::Constant(Constant::Char(c as i8)));
+            }
+            Some(Token::StringLiteral(string)) => {
+                self.token_index_increment();
+                node.borrow_mut().set_val(Leaf::StringLiteral(string));
+            }
+            Some(Token::LeftParen) => {
+                self.token_index_increment();
+                node.borrow_mut().set_val(Leaf::ParenthesizedExpression);
 
-                    // ')' が来ることを確認
-                    if let Some(Token::RightParen) = self.get_next_token() {
-                        // 何もしない
-                    } else {
-                        panic!("')' が見つかりませんでした : {:?}", self.tokens[self.token_index]);
-                    }
+                // '(' が来た場合は logical_or_expression を呼び出す
+                let logical_or_expression_node = self.logical_or_expression(&node)?;
+                node.borrow_mut().set_lhs(logical_or_expression_node);
 
-                    return Some(node);
-                }
-                _ => {
-                    panic!("primary_expression でエラーが発生しました : {:?}", self.tokens[self.token_index]);
-                }
+                // ')' が来ることを確認
+                self.expect(Token::RightParen, "')' が見つかりませんでした")?;
+            }
+            _ => {
+                return Err(self.error("式が見つかりませんでした"));
             }
-            return Some(node);
         }
 
-        None
+        self.set_span(&node, start);
+        Ok(node)
     }
 
     pub fn show_tree(&self)
@@ -1639,7 +1710,7 @@ mod tests {
 
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -1679,7 +1750,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -1746,7 +1817,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -1799,7 +1870,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -1866,7 +1937,7 @@ mod tests {
         // 改行と空白を削除
         let answer = answer.replace(" ", "").replace("\n", "");
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -1934,7 +2005,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -1992,7 +2063,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -2065,7 +2136,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -2118,7 +2189,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -2171,7 +2242,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -2231,7 +2302,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -2278,7 +2349,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -2352,7 +2423,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -2442,7 +2513,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -2499,7 +2570,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -2556,7 +2627,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -2588,7 +2659,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -2649,7 +2720,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -2746,7 +2817,7 @@ mod tests {
         let answer = answer.replace(" ", "").replace("\n", "");
 
         let mut parser = Parser::new(tokens);
-        parser.parse();
+        parser.parse().unwrap();
 
         let mut tree_viewer = TreeViewer::new();
         for root in &parser.roots {
@@ -2757,4 +2828,159 @@ mod tests {
 
         assert_eq!(result, answer);
     }
+
+    #[test]
+    fn test_node_spans() {
+        let program = "int x = 1;
+int main() {
+    if (x == 1) {
+        x = x + 2;
+    }
+    return x;
+}
+";
+        let mut lexer = crate::lexical::Lexer::new(program.to_string());
+        lexer.tokenize().unwrap();
+        let mut parser = Parser::with_spans(lexer.tokens().clone(), lexer.spans().clone());
+        parser.parse().unwrap();
+
+        let span_of = |node: &Rc<RefCell<Node>>| {
+            let span = node.borrow().span().clone();
+            (span.line(), span.column(), span.end_line(), span.end_column())
+        };
+
+        // int x = 1;
+        let declaration = &parser.roots()[0];
+        assert_eq!(span_of(declaration), (1, 1, 1, 10));
+        assert_eq!(span_of(declaration.borrow().lhs().unwrap()), (1, 5, 1, 5));
+
+        // 関数定義全体
+        let function = &parser.roots()[1];
+        assert_eq!(span_of(function), (2, 1, 7, 1));
+
+        let body = match function.borrow().val() {
+            Some(Leaf::FunctionDefinition(f)) => f.body().clone(),
+            _ => panic!("関数定義ではありません"),
+        };
+
+        // if 文は '}' まで
+        let if_statement = &body[0];
+        assert_eq!(span_of(if_statement), (3, 5, 5, 5));
+
+        // 条件式 x == 1
+        if let Some(Leaf::IfStatement(condition)) = if_statement.borrow().val() {
+            assert_eq!(span_of(condition), (3, 9, 3, 14));
+        } else {
+            panic!("if 文ではありません");
+        }
+
+        // return x;
+        assert_eq!(span_of(&body[1]), (6, 5, 6, 13));
+    }
+
+    fn parse_program(program: &str) -> Result<Vec<Rc<RefCell<Node>>>, Vec<ParseError>> {
+        let mut lexer = crate::lexical::Lexer::new(program.to_string());
+        lexer.tokenize().unwrap();
+        let mut parser = Parser::with_spans(lexer.tokens().clone(), lexer.spans().clone());
+        parser.parse()
+    }
+
+    #[test]
+    fn test_parse_errors_with_recovery() {
+        let program = "int x = ;
+int main() {
+    int y = 1
+    y = 2;
+    if (y == ) { y = 3; }
+    return y;
+}
+int z = 4;
+";
+        let errors = parse_program(program).unwrap_err();
+
+        // 1 回の解析ですべてのエラーが報告される
+        let positions: Vec<(usize, usize)> = errors.iter()
+            .map(|e| (e.span().line(), e.span().column()))
+            .collect();
+        assert_eq!(positions, vec![(1, 9), (4, 5), (5, 14)]);
+
+        assert_eq!(errors[0].found(), Some(&Token::Semicolon));
+        assert_eq!(errors[1].message(), "';' が見つかりませんでした");
+        assert_eq!(errors[1].found(), Some(&Token::Identifier("y".to_string())));
+        assert_eq!(errors[2].found(), Some(&Token::RightParen));
+    }
+
+    #[test]
+    fn test_expression_statements() {
+        let roots = parse_program("int main() { int x = 1; -x; (x); x; ; return; }").unwrap();
+
+        let body = match roots[0].borrow().val() {
+            Some(Leaf::FunctionDefinition(f)) => f.body().clone(),
+            _ => panic!("関数定義ではありません"),
+        };
+
+        let leaves: Vec<String> = body.iter()
+            .map(|node| node.borrow().val().unwrap().to_string())
+            .collect();
+        assert_eq!(leaves, vec![
+            "Declaration [Int]",
+            "UnaryExpression [Minus]",
+            "ParenthesizedExpression",
+            "Identifier [\"x\"]",
+            "BlockItem",
+            "Return",
+        ]);
+    }
+
+    #[test]
+    fn test_truncated_input() {
+        // 途中で終わる入力でも panic せずにエラーを返す
+        for program in ["int", "int x", "int x =", "int main(", "int main() {", "int main() { return 1 +", "x = (1"] {
+            let errors = parse_program(program).unwrap_err();
+            assert!(!errors.is_empty(), "{}", program);
+            assert!(errors.iter().any(|e| e.found().is_none()), "{}", program);
+        }
+
+        // 余分な '}' も読み飛ばして解析を続ける
+        let errors = parse_program("} int x = 1;").unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_string_literals() {
+        let roots = parse_program(r#"char s[] = "abc"; char t[8] = "xy"; int main() { return f("hi", 'a'); }"#).unwrap();
+
+        // サイズを省略した場合は '\0' を含めた長さになる
+        let sizes: Vec<String> = roots[..2].iter()
+            .map(|root| {
+                let rhs = root.borrow().rhs().unwrap().clone();
+                let string = rhs.borrow().lhs().unwrap().borrow().val().unwrap().to_string();
+                format!("{} {}", rhs.borrow().val().unwrap(), string)
+            })
+            .collect();
+        assert_eq!(sizes, vec!["Array [4] StringLiteral [\"abc\"]", "Array [8] StringLiteral [\"xy\"]"]);
+
+        let body = match roots[2].borrow().val() {
+            Some(Leaf::FunctionDefinition(f)) => f.body().clone(),
+            _ => panic!("関数定義ではありません"),
+        };
+        let call = body[0].borrow().lhs().unwrap().clone();
+        let arguments = match call.borrow().val() {
+            Some(Leaf::FunctionCall(function_call)) => function_call.arguments().clone(),
+            _ => panic!("関数呼び出しではありません"),
+        };
+        let arguments: Vec<String> = arguments.iter()
+            .map(|argument| argument.borrow().val().unwrap().to_string())
+            .collect();
+        assert_eq!(arguments, vec!["StringLiteral [\"hi\"]", "Constant [Char(97)]"]);
+
+        let errors = parse_program(r#"char a[2] = "abc"; int b[] = "x"; char c[] = 1; char d[];"#).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message()).collect();
+        assert_eq!(messages, vec![
+            "文字列が配列のサイズより長いです",
+            "文字列で初期化できるのは char の配列だけです",
+            "文字列リテラルが見つかりませんでした",
+            "配列のサイズが見つかりませんでした",
+        ]);
+    }
 }
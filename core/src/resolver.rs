@@ -0,0 +1,284 @@
+use crate::parser::{Leaf, Node};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// 識別子の参照先
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding
+{
+  // 関数内のスコープの深さと, そのスコープ内の位置
+  Local { depth: usize, slot: usize },
+
+  // グローバル変数の位置
+  Global(usize),
+}
+
+/// 実行前に識別子の参照先を決め, 構文木に書き込む
+pub struct Resolver
+{
+  // 関数内のスコープ. 空の場合はトップレベル
+  scopes: Vec<HashMap<String, usize>>,
+
+  globals: Vec<String>,
+  global_indices: HashMap<String, usize>,
+}
+
+impl Resolver
+{
+  /// 構文木のすべての変数を解決し, グローバル変数の名前一覧を返す
+  pub fn resolve(roots: &[Rc<RefCell<Node>>]) -> Vec<String>
+  {
+    let mut resolver = Resolver
+    {
+      scopes: Vec::new(),
+      globals: Vec::new(),
+      global_indices: HashMap::new(),
+    };
+
+    for root in roots.iter()
+    {
+      resolver.node(root);
+    }
+
+    resolver.globals
+  }
+
+  fn global(&mut self, name: &str) -> usize
+  {
+    if let Some(index) = self.global_indices.get(name)
+    {
+      return *index;
+    }
+
+    self.globals.push(name.to_string());
+    self.global_indices.insert(name.to_string(), self.globals.len() - 1);
+    self.globals.len() - 1
+  }
+
+  /// ローカル変数を内側のスコープから探し, なければグローバル変数とする
+  fn lookup(&mut self, name: &str) -> Binding
+  {
+    for (depth, scope) in self.scopes.iter().enumerate().rev()
+    {
+      if let Some(slot) = scope.get(name)
+      {
+        return Binding::Local { depth, slot: *slot };
+      }
+    }
+
+    Binding::Global(self.global(name))
+  }
+
+  /// 現在のスコープに変数を宣言する. 同じスコープの同名の変数は上書きする
+  fn declare(&mut self, name: &str) -> Binding
+  {
+    let depth = self.scopes.len().wrapping_sub(1);
+    match self.scopes.last_mut()
+    {
+      Some(scope) => {
+        let next = scope.len();
+        let slot = *scope.entry(name.to_string()).or_insert(next);
+        Binding::Local { depth, slot }
+      }
+      None => Binding::Global(self.global(name)),
+    }
+  }
+
+  /// 識別子のノードに参照先を書き込む
+  fn bind(&mut self, node: &Rc<RefCell<Node>>, declare: bool)
+  {
+    let name = match node.borrow().val()
+    {
+      Some(Leaf::Identifier(name)) => name.clone(),
+      _ => return,
+    };
+
+    let binding = if declare { self.declare(&name) } else { self.lookup(&name) };
+    node.borrow_mut().set_binding(binding);
+  }
+
+  fn node(&mut self, node: &Rc<RefCell<Node>>)
+  {
+    let val = match node.borrow().val()
+    {
+      Some(val) => val.clone(),
+      None => return,
+    };
+    let lhs = node.borrow().lhs().cloned();
+    let rhs = node.borrow().rhs().cloned();
+
+    match val
+    {
+      Leaf::Declaration(_) =>
+        {
+          // 初期化子は宣言より前に解決する
+          if let Some(rhs) = &rhs
+          {
+            self.node(rhs);
+          }
+          if let Some(lhs) = &lhs
+          {
+            self.bind(lhs, true);
+          }
+        }
+      Leaf::FunctionDefinition(function_definition) =>
+        {
+          // 引数と関数本体は同じスコープに置く
+          let mut parameters = HashMap::new();
+          for argument in function_definition.arguments().iter()
+          {
+            let next = parameters.len();
+            parameters.entry(argument.identify().clone()).or_insert(next);
+          }
+
+          let scopes = std::mem::replace(&mut self.scopes, vec![parameters]);
+          for node in function_definition.body().iter()
+          {
+            self.node(node);
+          }
+          self.scopes = scopes;
+        }
+      Leaf::FunctionCall(function_call) =>
+        {
+          for argument in function_call.arguments().iter()
+          {
+            self.node(argument);
+          }
+        }
+      Leaf::BlockItem(nodes) =>
+        {
+          // トップレベルのブロックはスコープを作らない
+          let is_local = !self.scopes.is_empty();
+          if is_local
+          {
+            self.scopes.push(HashMap::new());
+          }
+
+          for node in nodes.iter()
+          {
+            self.node(node);
+          }
+
+          if is_local
+          {
+            self.scopes.pop();
+          }
+        }
+      Leaf::IfStatement(condition) =>
+        {
+          self.node(&condition);
+          lhs.iter().chain(rhs.iter()).for_each(|node| self.node(node));
+        }
+      Leaf::ForStatement(for_statement) =>
+        {
+          self.node(for_statement.initializer());
+          self.node(for_statement.condition());
+          self.node(for_statement.statement());
+          self.node(for_statement.update());
+        }
+      Leaf::ArrayAssignment(index) =>
+        {
+          if let Some(lhs) = &lhs
+          {
+            self.bind(lhs, false);
+          }
+          self.node(&index);
+          if let Some(rhs) = &rhs
+          {
+            self.node(rhs);
+          }
+        }
+      Leaf::StructMemberAccess =>
+        {
+          // 右辺はメンバ名なので解決しない
+          if let Some(lhs) = &lhs
+          {
+            self.node(lhs);
+          }
+        }
+      Leaf::Identifier(_) =>
+        {
+          self.bind(node, false);
+        }
+
+      // 構造体のメンバ宣言は変数ではない
+      Leaf::StructDefinition(_, _) => {}
+
+      _ =>
+        {
+          lhs.iter().chain(rhs.iter()).for_each(|node| self.node(node));
+        }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+  use crate::lexical::Lexer;
+  use crate::parser::Parser;
+
+  fn find_identifiers(node: &Rc<RefCell<Node>>, name: &str, found: &mut Vec<Option<Binding>>)
+  {
+    let node = node.borrow();
+    match node.val()
+    {
+      Some(Leaf::Identifier(identifier)) if identifier == name => found.push(node.binding()),
+      Some(Leaf::FunctionDefinition(function_definition)) => {
+        function_definition.body().iter().for_each(|node| find_identifiers(node, name, found));
+      }
+      Some(Leaf::BlockItem(nodes)) => {
+        nodes.iter().for_each(|node| find_identifiers(node, name, found));
+      }
+      Some(Leaf::FunctionCall(function_call)) => {
+        function_call.arguments().iter().for_each(|node| find_identifiers(node, name, found));
+      }
+      _ => {}
+    }
+    node.lhs().iter().chain(node.rhs().iter()).for_each(|node| find_identifiers(node, name, found));
+  }
+
+  #[test]
+  fn test_resolve_bindings() {
+    let mut lexer = Lexer::new("
+        int g = 1;
+        int f(int a, int b) {
+            int c = a + g;
+            {
+                int a = b;
+                c = a;
+            }
+            return a + c;
+        }
+    ".to_string());
+    lexer.tokenize().unwrap();
+    let mut parser = Parser::new(lexer.tokens().clone());
+    let roots = parser.parse().unwrap();
+
+    let globals = Resolver::resolve(&roots);
+    assert_eq!(globals, vec!["g".to_string()]);
+
+    let mut found = Vec::new();
+    roots.iter().for_each(|root| find_identifiers(root, "a", &mut found));
+    assert_eq!(found, vec![
+      Some(Binding::Local { depth: 0, slot: 0 }),
+      Some(Binding::Local { depth: 1, slot: 0 }),
+      Some(Binding::Local { depth: 1, slot: 0 }),
+      Some(Binding::Local { depth: 0, slot: 0 }),
+    ]);
+
+    let mut found = Vec::new();
+    roots.iter().for_each(|root| find_identifiers(root, "c", &mut found));
+    assert_eq!(found, vec![
+      Some(Binding::Local { depth: 0, slot: 2 }),
+      Some(Binding::Local { depth: 0, slot: 2 }),
+      Some(Binding::Local { depth: 0, slot: 2 }),
+    ]);
+
+    let mut found = Vec::new();
+    roots.iter().for_each(|root| find_identifiers(root, "g", &mut found));
+    assert_eq!(found, vec![Some(Binding::Global(0)), Some(Binding::Global(0))]);
+  }
+}
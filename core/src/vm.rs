@@ -0,0 +1,1049 @@
+use crate::builtin;
+use crate::bytecode::{Instruction, Program};
+use crate::interpreter::{Array, Interpreter, NativeFunction, RuntimeError, RuntimeErrorKind, StackFrame, Struct, Variable, VariableType};
+use crate::lexical::{Span, ValueType};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+/// 実行中の関数呼び出し
+struct Frame
+{
+  function: usize,
+
+  // 次に実行する命令の位置
+  ip: usize,
+
+  // スタック上のローカル変数の先頭
+  base: usize,
+
+  call_site: Option<Span>,
+}
+
+/// バイトコードを実行するスタックマシン
+pub struct Vm
+{
+  program: Program,
+  stack: Vec<VariableType>,
+  frames: Vec<Frame>,
+
+  // 未定義のグローバル変数は None
+  globals: Vec<Option<VariableType>>,
+
+  // 定義文を実行済みの関数
+  defined: Vec<bool>,
+
+  // register_native で登録された関数と引数の数
+  native_functions: HashMap<String, (usize, NativeFunction)>,
+
+  // getchar などの組み込み関数の入力元
+  input: Box<dyn BufRead>,
+
+  // printf などの組み込み関数の出力先
+  output: Box<dyn Write>,
+
+  // 直前の scanf で読み取った値
+  scanned: Vec<VariableType>,
+}
+
+impl Vm
+{
+  /// 組み込み関数の入力は空になる
+  pub fn new(program: Program) -> Self
+  {
+    Self::with_input(program, Box::new(std::io::empty()))
+  }
+
+  /// 組み込み関数の入力を input から読む
+  pub fn with_input(program: Program, input: Box<dyn BufRead>) -> Self
+  {
+    let globals = vec![None; program.globals().len()];
+    let defined = vec![false; program.functions().len()];
+    Vm
+    {
+      program,
+      stack: Vec::new(),
+      frames: Vec::new(),
+      globals,
+      defined,
+      native_functions: HashMap::new(),
+      input,
+      output: Box::new(std::io::stdout()),
+      scanned: Vec::new(),
+    }
+  }
+
+  /// 組み込み関数の出力先を設定する. 初期値は標準出力
+  pub fn set_output(&mut self, output: Box<dyn Write>)
+  {
+    self.output = output;
+  }
+
+  /// ホストの関数を登録する. Interpreter::register_native と同じ規則で呼び出される
+  pub fn register_native<F>(&mut self, name: &str, arity: usize, function: F)
+  where
+    F: Fn(&[VariableType]) -> Result<VariableType, RuntimeError> + 'static,
+  {
+    self.native_functions.insert(name.to_string(), (arity, Rc::new(function)));
+  }
+
+  /// グローバル変数を Interpreter と同じ形で取得する
+  pub fn global_variables(&self) -> HashMap<String, Variable>
+  {
+    let mut variables = HashMap::new();
+    for (name, value) in self.program.globals().iter().zip(self.globals.iter())
+    {
+      let variable = match value
+      {
+        Some(VariableType::Array(array)) => Variable::Array(array.clone()),
+        Some(VariableType::Struct(s)) => Variable::Struct(s.clone()),
+        Some(value) => Variable::Value(value.clone()),
+        None => continue,
+      };
+      variables.insert(name.clone(), variable);
+    }
+    variables
+  }
+
+  /// トップレベルを実行した後に main を呼び出し, その戻り値を返す
+  pub fn run(&mut self) -> Result<VariableType, RuntimeError>
+  {
+    self.stack.clear();
+    self.frames.clear();
+    self.globals.iter_mut().for_each(|global| *global = None);
+    self.defined.iter_mut().for_each(|defined| *defined = false);
+
+    let init = self.program.init();
+    self.defined[init] = true;
+    self.execute(init, None)?;
+
+    let main = match self.program.function_index("main")
+    {
+      Some(main) if self.defined[main] => main,
+      _ => {
+        return Err(RuntimeError::new(RuntimeErrorKind::MissingMain, None, Vec::new()));
+      }
+    };
+
+    let expected = self.program.functions()[main].parameters().len();
+    if expected != 0
+    {
+      return Err(RuntimeError::new(RuntimeErrorKind::ArgumentCountMismatch {
+        function: "main".to_string(),
+        expected,
+        found: 0,
+      }, None, Vec::new()));
+    }
+
+    let value = self.execute(main, None)?;
+
+    self.output.flush()
+      .map_err(|error| RuntimeError::new(RuntimeErrorKind::Io(error.to_string()), None, Vec::new()))?;
+
+    Ok(value)
+  }
+
+  /// 関数を呼び出し, 戻るまで実行する
+  fn execute(&mut self, function: usize, call_site: Option<Span>) -> Result<VariableType, RuntimeError>
+  {
+    let depth = self.frames.len();
+    self.push_frame(function, 0, call_site);
+
+    while self.frames.len() > depth
+    {
+      self.step()?;
+    }
+
+    Ok(self.stack.pop().unwrap_or(VariableType::Void))
+  }
+
+  fn push_frame(&mut self, function: usize, argc: usize, call_site: Option<Span>)
+  {
+    let base = self.stack.len() - argc;
+    let locals = self.program.functions()[function].locals();
+    self.stack.resize(base + locals.max(argc), VariableType::Void);
+    self.frames.push(Frame { function, ip: 0, base, call_site });
+  }
+
+  /// 実行中の命令の位置と呼び出し履歴を付けたエラーを作成する
+  fn runtime_error(&self, kind: RuntimeErrorKind) -> RuntimeError
+  {
+    RuntimeError::new(kind, self.current_span(), self.call_stack())
+  }
+
+  /// 実行中の命令の位置
+  fn current_span(&self) -> Option<Span>
+  {
+    self.frames.last().and_then(|frame| {
+      self.program.functions()[frame.function].spans().get(frame.ip.saturating_sub(1)).cloned()
+    })
+  }
+
+  /// トップレベルを除いた呼び出し履歴
+  fn call_stack(&self) -> Vec<StackFrame>
+  {
+    let init = self.program.init();
+    self.frames.iter()
+      .filter(|frame| frame.function != init)
+      .map(|frame| {
+        StackFrame::new(self.program.functions()[frame.function].name().clone(), frame.call_site.clone())
+      })
+      .collect()
+  }
+
+  fn pop(&mut self) -> VariableType
+  {
+    self.stack.pop().unwrap_or(VariableType::Void)
+  }
+
+  fn name(&self, index: usize) -> String
+  {
+    self.program.names()[index].clone()
+  }
+
+  fn local(&mut self, slot: usize) -> &mut VariableType
+  {
+    let base = self.frames.last().map(|frame| frame.base).unwrap_or(0);
+    &mut self.stack[base + slot]
+  }
+
+  /// 命令を一つ実行する
+  fn step(&mut self) -> Result<(), RuntimeError>
+  {
+    let frame = self.frames.last_mut().unwrap();
+    let instruction = self.program.functions()[frame.function].code()[frame.ip];
+    frame.ip += 1;
+
+    match instruction
+    {
+      Instruction::Constant(index) => {
+        self.stack.push(self.program.constants()[index].clone());
+      }
+      Instruction::Pop => {
+        self.pop();
+      }
+      Instruction::LoadLocal(slot) => {
+        let value = self.local(slot).clone();
+        self.stack.push(value);
+      }
+      Instruction::DefineLocal(slot) => {
+        let value = self.pop();
+        *self.local(slot) = value;
+      }
+      Instruction::StoreLocal { slot, name } => {
+        let value = self.pop();
+        let target = self.local(slot).clone();
+        let value = self.assignable(target, value, name)?;
+        *self.local(slot) = value;
+      }
+      Instruction::SetLocalMember { slot, name, member } => {
+        let value = self.pop();
+        let member = self.name(member);
+        match self.local(slot)
+        {
+          VariableType::Struct(s) => {
+            Self::set_member(s, member, value);
+          }
+          _ => {
+            let kind = RuntimeErrorKind::TypeMismatch(format!("{} は構造体ではありません", self.name(name)));
+            return Err(self.runtime_error(kind));
+          }
+        }
+      }
+      Instruction::LoadGlobal(global) => {
+        match &self.globals[global]
+        {
+          Some(value) => self.stack.push(value.clone()),
+          None => {
+            let kind = RuntimeErrorKind::UndefinedVariable(self.program.globals()[global].clone());
+            return Err(self.runtime_error(kind));
+          }
+        }
+      }
+      Instruction::DefineGlobal(global) => {
+        let value = self.pop();
+        self.globals[global] = Some(value);
+      }
+      Instruction::StoreGlobal(global) => {
+        let value = self.pop();
+        let name = &self.program.globals()[global];
+        let target = match &self.globals[global]
+        {
+          Some(target) => target.clone(),
+          None => return Err(self.runtime_error(RuntimeErrorKind::UndefinedVariable(name.clone()))),
+        };
+        let value = self.assignable_global(target, value, global)?;
+        self.globals[global] = Some(value);
+      }
+      Instruction::SetGlobalMember { global, member } => {
+        let value = self.pop();
+        let member = self.name(member);
+        let name = self.program.globals()[global].clone();
+        match &mut self.globals[global]
+        {
+          Some(VariableType::Struct(s)) => {
+            Self::set_member(s, member, value);
+          }
+          Some(_) => {
+            return Err(self.runtime_error(RuntimeErrorKind::TypeMismatch(format!("{} は構造体ではありません", name))));
+          }
+          None => {
+            return Err(self.runtime_error(RuntimeErrorKind::UndefinedVariable(name)));
+          }
+        }
+      }
+      Instruction::ToInt(name) | Instruction::ToFloat(name) | Instruction::ToChar(name) => {
+        let value = match (instruction, self.pop())
+        {
+          (Instruction::ToInt(_), VariableType::Float(val)) => VariableType::Int(val as i32),
+          (Instruction::ToInt(_), VariableType::Int(val)) => VariableType::Int(val),
+          (Instruction::ToInt(_), VariableType::Char(val)) => VariableType::Int(val as i32),
+          (Instruction::ToFloat(_), VariableType::Float(val)) => VariableType::Float(val),
+          (Instruction::ToFloat(_), VariableType::Int(val)) => VariableType::Float(val as f64),
+          (Instruction::ToFloat(_), VariableType::Char(val)) => VariableType::Float(val as f64),
+          (Instruction::ToChar(_), value @ (VariableType::Int(_) | VariableType::Float(_) | VariableType::Char(_))) =>
+            Interpreter::store_as(&ValueType::Char, value),
+          (_, value) => {
+            let kind = RuntimeErrorKind::TypeMismatch(format!("{} に {} は代入できません", self.name(name), value));
+            return Err(self.runtime_error(kind));
+          }
+        };
+        self.stack.push(value);
+      }
+      Instruction::NewArray { name, size, element } => {
+        let element = self.program.constants()[element].clone();
+        let array = Array::new(self.name(name), element.clone(), vec![element; size]);
+        self.stack.push(VariableType::Array(Rc::new(RefCell::new(array))));
+      }
+      Instruction::CopyArray(index) => {
+        let array = match &self.program.constants()[index]
+        {
+          VariableType::Array(array) => array.borrow().clone(),
+          other => {
+            return Err(self.runtime_error(RuntimeErrorKind::TypeMismatch(format!("配列ではありません : {}", other))));
+          }
+        };
+        self.stack.push(VariableType::Array(Rc::new(RefCell::new(array))));
+      }
+      Instruction::Index => {
+        let index = self.pop();
+        let array = match self.pop()
+        {
+          VariableType::Array(array) => array,
+          other => {
+            return Err(self.runtime_error(RuntimeErrorKind::TypeMismatch(format!("配列ではありません : {}", other))));
+          }
+        };
+        let index = self.array_index(&array, index)?;
+        let value = array.borrow().values()[index].clone();
+        self.stack.push(value);
+      }
+      Instruction::CheckIndex(name) => {
+        let index = self.pop();
+        let array = match self.stack.last()
+        {
+          Some(VariableType::Array(array)) => array.clone(),
+          _ => {
+            let kind = RuntimeErrorKind::TypeMismatch(format!("{} は配列ではありません", self.name(name)));
+            return Err(self.runtime_error(kind));
+          }
+        };
+        let index = self.array_index(&array, index)?;
+        self.stack.push(VariableType::Int(index as i32));
+      }
+      Instruction::StoreIndex => {
+        let value = self.pop();
+        let index = self.pop();
+        let array = self.pop();
+        if let (VariableType::Array(array), VariableType::Int(index)) = (array, index)
+        {
+          let value = Interpreter::store_value(array.borrow().variable_type(), value);
+          array.borrow_mut().values_mut()[index as usize] = value;
+        }
+      }
+      Instruction::Member(member) => {
+        let member = self.name(member);
+        let value = match self.pop()
+        {
+          VariableType::Struct(s) => match s.members().get(&member)
+          {
+            Some(value) => value.clone(),
+            None => return Err(self.runtime_error(RuntimeErrorKind::UndefinedMember(member))),
+          },
+          other => {
+            return Err(self.runtime_error(RuntimeErrorKind::TypeMismatch(format!("構造体ではありません : {}", other))));
+          }
+        };
+        self.stack.push(value);
+      }
+      Instruction::Binary(op) => {
+        let rhs = self.pop();
+        let lhs = self.pop();
+        let value = Interpreter::binary_operator(&op, lhs, rhs).map_err(|kind| self.runtime_error(kind))?;
+        self.stack.push(value);
+      }
+      Instruction::Unary(op) => {
+        let value = self.pop();
+        let value = Interpreter::unary_operator(&op, value).map_err(|kind| self.runtime_error(kind))?;
+        self.stack.push(value);
+      }
+      Instruction::Test => {
+        let value = self.pop();
+        let value = Interpreter::truth_value(value).map_err(|kind| self.runtime_error(kind))?;
+        self.stack.push(VariableType::Int(value as i32));
+      }
+      Instruction::Jump(target) => {
+        self.jump(target);
+      }
+      Instruction::JumpIfFalse(target) => {
+        let value = self.pop();
+        if !Interpreter::truth_value(value).map_err(|kind| self.runtime_error(kind))?
+        {
+          self.jump(target);
+        }
+      }
+      Instruction::JumpIfFalseKeep(target) | Instruction::JumpIfTrueKeep(target) => {
+        let value = self.stack.last().cloned().unwrap_or(VariableType::Void);
+        let value = Interpreter::truth_value(value).map_err(|kind| self.runtime_error(kind))?;
+        if value == matches!(instruction, Instruction::JumpIfTrueKeep(_))
+        {
+          self.jump(target);
+        }
+      }
+      Instruction::DefineFunction(function) => {
+        self.defined[function] = true;
+      }
+      Instruction::Call { function, argc } => {
+        self.call(function, argc)?;
+      }
+      Instruction::CallExternal { name, argc } => {
+        self.call_external(name, argc)?;
+      }
+      Instruction::Scanf { name, argc } => {
+        self.scanf(name, argc)?;
+      }
+      Instruction::ScanfValue(index) => {
+        self.stack.push(self.scanned[index].clone());
+      }
+      Instruction::JumpIfNotScanned { index, target } => {
+        if index >= self.scanned.len()
+        {
+          self.jump(target);
+        }
+      }
+      Instruction::CheckVoid(name) => {
+        if let Some(VariableType::Void) = self.stack.last()
+        {
+          return Err(self.runtime_error(RuntimeErrorKind::VoidValue(self.name(name))));
+        }
+      }
+      Instruction::Return => {
+        let value = self.pop();
+        if let Some(frame) = self.frames.pop()
+        {
+          self.stack.truncate(frame.base);
+        }
+        self.stack.push(value);
+      }
+      Instruction::Raise(error) => {
+        return Err(self.runtime_error(self.program.errors()[error].clone()));
+      }
+    }
+
+    Ok(())
+  }
+
+  fn jump(&mut self, target: usize)
+  {
+    if let Some(frame) = self.frames.last_mut()
+    {
+      frame.ip = target;
+    }
+  }
+
+  /// 添字が int で配列の範囲内にあることを確認する
+  fn array_index(&self, array: &Rc<RefCell<Array>>, index: VariableType) -> Result<usize, RuntimeError>
+  {
+    let index = match Interpreter::promote(index)
+    {
+      VariableType::Int(val) => val,
+      other => return Err(self.runtime_error(RuntimeErrorKind::NonIntegerIndex(other.to_string()))),
+    };
+
+    let length = array.borrow().values().len();
+    if index < 0 || index as usize >= length
+    {
+      let array = array.borrow().name().clone();
+      return Err(self.runtime_error(RuntimeErrorKind::IndexOutOfBounds { array, index, length }));
+    }
+
+    Ok(index as usize)
+  }
+
+  /// 代入先の変数に value を代入できるか確認する
+  fn assignable(&self, target: VariableType, value: VariableType, name: usize) -> Result<VariableType, RuntimeError>
+  {
+    self.check_assignment(target, value, &self.program.names()[name])
+  }
+
+  fn assignable_global(&self, target: VariableType, value: VariableType, global: usize)
+                       -> Result<VariableType, RuntimeError>
+  {
+    self.check_assignment(target, value, &self.program.globals()[global])
+  }
+
+  fn check_assignment(&self, target: VariableType, value: VariableType, identifier: &String)
+                      -> Result<VariableType, RuntimeError>
+  {
+    match (target, value)
+    {
+      (VariableType::Array(_), _) => {
+        Err(self.runtime_error(RuntimeErrorKind::TypeMismatch(format!("配列 {} には代入できません", identifier))))
+      }
+      (VariableType::Struct(_), VariableType::Struct(s)) => Ok(VariableType::Struct(s)),
+      (VariableType::Struct(_), value) => {
+        Err(self.runtime_error(
+          RuntimeErrorKind::TypeMismatch(format!("構造体 {} に {} は代入できません", identifier, value))))
+      }
+      (target, value) => Ok(Interpreter::store_value(&target, value)),
+    }
+  }
+
+  /// 構造体のメンバに代入する. char のメンバには char に切り詰めて代入する
+  fn set_member(s: &mut Struct, member: String, value: VariableType)
+  {
+    let value = match s.members().get(&member)
+    {
+      Some(target) => Interpreter::store_value(target, value),
+      None => value,
+    };
+    s.members_mut().insert(member, value);
+  }
+
+  /// ホストの関数か組み込み関数を呼び出し, 戻り値を積む
+  fn call_external(&mut self, name: usize, argc: usize) -> Result<(), RuntimeError>
+  {
+    let name = self.name(name);
+    let value = if let Some((arity, function)) = self.native_functions.get(&name).cloned()
+    {
+      if arity != argc
+      {
+        return Err(self.runtime_error(RuntimeErrorKind::ArgumentCountMismatch {
+          function: name,
+          expected: arity,
+          found: argc,
+        }));
+      }
+
+      // ホストが返したエラーはそのまま返し, 呼び出し履歴だけ外側に付け足す
+      let arguments = self.stack.split_off(self.stack.len() - argc);
+      function(&arguments).map_err(|error| {
+        let mut call_stack = self.call_stack();
+        call_stack.push(StackFrame::new(name.clone(), self.current_span()));
+        error.called_from(call_stack)
+      })?
+    } else if builtin::is_builtin(&name)
+    {
+      let arguments = self.stack.split_off(self.stack.len() - argc);
+      builtin::call(&name, &arguments, self.input.as_mut(), self.output.as_mut())
+        .map_err(|kind| self.runtime_error(kind))?
+    } else {
+      return Err(self.runtime_error(RuntimeErrorKind::UndefinedFunction(name)));
+    };
+
+    self.stack.push(value);
+    Ok(())
+  }
+
+  /// scanf を呼び出し, 読み取った数を積む. 読み取った値は scanned に残す
+  fn scanf(&mut self, name: usize, argc: usize) -> Result<(), RuntimeError>
+  {
+    self.scanned.clear();
+    // ホストの関数が登録されていれば, 引数には代入しない
+    if self.native_functions.contains_key(&self.name(name))
+    {
+      return self.call_external(name, argc);
+    }
+
+    let arguments = self.stack.split_off(self.stack.len() - argc);
+    let count = match builtin::scanf(&arguments, self.input.as_mut()).map_err(|kind| self.runtime_error(kind))?
+    {
+      Some(values) => {
+        self.scanned = values;
+        self.scanned.len() as i32
+      }
+      None => -1,
+    };
+    self.stack.push(VariableType::Int(count));
+    Ok(())
+  }
+
+  fn call(&mut self, function: usize, argc: usize) -> Result<(), RuntimeError>
+  {
+    let name = self.program.functions()[function].name().clone();
+    if !self.defined[function]
+    {
+      return Err(self.runtime_error(RuntimeErrorKind::UndefinedFunction(name)));
+    }
+
+    // 引数の型を確認する. 配列は参照, 構造体は値で渡す
+    let base = self.stack.len() - argc;
+    for (parameter, argument) in self.program.functions()[function].parameters().iter().zip(&mut self.stack[base..])
+    {
+      match (parameter.type_specifier(), &*argument)
+      {
+        (ValueType::Array(_, _), VariableType::Array(_)) | (ValueType::Struct(_), VariableType::Struct(_)) => {}
+        (ValueType::Array(_, _), _) | (ValueType::Struct(_), _) |
+        (_, VariableType::Array(_)) | (_, VariableType::Struct(_)) => {
+          return Err(self.runtime_error(RuntimeErrorKind::TypeMismatch(
+            format!("{} の引数 {} に渡された値の型が一致しません", name, parameter.identify()))));
+        }
+        (value_type, _) => {
+          *argument = Interpreter::store_as(value_type, argument.clone());
+        }
+      }
+    }
+
+    let call_site = self.frames.last().and_then(|frame| {
+      self.program.functions()[frame.function].spans().get(frame.ip - 1).cloned()
+    });
+    self.push_frame(function, argc, call_site);
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+  use crate::bytecode::Compiler;
+  use crate::lexical::Lexer;
+  use crate::parser::Parser;
+
+  fn parse(program: &str) -> Vec<Rc<RefCell<crate::parser::Node>>> {
+    let mut lexer = Lexer::new(program.to_string());
+    lexer.tokenize().unwrap();
+    let mut parser = Parser::with_spans(lexer.tokens().clone(), lexer.spans().clone());
+    parser.parse().unwrap()
+  }
+
+  /// Interpreter と VM で同じ結果になることを確認する
+  fn run_both(program: &str) -> Result<VariableType, RuntimeError> {
+    let roots = parse(program);
+
+    let mut interpreter = Interpreter::new(&roots);
+    let expected = interpreter.run();
+
+    let mut vm = Vm::new(Compiler::compile(&roots));
+    let actual = vm.run();
+
+    assert_eq!(actual, expected);
+    if expected.is_ok()
+    {
+      assert_eq!(vm.global_variables(), interpreter.global_variables());
+    }
+    actual
+  }
+
+  #[test]
+  fn test_program() {
+    let result = run_both("
+        int a = 10;
+        float b = 2.5;
+        int c[5];
+        int add(int x, int y) { return x + y; }
+        void set(int v) { a = v; }
+        int main() {
+            int i;
+            for (i = 0; i < 5; i = i + 1) {
+                c[i] = i * i;
+            }
+            set(add(c[2], c[3]));
+            b = b * 2;
+            return a + b;
+        }
+    ");
+    assert_eq!(result, Ok(VariableType::Float(18.0)));
+  }
+
+  #[test]
+  fn test_recursion() {
+    let result = run_both("
+        int fib(int n) {
+            if (n < 2) { return n; }
+            return fib(n - 1) + fib(n - 2);
+        }
+        int main() { return fib(15); }
+    ");
+    assert_eq!(result, Ok(VariableType::Int(610)));
+  }
+
+  #[test]
+  fn test_loops() {
+    let result = run_both("
+        int count = 0;
+        int main() {
+            int i = 0;
+            while (1) {
+                i = i + 1;
+                if (i > 20) { break; }
+                if (i % 2 == 0) { continue; }
+                int j;
+                for (j = 0; j < i; j = j + 1) {
+                    if (j == 3) { continue; }
+                    if (j == 5) { break; }
+                    count = count + 1;
+                }
+            }
+            return count;
+        }
+    ");
+    assert_eq!(result, Ok(VariableType::Int(36)));
+  }
+
+  #[test]
+  fn test_scopes() {
+    let result = run_both("
+        int x = 1;
+        int main() {
+            int y = x;
+            {
+                int x = 10;
+                y = y + x;
+            }
+            {
+                int z = 100;
+                y = y + z + x;
+            }
+            return y;
+        }
+    ");
+    assert_eq!(result, Ok(VariableType::Int(112)));
+  }
+
+  #[test]
+  fn test_array_by_reference() {
+    let result = run_both("
+        int data[4];
+        void fill(int arr[4], int value) {
+            int i;
+            for (i = 0; i < 4; i = i + 1) { arr[i] = value + i; }
+        }
+        int sum(int arr[4]) {
+            int total = 0;
+            int i;
+            for (i = 0; i < 4; i = i + 1) { total = total + arr[i]; }
+            return total;
+        }
+        int main() {
+            int local[4];
+            fill(data, 1);
+            fill(local, 10);
+            return sum(data) + sum(local);
+        }
+    ");
+    assert_eq!(result, Ok(VariableType::Int(56)));
+  }
+
+  #[test]
+  fn test_struct() {
+    let result = run_both("
+        struct Point { int x; int y; };
+        struct Line { struct Point a; int width; };
+        struct Point origin;
+        int length(struct Point p) {
+            p.x = p.x * 2;
+            return p.x + p.y;
+        }
+        int main() {
+            struct Point p;
+            struct Line l;
+            p.x = 3;
+            p.y = 4;
+            origin = p;
+            l.width = length(p);
+            return p.x + l.width + origin.y;
+        }
+    ");
+    assert_eq!(result, Ok(VariableType::Int(17)));
+  }
+
+  #[test]
+  fn test_short_circuit() {
+    let result = run_both("
+        int calls = 0;
+        int touch() { calls = calls + 1; return 1; }
+        int main() {
+            int a = 0 && touch();
+            int b = 1 || touch();
+            int c = 1 && touch();
+            int d = 0 || touch();
+            int arr[3];
+            int i = 5;
+            if (i < 3 && arr[i] == 0) { return -1; }
+            return a + b * 10 + c * 100 + d * 1000 + calls * 10000;
+        }
+    ");
+    assert_eq!(result, Ok(VariableType::Int(21110)));
+  }
+
+  #[test]
+  fn test_runtime_errors() {
+    let error = run_both("
+        int div(int a, int b) {
+            return a / b;
+        }
+        int main() {
+            return div(1, 0);
+        }
+    ").unwrap_err();
+    assert_eq!(error.kind(), &RuntimeErrorKind::DivisionByZero);
+    assert_eq!(error.call_stack().len(), 2);
+
+    let error = run_both("int main() { int a[2]; return a[2]; }").unwrap_err();
+    assert!(matches!(error.kind(), RuntimeErrorKind::IndexOutOfBounds { .. }));
+
+    let error = run_both("int main() { return undefined; }").unwrap_err();
+    assert_eq!(error.kind(), &RuntimeErrorKind::UndefinedVariable("undefined".to_string()));
+
+    let error = run_both("void f() {} int main() { return f() + 1; }").unwrap_err();
+    assert_eq!(error.kind(), &RuntimeErrorKind::VoidValue("f".to_string()));
+
+    let error = run_both("int f(int a) { return a; } int main() { return f(); }").unwrap_err();
+    assert!(matches!(error.kind(), RuntimeErrorKind::ArgumentCountMismatch { .. }));
+
+    let error = run_both("int x = 1;").unwrap_err();
+    assert_eq!(error.kind(), &RuntimeErrorKind::MissingMain);
+    assert_eq!(error.span(), None);
+  }
+
+  #[test]
+  fn test_strings() {
+    let result = run_both(r#"
+        char name[] = "vm";
+        char initial;
+        int count(char s[4], char c) {
+            int i = 0;
+            int n = 0;
+            while (s[i] != 0) {
+                if (s[i] == c) { n = n + 1; }
+                i = i + 1;
+            }
+            return n;
+        }
+        int main() {
+            char s[4] = "aba";
+            initial = name[0] + 0.5;
+            name[1] = 300;
+            return count(s, 'a' + 256) * 10 + count("bbb", 98);
+        }
+    "#);
+    assert_eq!(result, Ok(VariableType::Int(23)));
+  }
+
+  /// テストで出力を読み出せるように共有する出力先
+  #[derive(Clone, Default)]
+  struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+  impl Write for SharedOutput
+  {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize>
+    {
+      self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+      Ok(())
+    }
+  }
+
+  /// Interpreter と VM で実行し, 戻り値と出力が一致することを確かめる
+  fn run_both_with_output(program: &str) -> (Result<VariableType, RuntimeError>, String) {
+    let roots = parse(program);
+
+    let expected_output = SharedOutput::default();
+    let mut interpreter = Interpreter::new(&roots);
+    interpreter.set_output(Box::new(expected_output.clone()));
+    let expected = interpreter.run();
+
+    let actual_output = SharedOutput::default();
+    let mut vm = Vm::new(Compiler::compile(&roots));
+    vm.set_output(Box::new(actual_output.clone()));
+    let actual = vm.run();
+
+    assert_eq!(actual, expected);
+    let output = String::from_utf8(actual_output.0.borrow().clone()).unwrap();
+    assert_eq!(output, String::from_utf8(expected_output.0.borrow().clone()).unwrap());
+    (actual, output)
+  }
+
+  #[test]
+  fn test_builtin_output() {
+    let (result, output) = run_both_with_output(r#"
+        char name[8] = "vm";
+        int show(int n) {
+            return printf("%s %d %f %c%%\n", name, n, n / 2.0, 'a' + n);
+        }
+        int main() {
+            int i;
+            for (i = 0; i < 2; i = i + 1) {
+                putchar('0' + i);
+            }
+            puts("");
+            return show(3);
+        }
+    "#);
+    assert_eq!(result, Ok(VariableType::Int(17)));
+    assert_eq!(output, "01\nvm 3 1.500000 d%\n");
+
+    // ユーザが定義した関数が組み込み関数より優先される
+    let (result, output) = run_both_with_output("
+        int puts(int n) { return n; }
+        int main() { return puts(4); }
+    ");
+    assert_eq!(result, Ok(VariableType::Int(4)));
+    assert_eq!(output, "");
+
+    let (result, _) = run_both_with_output(r#"int main() { printf("%d\n", "text"); return 0; }"#);
+    assert!(matches!(result.unwrap_err().kind(), RuntimeErrorKind::TypeMismatch(_)));
+  }
+
+  #[test]
+  fn test_builtin_input() {
+    let program = r#"
+        int main() {
+            int n = read_int();
+            int i;
+            float sum = 0.0;
+            for (i = 0; i < n; i = i + 1) {
+                sum = sum + read_float();
+            }
+            printf("%f\n", sum);
+            int rest = 0;
+            while (getchar() != -1) { rest = rest + 1; }
+            return rest;
+        }
+    "#;
+    let roots = parse(program);
+    for (input, expected) in [("2 1.5 2.25\nab", 3), ("0", 0), ("", 0)]
+    {
+      let expected_output = SharedOutput::default();
+      let mut interpreter = Interpreter::with_input(&roots, Box::new(input.as_bytes()));
+      interpreter.set_output(Box::new(expected_output.clone()));
+      let expected_value = interpreter.run();
+
+      let actual_output = SharedOutput::default();
+      let mut vm = Vm::with_input(Compiler::compile(&roots), Box::new(input.as_bytes()));
+      vm.set_output(Box::new(actual_output.clone()));
+      assert_eq!(vm.run(), expected_value, "入力 : {:?}", input);
+      assert_eq!(actual_output.0.borrow().clone(), expected_output.0.borrow().clone(), "入力 : {:?}", input);
+      assert_eq!(expected_value, Ok(VariableType::Int(expected)));
+    }
+
+    // scanf は読み取った数だけ変数か配列の要素に代入する
+    let programs = [
+      r#"
+        int total;
+        int main() {
+            int n;
+            float scale;
+            int a[3];
+            int count = scanf("%d %f %d", n, scale, a[n]);
+            total = scanf("%d,%d", a[0], total);
+            printf("%d %d %f %d %d %d\n", count, n, scale, a[0], a[1], a[2]);
+            return total;
+        }
+      "#,
+      r#"int main() { int n = 7; return scanf("%d %d", n, 1) * 10 + n; }"#,
+      r#"int main() { int a[2]; return scanf("%d %d", a[0], a); }"#,
+    ];
+    for program in programs
+    {
+      let roots = parse(program);
+      for input in ["1 0.5 8 4,5", "2 1.5 9", "2 x", "", "-3 2.0 1"]
+      {
+        let expected_output = SharedOutput::default();
+        let mut interpreter = Interpreter::with_input(&roots, Box::new(input.as_bytes()));
+        interpreter.set_output(Box::new(expected_output.clone()));
+        let expected_value = interpreter.run();
+
+        let actual_output = SharedOutput::default();
+        let mut vm = Vm::with_input(Compiler::compile(&roots), Box::new(input.as_bytes()));
+        vm.set_output(Box::new(actual_output.clone()));
+        assert_eq!(vm.run(), expected_value, "入力 : {:?}", input);
+        assert_eq!(actual_output.0.borrow().clone(), expected_output.0.borrow().clone(), "入力 : {:?}", input);
+      }
+    }
+  }
+
+  #[test]
+  fn test_native_functions() {
+    let roots = parse("
+        int scores[3];
+        int check(int n) { return fail(n); }
+        int main() {
+            fill(scores, 4);
+            if (scores[2] != 4) { return 0; }
+            return twice(scores[0]) + check(1);
+        }
+    ");
+
+    type Function = Box<dyn Fn(&[VariableType]) -> Result<VariableType, RuntimeError>>;
+
+    // Interpreter と同じ関数を登録する
+    fn register(register_native: &mut dyn FnMut(&str, usize, Function))
+    {
+      register_native("fill", 2, Box::new(|arguments| {
+        if let [VariableType::Array(array), value] = arguments {
+          let length = array.borrow().values().len();
+          for index in 0..length {
+            array.borrow_mut().set(index, value.clone()).unwrap();
+          }
+        }
+        Ok(VariableType::Void)
+      }));
+      register_native("twice", 1, Box::new(|arguments| match arguments {
+        [VariableType::Int(value)] => Ok(VariableType::Int(value * 2)),
+        _ => Ok(VariableType::Int(0)),
+      }));
+      register_native("fail", 1, Box::new(|arguments| match arguments {
+        [VariableType::Int(0)] => Ok(VariableType::Int(0)),
+        _ => Err(RuntimeError::new(RuntimeErrorKind::Unsupported("失敗".to_string()), None,
+                                   vec![StackFrame::new("host".to_string(), None)])),
+      }));
+    }
+
+    let mut interpreter = Interpreter::new(&roots);
+    register(&mut |name, arity, function| interpreter.register_native(name, arity, function));
+    let expected = interpreter.run();
+
+    let mut vm = Vm::new(Compiler::compile(&roots));
+    register(&mut |name, arity, function| vm.register_native(name, arity, function));
+    let actual = vm.run();
+
+    assert_eq!(actual, expected);
+    let error = actual.unwrap_err();
+    assert_eq!(error.span(), None);
+    let functions: Vec<&String> = error.call_stack().iter().map(|frame| frame.function()).collect();
+    assert_eq!(functions, vec!["main", "check", "fail", "host"]);
+    assert_eq!(vm.global_variables(), interpreter.global_variables());
+
+    // 配列は参照で渡される
+    let roots = parse("
+        int scores[3];
+        int main() { fill(scores, 4); return twice(scores[2]); }
+    ");
+    let mut vm = Vm::new(Compiler::compile(&roots));
+    register(&mut |name, arity, function| vm.register_native(name, arity, function));
+    assert_eq!(vm.run(), Ok(VariableType::Int(8)));
+
+    let roots = parse("int main() { return twice(1, 2); }");
+    let mut vm = Vm::new(Compiler::compile(&roots));
+    register(&mut |name, arity, function| vm.register_native(name, arity, function));
+    assert!(matches!(vm.run().unwrap_err().kind(), RuntimeErrorKind::ArgumentCountMismatch { .. }));
+    let error = run_both("int main() { return unknown(1); }").unwrap_err();
+    assert_eq!(error.kind(), &RuntimeErrorKind::UndefinedFunction("unknown".to_string()));
+  }
+}
@@ -8,7 +8,11 @@ use std::path::Path;
 use core::tree_viewer::TreeViewer;
 use core::lexical::Lexer;
 use core::parser::Parser;
-use core::llvm_ir::compile;
+use core::llvm_ir::{compile, jit_run, CompileOptions};
+use core::interpreter::Interpreter;
+use std::env;
+use std::fs;
+use std::process::{self, Command};
 
 struct CodeGen<'ctx> {
     context: &'ctx Context,
@@ -99,31 +103,85 @@ fn easy_compiler() -> Result<(), Box<dyn Error>>
 }
 
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let program = String::from("
-int add(int x, int y) {
-    int z = x + y;
-    return z;
-}
+/// オブジェクトファイルを system の cc でリンクして実行ファイルにする
+fn link_executable(object: &Path, output: &Path) -> Result<(), Box<dyn Error>> {
+    let status = Command::new("cc")
+        .arg(object)
+        .arg("-o")
+        .arg(output)
+        .status()?;
+    if !status.success() {
+        return Err(format!("リンクに失敗しました : {}", status).into());
+    }
 
-int main()
-{
-    int a = add(1, 2);
-    return 1;
+    Ok(())
 }
-    ");
 
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args: Vec<String> = env::args().collect();
+
+    // --jit を指定した場合は JIT で実行し, インタプリタと結果と時間を比較する
+    let use_jit = args.iter().any(|arg| arg == "--jit");
+    args.retain(|arg| arg != "--jit");
+
+    // --print-ir を指定した場合は最適化の前後の LLVM IR を表示する
+    let print_ir = args.iter().any(|arg| arg == "--print-ir");
+    args.retain(|arg| arg != "--print-ir");
+
+    // -O0 から -O3 で最適化レベルを指定する. 指定がない場合は -O0
+    let mut level = 0;
+    for arg in args.iter().filter(|arg| arg.starts_with("-O")) {
+        level = arg["-O".len()..].parse()
+            .map_err(|_| format!("不正な最適化レベルです : {}", arg))?;
+    }
+    args.retain(|arg| !arg.starts_with("-O"));
+    let options = CompileOptions::new(level, print_ir)?;
+
+    // -D名前=値 でマクロを定義する
+    let definitions: Vec<String> = args.iter().filter_map(|arg| arg.strip_prefix("-D")).map(String::from).collect();
+    args.retain(|arg| !arg.starts_with("-D"));
+
+    // -Iディレクトリ で #include のファイルを探すディレクトリを追加する
+    let include_directories: Vec<String> = args.iter().filter_map(|arg| arg.strip_prefix("-I")).map(String::from).collect();
+    args.retain(|arg| !arg.starts_with("-I"));
+
+    if args.len() < 2 || (!use_jit && args.len() < 3) {
+        println!("Usage: {} [-O0|-O1|-O2|-O3] [--print-ir] [-D<name>[=<value>]]... [-I<directory>]... <input_path> <output_path>", args[0]);
+        println!("       {} [-O0|-O1|-O2|-O3] [--print-ir] [-D<name>[=<value>]]... [-I<directory>]... --jit <input_path>", args[0]);
+        println!("output_path が .o で終わる場合はオブジェクトファイル, それ以外は実行ファイルを出力します");
+        process::exit(0);
+    }
+
+    let input_path = &args[1];
+    let program = fs::read_to_string(input_path)?;
 
-    let mut lexer = Lexer::new(program);
-    lexer.tokenize();
+    let mut lexer = Lexer::with_file_name(program, input_path);
+    for directory in include_directories.iter() {
+        lexer.add_include_directory(directory);
+    }
+    for definition in definitions.iter() {
+        lexer.define(definition)?;
+    }
+    if let Err(errors) = lexer.tokenize() {
+        for error in &errors {
+            eprintln!("{}", error);
+        }
+        return Err(format!("字句解析で {} 個のエラーが見つかりました", errors.len()).into());
+    }
 
     lexer.show_tokens();
 
     println!("----------------------");
 
     let tokens = lexer.tokens().clone();
-    let mut parser = Parser::new(tokens);
-    parser.parse();
+    let spans = lexer.spans().clone();
+    let mut parser = Parser::with_spans(tokens, spans);
+    if let Err(errors) = parser.parse() {
+        for error in &errors {
+            eprintln!("{}", error);
+        }
+        return Err(format!("構文解析で {} 個のエラーが見つかりました", errors.len()).into());
+    }
 
     println!("----------------------");
     parser.show_tree();
@@ -137,7 +195,76 @@ int main()
     tree_viewer.output_dot("trees/output.dot");
 
     let roots = parser.roots();
-    compile(roots)?;
+    if use_jit {
+        println!("----------------------");
+        let start = std::time::Instant::now();
+        let jit_result = jit_run(roots, &options)?;
+        let jit_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let mut interpreter = Interpreter::new(roots);
+        let interpreter_result = interpreter.run()?;
+        let interpreter_time = start.elapsed();
+
+        println!("----------------------");
+        println!("jit         : result: {:?}, calculation time: {:?}", jit_result, jit_time);
+        println!("interpreter : result: {:?}, calculation time: {:?}", interpreter_result, interpreter_time);
+        return Ok(());
+    }
+
+    let output_path = Path::new(&args[2]);
+    if output_path.extension().is_some_and(|extension| extension == "o") {
+        compile(roots, output_path, &options)?;
+    } else {
+        // 一時ディレクトリのオブジェクトファイルを経由してリンクする. 利用者のファイルは消さない
+        let temporary_directory = env::temp_dir().join(format!("nagato-{}", process::id()));
+        fs::create_dir_all(&temporary_directory)?;
+        let object_path = temporary_directory.join("output.o");
+        let result = match compile(roots, &object_path, &options) {
+            Ok(()) => link_executable(&object_path, output_path),
+            Err(error) => Err(error.into()),
+        };
+        fs::remove_dir_all(&temporary_directory)?;
+        result?;
+    }
+    println!("{} を出力しました", output_path.display());
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile_and_link() {
+        let program = "
+            int square(int n) {
+                return n * n;
+            }
+
+            int main() {
+                return square(6) + 6;
+            }
+        ";
+        let mut lexer = Lexer::with_file_name(program.to_string(), "test.c");
+        lexer.tokenize().unwrap();
+        let mut parser = Parser::with_spans(lexer.tokens().clone(), lexer.spans().clone());
+        parser.parse().unwrap();
+
+        let directory = env::temp_dir().join(format!("nagato-test-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        for level in 0..=3 {
+            let object = directory.join(format!("test{}.o", level));
+            let executable = directory.join(format!("test{}", level));
+            compile(parser.roots(), &object, &CompileOptions::new(level, false).unwrap()).unwrap();
+            assert!(object.exists(), "-O{}", level);
+            link_executable(&object, &executable).unwrap();
+
+            // main の戻り値が終了コードになる
+            let status = Command::new(&executable).status().unwrap();
+            assert_eq!(status.code(), Some(42), "-O{}", level);
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -2,31 +2,65 @@ use core::tree_viewer::TreeViewer;
 use core::lexical::Lexer;
 use core::parser::Parser;
 use core::interpreter::Interpreter;
+use core::bytecode::Compiler;
+use core::vm::Vm;
 use std::env;
 use std::fs;
 use std::process;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+
+    // --vm を指定した場合はバイトコードにコンパイルして実行する
+    let use_vm = args.iter().any(|arg| arg == "--vm");
+    args.retain(|arg| arg != "--vm");
+
+    // -D名前=値 でマクロを定義する
+    let definitions: Vec<String> = args.iter().filter_map(|arg| arg.strip_prefix("-D")).map(String::from).collect();
+    args.retain(|arg| !arg.starts_with("-D"));
+
+    // -Iディレクトリ で #include のファイルを探すディレクトリを追加する
+    let include_directories: Vec<String> = args.iter().filter_map(|arg| arg.strip_prefix("-I")).map(String::from).collect();
+    args.retain(|arg| !arg.starts_with("-I"));
 
     if args.len() < 2 {
-        println!("Usage: {} <file_path>", args[0]);
+        println!("Usage: {} [--vm] [-D<name>[=<value>]]... [-I<directory>]... <file_path>", args[0]);
         process::exit(0);
     }
 
     let file_path = &args[1];
     let program = fs::read_to_string(file_path).expect("Failed to read file");
 
-    let mut lexer = Lexer::new(program);
-    lexer.tokenize();
+    let mut lexer = Lexer::with_file_name(program, file_path);
+    for directory in include_directories.iter() {
+        lexer.add_include_directory(directory);
+    }
+    for definition in definitions.iter() {
+        if let Err(error) = lexer.define(definition) {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    }
+    if let Err(errors) = lexer.tokenize() {
+        for error in errors {
+            eprintln!("{}", error);
+        }
+        process::exit(1);
+    }
 
     lexer.show_tokens();
 
     println!("----------------------");
 
     let tokens = lexer.tokens().clone();
-    let mut parser = Parser::new(tokens);
-    parser.parse();
+    let spans = lexer.spans().clone();
+    let mut parser = Parser::with_spans(tokens, spans);
+    if let Err(errors) = parser.parse() {
+        for error in errors {
+            eprintln!("{}", error);
+        }
+        process::exit(1);
+    }
 
     println!("----------------------");
     parser.show_tree();
@@ -40,13 +74,27 @@ fn main() {
     tree_viewer.output_dot("trees/output.dot");
 
     println!("----------------------");
-    let mut interpreter = Interpreter::new(parser.roots());
     // 時間計測スタート
     let start = std::time::Instant::now();
-    let val = interpreter.run();
+    // 組み込み関数の入力は標準入力から読む
+    let val = if use_vm {
+        let mut vm = Vm::with_input(Compiler::compile(parser.roots()), Box::new(std::io::stdin().lock()));
+        vm.run()
+    } else {
+        let mut interpreter = Interpreter::with_input(parser.roots(), Box::new(std::io::stdin().lock()));
+        let val = interpreter.run();
+        interpreter.show_variables();
+        val
+    };
     // 時間計測終了
     let end = std::time::Instant::now();
-    interpreter.show_variables();
+    let val = match val {
+        Ok(val) => val,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
     println!("----------------------");
 
     println!("calculation time: {:?}", end.duration_since(start));